@@ -0,0 +1,13 @@
+-- SESSIONS (one row per login; revoking a session invalidates its tokens)
+CREATE TABLE IF NOT EXISTS sessions (
+    session_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions (user_id);
+
+CREATE INDEX IF NOT EXISTS idx_users_forgot_password_token ON users (forgot_password_token);
+CREATE INDEX IF NOT EXISTS idx_users_email_verification_token ON users (email_verification_token);
@@ -0,0 +1,5 @@
+-- Refresh tokens are stored hashed; sessions started before this have none and simply expire
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS refresh_token_hash TEXT NULL UNIQUE;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS previous_refresh_token_hash TEXT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_sessions_previous_refresh_token_hash ON sessions (previous_refresh_token_hash);
@@ -0,0 +1,31 @@
+-- LOGIN ATTEMPTS (used for per-account and per-IP throttling)
+CREATE TABLE IF NOT EXISTS login_attempts (
+    attempt_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    username TEXT NOT NULL,
+    ip_address TEXT NOT NULL,
+    succeeded BOOLEAN NOT NULL,
+    attempted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_login_attempts_username ON login_attempts (lower(username), attempted_at);
+CREATE INDEX IF NOT EXISTS idx_login_attempts_ip ON login_attempts (ip_address, attempted_at);
+
+
+-- SECURITY EVENTS (lockouts etc., visible to admins)
+DO $$ BEGIN
+    CREATE TYPE security_event_type AS ENUM ('accountlockout', 'iplockout');
+EXCEPTION WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE IF NOT EXISTS security_events (
+    event_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    event_type security_event_type NOT NULL,
+    user_id UUID REFERENCES users(user_id) ON DELETE SET NULL,
+    username TEXT NULL,
+    ip_address TEXT NULL,
+    details TEXT NULL,
+    locked_until TIMESTAMP NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_security_events_created_at ON security_events (created_at DESC);
@@ -0,0 +1,14 @@
+-- USERNAME HISTORY (also drives the username change cooldown)
+CREATE TABLE IF NOT EXISTS username_history (
+    history_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    old_username TEXT NOT NULL,
+    new_username TEXT NOT NULL,
+    changed_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_username_history_user_id ON username_history (user_id, changed_at DESC);
+
+-- Case-insensitive lookups for uniqueness checks
+CREATE INDEX IF NOT EXISTS idx_users_lower_username ON users (lower(username));
+CREATE INDEX IF NOT EXISTS idx_users_lower_email ON users (lower(email));
@@ -0,0 +1,18 @@
+-- ADMIN IMPERSONATION
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS impersonator_id UUID NULL REFERENCES users(user_id) ON DELETE CASCADE;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS impersonation_reason TEXT NULL;
+
+-- Every request made with an impersonation token, including blocked ones
+CREATE TABLE IF NOT EXISTS impersonation_audit_log (
+    log_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    session_id UUID NOT NULL REFERENCES sessions(session_id) ON DELETE CASCADE,
+    impersonator_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    method TEXT NOT NULL,
+    path TEXT NOT NULL,
+    status_code INT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_impersonation_audit_log_session_id ON impersonation_audit_log (session_id);
+CREATE INDEX IF NOT EXISTS idx_impersonation_audit_log_created_at ON impersonation_audit_log (created_at DESC);
@@ -0,0 +1,6 @@
+-- DEVICE DETAILS FOR THE SESSIONS LIST
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS user_agent TEXT NULL;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS ip_address TEXT NULL;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS last_seen_at TIMESTAMP NULL;
+
+UPDATE sessions SET last_seen_at = created_at WHERE last_seen_at IS NULL;
@@ -0,0 +1,6 @@
+-- AVATARS (uploads live in storage; avatar_version picks the current one)
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar_version BIGINT NULL;
+
+-- Replace ui-avatars.com links with our own avatar route
+UPDATE users SET avatar_url = '/api/public/avatars/' || user_id::TEXT
+WHERE avatar_url LIKE 'https://ui-avatars.com/%';
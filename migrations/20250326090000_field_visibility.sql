@@ -0,0 +1,38 @@
+-- PER-FIELD PROFILE VISIBILITY (replaces users.privacy)
+DO $$ BEGIN
+    CREATE TYPE field_visibility AS ENUM ('public', 'members', 'matched_only', 'private');
+EXCEPTION WHEN duplicate_object THEN null;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE location_precision AS ENUM ('exact', 'city', 'country');
+EXCEPTION WHEN duplicate_object THEN null;
+END $$;
+
+-- Users without a row get the same defaults from the application
+CREATE TABLE IF NOT EXISTS profile_visibility (
+    user_id UUID PRIMARY KEY REFERENCES users(user_id) ON DELETE CASCADE,
+    bio field_visibility NOT NULL DEFAULT 'members',
+    interests field_visibility NOT NULL DEFAULT 'members',
+    experience field_visibility NOT NULL DEFAULT 'members',
+    languages field_visibility NOT NULL DEFAULT 'members',
+    location field_visibility NOT NULL DEFAULT 'matched_only',
+    location_precision location_precision NOT NULL DEFAULT 'city',
+    age field_visibility NOT NULL DEFAULT 'matched_only',
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- Keep what existing users could already see: private profiles showed nothing, the rest showed
+-- their text fields to logged-in users. Location and age were never shown to anyone.
+INSERT INTO profile_visibility (user_id, bio, interests, experience, languages, location, age)
+SELECT user_id,
+       CASE WHEN privacy THEN 'private' ELSE 'members' END::field_visibility,
+       CASE WHEN privacy THEN 'private' ELSE 'members' END::field_visibility,
+       CASE WHEN privacy THEN 'private' ELSE 'members' END::field_visibility,
+       CASE WHEN privacy THEN 'private' ELSE 'members' END::field_visibility,
+       'private',
+       'private'
+FROM users
+ON CONFLICT (user_id) DO NOTHING;
+
+ALTER TABLE users DROP COLUMN IF EXISTS privacy;
@@ -0,0 +1,18 @@
+-- PERSONAL DATA EXPORTS (archives are built in the background and kept in storage)
+DO $$ BEGIN
+    CREATE TYPE export_status AS ENUM ('pending', 'ready', 'failed');
+EXCEPTION WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE IF NOT EXISTS data_exports (
+    export_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    status export_status NOT NULL DEFAULT 'pending',
+    storage_key TEXT NULL,
+    size_bytes BIGINT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMP NULL,
+    expires_at TIMESTAMP NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_data_exports_user_id ON data_exports (user_id, created_at DESC);
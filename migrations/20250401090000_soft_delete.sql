@@ -0,0 +1,8 @@
+-- SOFT ACCOUNT DELETION (rows are anonymised in place after the grace period, never deleted,
+-- so other people's conversations and matches keep their history)
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deletion_requested_at TIMESTAMP NULL;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deletion_scheduled_for TIMESTAMP NULL;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS purged_at TIMESTAMP NULL;
+
+CREATE INDEX IF NOT EXISTS idx_users_deletion_scheduled_for ON users (deletion_scheduled_for)
+    WHERE deletion_scheduled_for IS NOT NULL AND purged_at IS NULL;
@@ -0,0 +1,116 @@
+-- STRUCTURED PROFILE FIELDS
+-- interests/experience become taxonomy slugs, available_days a weekday enum and languages BCP 47
+-- tags, all as arrays instead of free-form JSONB. Existing values are normalised on the way.
+DO $$ BEGIN
+    CREATE TYPE weekday AS ENUM ('monday', 'tuesday', 'wednesday', 'thursday', 'friday', 'saturday', 'sunday');
+EXCEPTION WHEN duplicate_object THEN null;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE profile_tag_kind AS ENUM ('interest', 'experience');
+EXCEPTION WHEN duplicate_object THEN null;
+END $$;
+
+-- Admin-managed choices for interests and experience. Retired tags stay so old profiles keep them.
+CREATE TABLE IF NOT EXISTS profile_tags (
+    tag_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    kind profile_tag_kind NOT NULL,
+    slug TEXT NOT NULL CHECK (slug ~ '^[a-z0-9]+(-[a-z0-9]+)*$'),
+    label TEXT NOT NULL CHECK (char_length(label) > 0),
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (kind, slug)
+);
+
+CREATE OR REPLACE FUNCTION pg_temp.slugify(value TEXT) RETURNS TEXT AS $$
+    SELECT trim(BOTH '-' FROM regexp_replace(lower(trim(value)), '[^a-z0-9]+', '-', 'g'))
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION pg_temp.json_strings(value JSONB) RETURNS SETOF TEXT AS $$
+    SELECT jsonb_array_elements_text(CASE WHEN jsonb_typeof(value) = 'array' THEN value ELSE '[]' END)
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION pg_temp.normalize_tags(value JSONB) RETURNS TEXT[] AS $$
+    SELECT NULLIF(ARRAY(
+        SELECT DISTINCT pg_temp.slugify(v) FROM pg_temp.json_strings(value) v
+        WHERE pg_temp.slugify(v) <> ''
+        ORDER BY 1
+    ), '{}')
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION pg_temp.normalize_days(value JSONB) RETURNS weekday[] AS $$
+    SELECT NULLIF(ARRAY(
+        SELECT DISTINCT d FROM (
+            SELECT CASE left(lower(trim(v)), 3)
+                WHEN 'mon' THEN 'monday'::weekday
+                WHEN 'tue' THEN 'tuesday'::weekday
+                WHEN 'wed' THEN 'wednesday'::weekday
+                WHEN 'thu' THEN 'thursday'::weekday
+                WHEN 'fri' THEN 'friday'::weekday
+                WHEN 'sat' THEN 'saturday'::weekday
+                WHEN 'sun' THEN 'sunday'::weekday
+            END AS d
+            FROM pg_temp.json_strings(value) v
+        ) days
+        WHERE d IS NOT NULL
+        ORDER BY d
+    ), '{}')
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Bare codes and code-region pairs are recased; common English names are mapped; anything else
+-- can't be interpreted safely and is dropped
+CREATE OR REPLACE FUNCTION pg_temp.normalize_language(value TEXT) RETURNS TEXT AS $$
+    SELECT CASE
+        WHEN v ~ '^[a-z]{2,3}$' THEN v
+        WHEN v ~ '^[a-z]{2,3}[-_][a-z]{2}$' THEN split_part(replace(v, '_', '-'), '-', 1) || '-' || upper(right(v, 2))
+        ELSE (SELECT code FROM (VALUES
+            ('english', 'en'), ('spanish', 'es'), ('french', 'fr'), ('german', 'de'), ('italian', 'it'),
+            ('portuguese', 'pt'), ('dutch', 'nl'), ('polish', 'pl'), ('russian', 'ru'), ('ukrainian', 'uk'),
+            ('arabic', 'ar'), ('hebrew', 'he'), ('turkish', 'tr'), ('greek', 'el'), ('swedish', 'sv'),
+            ('norwegian', 'no'), ('danish', 'da'), ('finnish', 'fi'), ('chinese', 'zh'), ('mandarin', 'zh'),
+            ('cantonese', 'yue'), ('japanese', 'ja'), ('korean', 'ko'), ('hindi', 'hi'), ('urdu', 'ur'),
+            ('bengali', 'bn'), ('punjabi', 'pa'), ('tamil', 'ta'), ('vietnamese', 'vi'), ('thai', 'th'),
+            ('indonesian', 'id'), ('malay', 'ms'), ('tagalog', 'tl'), ('filipino', 'fil'), ('swahili', 'sw'),
+            ('persian', 'fa'), ('farsi', 'fa'), ('romanian', 'ro'), ('hungarian', 'hu'), ('czech', 'cs')
+        ) AS names(name, code) WHERE name = v)
+    END
+    FROM (SELECT lower(trim(value)) AS v) input
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION pg_temp.normalize_languages(value JSONB) RETURNS TEXT[] AS $$
+    SELECT NULLIF(ARRAY(
+        SELECT DISTINCT l FROM (SELECT pg_temp.normalize_language(v) AS l FROM pg_temp.json_strings(value) v) langs
+        WHERE l IS NOT NULL
+        ORDER BY l
+    ), '{}')
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION pg_temp.valid_location(value JSONB) RETURNS BOOLEAN AS $$
+    SELECT CASE
+        WHEN jsonb_typeof(value->'latitude') IS DISTINCT FROM 'number'
+          OR jsonb_typeof(value->'longitude') IS DISTINCT FROM 'number' THEN FALSE
+        ELSE (value->>'latitude')::float8 BETWEEN -90 AND 90 AND (value->>'longitude')::float8 BETWEEN -180 AND 180
+    END
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Every value already in use becomes a tag, so nobody loses what they entered
+INSERT INTO profile_tags (kind, slug, label)
+SELECT DISTINCT ON (slug) 'interest'::profile_tag_kind, slug, initcap(regexp_replace(trim(v), '\s+', ' ', 'g'))
+FROM (SELECT v, pg_temp.slugify(v) AS slug FROM users, pg_temp.json_strings(interests) v) t
+WHERE slug <> ''
+ORDER BY slug, v
+ON CONFLICT (kind, slug) DO NOTHING;
+
+INSERT INTO profile_tags (kind, slug, label)
+SELECT DISTINCT ON (slug) 'experience'::profile_tag_kind, slug, initcap(regexp_replace(trim(v), '\s+', ' ', 'g'))
+FROM (SELECT v, pg_temp.slugify(v) AS slug FROM users, pg_temp.json_strings(experience) v) t
+WHERE slug <> ''
+ORDER BY slug, v
+ON CONFLICT (kind, slug) DO NOTHING;
+
+ALTER TABLE users ALTER COLUMN interests TYPE TEXT[] USING pg_temp.normalize_tags(interests);
+ALTER TABLE users ALTER COLUMN experience TYPE TEXT[] USING pg_temp.normalize_tags(experience);
+ALTER TABLE users ALTER COLUMN available_days TYPE weekday[] USING pg_temp.normalize_days(available_days);
+ALTER TABLE users ALTER COLUMN languages TYPE TEXT[] USING pg_temp.normalize_languages(languages);
+
+UPDATE users SET location = NULL WHERE location IS NOT NULL AND NOT pg_temp.valid_location(location);
@@ -0,0 +1,9 @@
+-- USER DIRECTORY SEARCH
+-- Trigram index for fuzzy username matching, plus a prefix index on the lowercased name
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS idx_users_username_trgm ON users USING gin (username gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_users_username_prefix ON users (lower(username) text_pattern_ops);
+
+-- Interest filters are array containment
+CREATE INDEX IF NOT EXISTS idx_users_interests ON users USING gin (interests);
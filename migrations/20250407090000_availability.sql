@@ -0,0 +1,26 @@
+-- TIMEZONE-AWARE AVAILABILITY (replaces users.available_days)
+-- Users keep an IANA timezone and weekly windows in local time, e.g. Tuesday 18:00-21:00.
+-- Times are minutes after local midnight; an end of 1440 means "until midnight".
+ALTER TABLE users ADD COLUMN IF NOT EXISTS timezone TEXT NOT NULL DEFAULT 'UTC';
+
+CREATE TABLE IF NOT EXISTS user_availability (
+    slot_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    weekday weekday NOT NULL,
+    start_minute SMALLINT NOT NULL CHECK (start_minute BETWEEN 0 AND 1439),
+    end_minute SMALLINT NOT NULL CHECK (end_minute BETWEEN 1 AND 1440),
+    CHECK (start_minute < end_minute)
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_availability_user ON user_availability (user_id);
+
+-- Nobody gave hours before, so each listed day becomes a whole-day window until they refine it
+DO $$ BEGIN
+    IF EXISTS (SELECT 1 FROM information_schema.columns
+               WHERE table_name = 'users' AND column_name = 'available_days') THEN
+        INSERT INTO user_availability (user_id, weekday, start_minute, end_minute)
+        SELECT user_id, day, 0, 1440 FROM users, unnest(available_days) AS day;
+
+        ALTER TABLE users DROP COLUMN available_days;
+    END IF;
+END $$;
@@ -0,0 +1,7 @@
+-- SPONSOR APPLICATION REVIEW
+-- Admins can ask for more information instead of deciding straight away
+ALTER TYPE application_status ADD VALUE IF NOT EXISTS 'needs_info';
+
+ALTER TABLE sponsor_applications ADD COLUMN IF NOT EXISTS reviewed_at TIMESTAMP NULL;
+
+CREATE INDEX IF NOT EXISTS idx_sponsor_applications_status ON sponsor_applications (status, created_at);
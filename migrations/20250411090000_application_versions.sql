@@ -0,0 +1,21 @@
+-- SPONSOR APPLICATION HISTORY
+-- Every submission and edit is kept as an immutable version; the application row holds the latest
+CREATE TABLE IF NOT EXISTS sponsor_application_versions (
+    version_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    application_id UUID NOT NULL REFERENCES sponsor_applications(application_id) ON DELETE CASCADE,
+    version INT NOT NULL CHECK (version > 0),
+    application_info TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (application_id, version)
+);
+
+INSERT INTO sponsor_application_versions (application_id, version, application_info, created_at)
+SELECT application_id, 1, application_info, COALESCE(created_at, NOW())
+FROM sponsor_applications
+ON CONFLICT (application_id, version) DO NOTHING;
+
+-- Withdrawn applications stay for admins instead of being deleted
+ALTER TABLE sponsor_applications ADD COLUMN IF NOT EXISTS withdrawn_at TIMESTAMP NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_sponsor_applications_active_user
+    ON sponsor_applications (user_id) WHERE withdrawn_at IS NULL;
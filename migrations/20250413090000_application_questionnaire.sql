@@ -0,0 +1,31 @@
+-- SPONSOR APPLICATION QUESTIONNAIRE
+-- Admins publish numbered versions of the questions; a published version never changes, so
+-- answers always read against the questions they were given for. At most one version is active.
+CREATE TABLE IF NOT EXISTS sponsor_questionnaires (
+    version INT PRIMARY KEY CHECK (version > 0),
+    questions JSONB NOT NULL CHECK (jsonb_typeof(questions) = 'array'),
+    created_by UUID NULL REFERENCES users(user_id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    active BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_sponsor_questionnaires_active
+    ON sponsor_questionnaires (active) WHERE active;
+
+-- Answers keyed by question key; NULL for applications made before there was a questionnaire
+ALTER TABLE sponsor_applications ADD COLUMN IF NOT EXISTS questionnaire_version INT NULL
+    REFERENCES sponsor_questionnaires(version);
+ALTER TABLE sponsor_applications ADD COLUMN IF NOT EXISTS answers JSONB NULL;
+
+ALTER TABLE sponsor_application_versions ADD COLUMN IF NOT EXISTS questionnaire_version INT NULL
+    REFERENCES sponsor_questionnaires(version);
+ALTER TABLE sponsor_application_versions ADD COLUMN IF NOT EXISTS answers JSONB NULL;
+
+-- Reviewers filter the queue by answers
+CREATE INDEX IF NOT EXISTS idx_sponsor_applications_answers
+    ON sponsor_applications USING GIN (answers jsonb_path_ops);
+
+-- Applications that answer at least one question don't also need a long free-text description
+ALTER TABLE sponsor_applications DROP CONSTRAINT IF EXISTS sponsor_applications_application_info_check;
+ALTER TABLE sponsor_applications ADD CONSTRAINT sponsor_applications_application_info_check
+    CHECK (char_length(application_info) > 20 OR (answers IS NOT NULL AND answers <> '{}'::jsonb));
@@ -0,0 +1,50 @@
+-- SPONSOR APPLICATION ATTACHMENTS AND REFERENCES
+DO $$ BEGIN
+    CREATE TYPE attachment_kind AS ENUM ('certificate', 'background_check', 'other');
+EXCEPTION WHEN duplicate_object THEN null;
+END $$;
+
+-- Infected uploads are refused, so stored files are either clean or were never scanned
+DO $$ BEGIN
+    CREATE TYPE scan_status AS ENUM ('clean', 'unscanned');
+EXCEPTION WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE IF NOT EXISTS sponsor_application_attachments (
+    attachment_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    application_id UUID NOT NULL REFERENCES sponsor_applications(application_id) ON DELETE CASCADE,
+    kind attachment_kind NOT NULL DEFAULT 'other',
+    file_name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    sha256 TEXT NOT NULL,
+    storage_key TEXT NOT NULL,
+    scan_status scan_status NOT NULL,
+    -- Which scanner passed it, e.g. `clamav`
+    scanned_by TEXT NULL,
+    uploaded_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_sponsor_application_attachments_application
+    ON sponsor_application_attachments (application_id);
+
+-- People who vouch for an applicant. They answer through an emailed single-use link; only a hash
+-- of its token is kept.
+CREATE TABLE IF NOT EXISTS sponsor_application_references (
+    reference_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    application_id UUID NOT NULL REFERENCES sponsor_applications(application_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    relationship TEXT NOT NULL,
+    token_hash TEXT NULL UNIQUE,
+    token_expires_at TIMESTAMP NULL,
+    sent_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    responded_at TIMESTAMP NULL,
+    recommends BOOLEAN NULL,
+    known_for_years REAL NULL,
+    comments TEXT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_sponsor_application_references_email
+    ON sponsor_application_references (application_id, lower(email));
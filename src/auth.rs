@@ -1,8 +1,21 @@
-use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey, errors::Error};
+use jsonwebtoken::{encode, decode, decode_header, Header, Validation, errors::{Error, ErrorKind}};
 use serde::{Serialize, Deserialize};
 use chrono::{Utc, Duration};
-use std::env;
-use dotenvy::dotenv;
+use crate::config::env_or;
+use crate::handlers::jwt_keys::KEY_SET;
+use actix_web::{HttpMessage, HttpRequest};
+use uuid::Uuid;
+/// What a token may be used for
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenType {
+    /// Normal API access
+    #[default]
+    Access,
+    /// Password checked, second factor still outstanding; only accepted by the MFA endpoints
+    MfaPending,
+}
+
 /// Structure representing JWT claims
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -10,33 +23,127 @@ pub struct Claims {
     pub username: String,  
     pub role:String,
     pub exp: usize,        
+    #[serde(default)]
+    pub iat: usize,
+    /// Session the token belongs to
+    #[serde(default)]
+    pub sid: Option<String>,
+    #[serde(default)]
+    pub token_type: TokenType,
+    /// Admin acting as this user; set only on impersonation tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonator: Option<String>,
+    /// Impersonation tokens are read-only unless the admin asked otherwise
+    #[serde(default)]
+    pub read_only: bool,
 }
 
-/// Generates a JWT token for a given user
-pub fn generate_jwt(user_id: &str, username: &str,role:&str) -> Result<String, Error> {
-    dotenv().ok();
-    let secret_key = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+/// Lifetime of an access token; clients use their refresh token to get a new one
+pub fn access_token_ttl() -> Duration {
+    Duration::minutes(env_or("ACCESS_TOKEN_TTL_MINUTES", 15))
+}
 
-    let expiration = Utc::now() + Duration::hours(8); 
+/// Generates a short-lived access token for a given user and session
+pub fn generate_jwt(user_id: &str, username: &str,role:&str, session_id: &str) -> Result<String, Error> {
+    let now = Utc::now();
+    let expiration = now + access_token_ttl();
     let claims = Claims {
         id: user_id.to_string(),
         username: username.to_string(),
         role:role.to_string(),
         exp: expiration.timestamp() as usize,
+        iat: now.timestamp() as usize,
+        sid: Some(session_id.to_string()),
+        token_type: TokenType::Access,
+        impersonator: None,
+        read_only: false,
+    };
+
+    sign(&claims)
+}
+
+/// Lifetime of the challenge token handed out between the password and second-factor steps
+pub fn mfa_challenge_ttl() -> Duration {
+    Duration::minutes(env_or("MFA_CHALLENGE_TTL_MINUTES", 5))
+}
+
+/// Generates an "mfa_pending" token proving the password step succeeded
+pub fn generate_mfa_challenge(user_id: &str, username: &str, role: &str) -> Result<String, Error> {
+    let now = Utc::now();
+    let expiration = now + mfa_challenge_ttl();
+    let claims = Claims {
+        id: user_id.to_string(),
+        username: username.to_string(),
+        role: role.to_string(),
+        exp: expiration.timestamp() as usize,
+        iat: now.timestamp() as usize,
+        sid: None,
+        token_type: TokenType::MfaPending,
+        impersonator: None,
+        read_only: false,
     };
 
-    encode(&Header::default(), &claims, &EncodingKey::from_secret(secret_key.as_ref()))
+    sign(&claims)
+}
+
+/// Generates an access token that lets `impersonator_id` act as the given user in an
+/// impersonation session
+pub fn generate_impersonation_jwt(
+    user_id: &str,
+    username: &str,
+    role: &str,
+    session_id: &str,
+    impersonator_id: &str,
+    read_only: bool,
+    ttl: Duration,
+) -> Result<String, Error> {
+    let now = Utc::now();
+    let claims = Claims {
+        id: user_id.to_string(),
+        username: username.to_string(),
+        role: role.to_string(),
+        exp: (now + ttl).timestamp() as usize,
+        iat: now.timestamp() as usize,
+        sid: Some(session_id.to_string()),
+        token_type: TokenType::Access,
+        impersonator: Some(impersonator_id.to_string()),
+        read_only,
+    };
+
+    sign(&claims)
+}
+
+/// Signs claims with the current signing key, naming it in the `kid` header
+fn sign(claims: &Claims) -> Result<String, Error> {
+    let signing = &KEY_SET.signing;
+    let mut header = Header::new(signing.alg);
+    header.kid = Some(signing.kid.clone());
+    encode(&header, claims, &signing.key)
 }
 
 /// Validates a JWT token and extracts the user information
 pub fn validate_jwt(token: &str) -> Result<Claims, Error> {
-    dotenv().ok();
-    let secret_key = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret_key.as_ref()),
-        &Validation::new(jsonwebtoken::Algorithm::HS256),
-    )?;
+    let header = decode_header(token)?;
+    let key = KEY_SET
+        .verification_key(header.kid.as_deref())
+        .ok_or_else(|| Error::from(ErrorKind::InvalidToken))?;
+
+    let token_data = decode::<Claims>(token, &key.key, &Validation::new(key.alg))?;
     Ok(token_data.claims)
 }
+
+/// The logged-in user's id, from the claims `AuthMiddleware` stored on the request
+pub fn logged_in_user_id(req: &HttpRequest) -> Option<Uuid> {
+    req.extensions()
+        .get::<Claims>()
+        .and_then(|claims| Uuid::parse_str(&claims.id).ok())
+}
+
+/// The logged-in user and the session behind the request's access token
+pub fn current_session(req: &HttpRequest) -> Option<(Uuid, Option<Uuid>)> {
+    let extensions = req.extensions();
+    let claims = extensions.get::<Claims>()?;
+    let user_id = Uuid::parse_str(&claims.id).ok()?;
+    let session_id = claims.sid.as_deref().and_then(|sid| Uuid::parse_str(sid).ok());
+    Some((user_id, session_id))
+}
@@ -0,0 +1,30 @@
+use std::env;
+use std::str::FromStr;
+
+/// Reads an environment variable and parses it, falling back to `default` when unset or invalid
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+/// Reads a boolean flag such as `true`, `1` or `yes`
+pub fn env_flag(key: &str, default: bool) -> bool {
+    match env::var(key) {
+        Ok(v) => matches!(v.trim().to_lowercase().as_str(), "true" | "1" | "yes" | "on"),
+        Err(_) => default,
+    }
+}
+
+/// Reads a comma-separated list, ignoring empty entries
+pub fn env_list(key: &str) -> Vec<String> {
+    env::var(key)
+        .map(|v| {
+            v.split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
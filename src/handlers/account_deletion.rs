@@ -0,0 +1,223 @@
+use crate::config::env_or;
+use crate::handlers::avatars::{AVATAR_SIZES, avatar_key, avatar_url};
+use crate::handlers::data_export::purge_expired_exports;
+use crate::handlers::notifications::notify_user;
+use crate::handlers::sessions::revoke_all_sessions;
+use crate::handlers::storage::StorageBackend;
+use chrono::NaiveDateTime;
+use serde_json::json;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Days a deleted account can still be restored, from `ACCOUNT_DELETION_GRACE_DAYS`
+pub fn deletion_grace_days() -> i32 {
+    env_or("ACCOUNT_DELETION_GRACE_DAYS", 30)
+}
+
+/// Mark an account for deletion and sign it out everywhere. Nothing is removed until the grace
+/// period is over. Returns when the account will be purged.
+pub async fn schedule_account_deletion(pool: &PgPool, user_id: Uuid) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+    let scheduled_for: Option<NaiveDateTime> = sqlx::query_scalar(
+        "UPDATE users
+         SET deletion_requested_at = NOW(), deletion_scheduled_for = NOW() + make_interval(days => $1)
+         WHERE user_id = $2 AND purged_at IS NULL
+         RETURNING deletion_scheduled_for",
+    )
+    .bind(deletion_grace_days())
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    if scheduled_for.is_some() {
+        revoke_all_sessions(pool, user_id).await?;
+    }
+    Ok(scheduled_for)
+}
+
+/// Cancel a scheduled deletion; `false` if there was none (or it already happened)
+pub async fn cancel_account_deletion(pool: &PgPool, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET deletion_requested_at = NULL, deletion_scheduled_for = NULL
+         WHERE user_id = $1 AND deletion_scheduled_for IS NOT NULL AND purged_at IS NULL",
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[derive(sqlx::FromRow)]
+struct PurgedAccount {
+    username: String,
+    avatar_version: Option<i64>,
+}
+
+/// What a purge leaves behind that lives outside the database, or needs telling about it
+struct PurgeOutcome {
+    old_username: String,
+    avatar_version: Option<i64>,
+    /// Stored files to delete once the transaction has committed
+    storage_keys: Vec<String>,
+    ended_matches: Vec<Uuid>,
+}
+
+/// Anonymise one account in place. The row and everything the user wrote stay, so other people's
+/// conversations, posts and match history keep making sense; they just show a deleted user.
+async fn purge_account(pool: &PgPool, user_id: Uuid) -> Result<Option<PurgeOutcome>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let account = sqlx::query_as::<_, PurgedAccount>(
+        "SELECT username, avatar_version FROM users
+         WHERE user_id = $1 AND deletion_scheduled_for <= NOW() AND purged_at IS NULL
+         FOR UPDATE",
+    )
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    // Restored (or purged by another worker) since it was picked
+    let Some(account) = account else {
+        return Ok(None);
+    };
+
+    let simple_id = user_id.simple().to_string();
+    sqlx::query(
+        "UPDATE users SET
+             username = $1, email = $2, password_hash = '!', avatar_url = $3, avatar_version = NULL,
+             user_profile = 'This account has been deleted.', bio = NULL, dob = DATE '1900-01-01',
+             location = NULL, interests = NULL, experience = NULL, languages = NULL, timezone = 'UTC',
+             email_verified = FALSE, email_verification_token = NULL,
+             forgot_password_token = NULL, forgot_password_expires_at = NULL,
+             banned_until = NULL, purged_at = NOW()
+         WHERE user_id = $4",
+    )
+    .bind(format!("deleted-user-{}", &simple_id[..12]))
+    .bind(format!("deleted-{}@deleted.invalid", simple_id))
+    .bind(avatar_url(user_id, None))
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    // Matches end with the account; their history stays
+    let ended_matches: Vec<Uuid> = sqlx::query_scalar::<_, Option<Uuid>>(
+        "UPDATE matching_requests SET status = 'declined'
+         WHERE status IN ('pending', 'accepted') AND (member_id = $1 OR sponsor_id = $1)
+         RETURNING CASE WHEN member_id = $1 THEN sponsor_id ELSE member_id END",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let mut storage_keys: Vec<Option<String>> =
+        sqlx::query_scalar("DELETE FROM data_exports WHERE user_id = $1 RETURNING storage_key")
+            .bind(user_id)
+            .fetch_all(&mut *tx)
+            .await?;
+    storage_keys.extend(
+        sqlx::query_scalar::<_, String>(
+            "DELETE FROM sponsor_application_attachments
+             WHERE application_id IN (SELECT application_id FROM sponsor_applications WHERE user_id = $1)
+             RETURNING storage_key",
+        )
+        .bind(user_id)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(Some),
+    );
+
+    // Private data with no value to anyone else
+    for query in [
+        "DELETE FROM profile_visibility WHERE user_id = $1",
+        "DELETE FROM user_availability WHERE user_id = $1",
+        "DELETE FROM username_history WHERE user_id = $1",
+        "DELETE FROM user_mfa WHERE user_id = $1",
+        "DELETE FROM mfa_recovery_codes WHERE user_id = $1",
+        "DELETE FROM user_blocks WHERE blocker_id = $1 OR blocked_id = $1",
+        "DELETE FROM sponsor_applications WHERE user_id = $1",
+        "UPDATE sessions SET user_agent = NULL, ip_address = NULL, revoked_at = COALESCE(revoked_at, NOW())
+         WHERE user_id = $1",
+    ] {
+        sqlx::query(query).bind(user_id).execute(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(Some(PurgeOutcome {
+        old_username: account.username,
+        avatar_version: account.avatar_version,
+        storage_keys: storage_keys.into_iter().flatten().collect(),
+        ended_matches,
+    }))
+}
+
+/// Tell someone their match's account is gone, by announcement and live if they're connected
+async fn notify_match_ended(pool: &PgPool, user_id: Uuid, old_username: &str) {
+    let message = format!("{} has deleted their account, so your match with them has ended.", old_username);
+    let payload = json!({ "type": "match_ended", "reason": "account_deleted" });
+    notify_user(pool, user_id, &message, payload).await;
+}
+
+/// Purge every account whose grace period is over
+pub async fn purge_due_accounts(pool: &PgPool, storage: &dyn StorageBackend) -> Result<usize, sqlx::Error> {
+    let due: Vec<Uuid> = sqlx::query_scalar(
+        "SELECT user_id FROM users
+         WHERE deletion_scheduled_for <= NOW() AND purged_at IS NULL
+         ORDER BY deletion_scheduled_for",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut purged = 0;
+    for user_id in due {
+        let outcome = match purge_account(pool, user_id).await {
+            Ok(Some(outcome)) => outcome,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Failed to purge account {}: {:?}", user_id, e);
+                continue;
+            }
+        };
+        purged += 1;
+
+        let mut keys = outcome.storage_keys;
+        if let Some(version) = outcome.avatar_version {
+            keys.extend(AVATAR_SIZES.iter().map(|size| avatar_key(user_id, version, *size)));
+        }
+        for key in keys {
+            if let Err(e) = storage.delete(&key).await {
+                eprintln!("Failed to delete {}: {}", key, e);
+            }
+        }
+
+        for match_id in outcome.ended_matches {
+            notify_match_ended(pool, match_id, &outcome.old_username).await;
+        }
+    }
+    Ok(purged)
+}
+
+/// Run `purge_due_accounts` and `purge_expired_exports` every `ACCOUNT_PURGE_INTERVAL_MINUTES`
+pub fn spawn_purge_job(pool: PgPool, storage: Arc<dyn StorageBackend>) {
+    let every = Duration::from_secs(60 * env_or("ACCOUNT_PURGE_INTERVAL_MINUTES", 60u64).max(1));
+    actix_web::rt::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            match purge_due_accounts(&pool, storage.as_ref()).await {
+                Ok(0) => {}
+                Ok(count) => println!("Purged {} deleted account(s)", count),
+                Err(e) => eprintln!("Account purge failed: {:?}", e),
+            }
+            match purge_expired_exports(&pool, storage.as_ref()).await {
+                Ok(0) => {}
+                Ok(count) => println!("Deleted {} expired data export(s)", count),
+                Err(e) => eprintln!("Export cleanup failed: {:?}", e),
+            }
+        }
+    });
+}
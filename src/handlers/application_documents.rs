@@ -0,0 +1,154 @@
+use crate::config::env_or;
+use crate::handlers::mailer::{Mailer, OutgoingEmail, app_base_url};
+use crate::handlers::storage::StorageBackend;
+use crate::models::all_models::SponsorApplicationAttachment;
+use actix_web::HttpResponse;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+pub const ATTACHMENT_COLUMNS: &str = "attachment_id, application_id, kind, file_name, content_type, size_bytes, \
+                                      sha256, storage_key, scan_status, scanned_by, uploaded_at";
+pub const REFERENCE_COLUMNS: &str = "reference_id, application_id, name, email, relationship, sent_at, \
+                                     token_expires_at, responded_at, recommends, known_for_years, comments, created_at";
+
+/// Most files one application can have
+pub const MAX_ATTACHMENTS: i64 = 10;
+/// Most references one application can name
+pub const MAX_REFERENCES: i64 = 5;
+
+/// Scans and certificates; checked against the file's magic bytes, not what the client claims
+const ALLOWED_TYPES: &[&str] = &["application/pdf", "image/jpeg", "image/png"];
+
+/// Largest accepted attachment, from `SPONSOR_ATTACHMENT_MAX_BYTES`
+pub fn attachment_max_bytes() -> usize {
+    env_or("SPONSOR_ATTACHMENT_MAX_BYTES", 10 * 1024 * 1024)
+}
+
+/// How long a reference's link stays valid, from `SPONSOR_REFERENCE_TTL_DAYS`
+pub fn reference_ttl_days() -> i32 {
+    env_or("SPONSOR_REFERENCE_TTL_DAYS", 14)
+}
+
+pub fn attachment_key(application_id: Uuid, attachment_id: Uuid) -> String {
+    format!("sponsor-applications/{}/{}", application_id, attachment_id)
+}
+
+/// The upload's real type, if it's one we accept
+pub fn detect_attachment_type(bytes: &[u8]) -> Option<&'static str> {
+    infer::get(bytes)
+        .map(|kind| kind.mime_type())
+        .filter(|mime| ALLOWED_TYPES.contains(mime))
+}
+
+/// A file name that's safe to store and to send back in a header: no path, quotes or control
+/// characters, ASCII only and not too long
+pub fn clean_file_name(name: Option<&str>) -> String {
+    let base = name.unwrap_or_default().rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .take(200)
+        .collect();
+    match cleaned.trim() {
+        "" | "." | ".." => "attachment".to_string(),
+        name => name.to_string(),
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Reference links are stored only as SHA-256 hashes
+pub fn hash_reference_token(token: &str) -> String {
+    sha256_hex(token.as_bytes())
+}
+
+/// Ask a reference to vouch for an applicant through the form at `token`'s link
+pub async fn send_reference_request(
+    mailer: &dyn Mailer,
+    name: &str,
+    email: &str,
+    relationship: &str,
+    applicant: &str,
+    token: &str,
+) -> Result<(), String> {
+    let link = format!("{}/sponsor-reference?token={}", app_base_url(), token);
+    mailer
+        .send(OutgoingEmail {
+            to: email.to_string(),
+            subject: format!("{} has named you as a reference", applicant),
+            body: format!(
+                "Hi {},\n\n{} has applied to become a sponsor and named you as a reference ({}). Please tell us about them using the form below. The link is valid for {} days and can only be used once:\n\n{}\n\nIf you don't know {}, you can ignore this email.",
+                name,
+                applicant,
+                relationship,
+                reference_ttl_days(),
+                link,
+                applicant
+            ),
+        })
+        .await
+}
+
+/// Send a stored attachment back as a download
+pub async fn attachment_download(storage: &dyn StorageBackend, attachment: &SponsorApplicationAttachment) -> HttpResponse {
+    match storage.get(&attachment.storage_key).await {
+        Ok(Some(object)) => HttpResponse::Ok()
+            .content_type(attachment.content_type.as_str())
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", attachment.file_name),
+            ))
+            .insert_header(("X-Content-Type-Options", "nosniff"))
+            .insert_header(("Cache-Control", "no-store"))
+            .body(object.bytes),
+        Ok(None) => HttpResponse::NotFound().body("Attachment not found"),
+        Err(e) => {
+            eprintln!("Failed to load attachment: {}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch attachment")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_stripped_from_file_names() {
+        assert_eq!(clean_file_name(Some("../../etc/passwd")), "passwd");
+        assert_eq!(clean_file_name(Some("..\\..\\boot.ini")), "boot.ini");
+        assert_eq!(clean_file_name(Some("C:\\Users\\me\\certificate.pdf")), "certificate.pdf");
+        for name in ["..", "uploads/..", "/", "dir/", "  "] {
+            assert_eq!(clean_file_name(Some(name)), "attachment", "{:?}", name);
+        }
+        assert_eq!(clean_file_name(None), "attachment");
+    }
+
+    #[test]
+    fn quotes_and_control_characters_are_removed() {
+        assert_eq!(clean_file_name(Some("cert\"; filename=\"evil.exe.pdf")), "cert; filename=evil.exe.pdf");
+        assert_eq!(clean_file_name(Some("cert\r\nX-Injected: 1.pdf")), "certX-Injected: 1.pdf");
+        assert_eq!(clean_file_name(Some("\u{0}\u{7f}scan.png")), "scan.png");
+    }
+
+    #[test]
+    fn file_names_become_short_ascii() {
+        assert_eq!(clean_file_name(Some("Bescheinigung-Müller.pdf")), "Bescheinigung-M_ller.pdf");
+        assert_eq!(clean_file_name(Some("証明書.pdf")), "___.pdf");
+        assert_eq!(clean_file_name(Some(&"a".repeat(300))).len(), 200);
+    }
+
+    #[test]
+    fn only_pdf_jpeg_and_png_are_accepted() {
+        assert_eq!(detect_attachment_type(b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n"), Some("application/pdf"));
+        assert_eq!(detect_attachment_type(b"\xff\xd8\xff\xe0\x00\x10JFIF\x00"), Some("image/jpeg"));
+        assert_eq!(detect_attachment_type(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"), Some("image/png"));
+        assert_eq!(detect_attachment_type(b"GIF89a\x01\x00\x01\x00"), None);
+        assert_eq!(detect_attachment_type(b"MZ\x90\x00\x03\x00\x00\x00"), None);
+        assert_eq!(detect_attachment_type(b"certificate.pdf"), None);
+        assert_eq!(detect_attachment_type(b""), None);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::auth::access_token_ttl;
+use crate::config::{env_flag, env_or};
+use crate::handlers::sessions::{generate_token, refresh_token_ttl_days};
+use actix_web::cookie::{Cookie, SameSite, time};
+use actix_web::http::Method;
+use actix_web::{HttpRequest, HttpResponseBuilder};
+use lazy_static::lazy_static;
+use std::env;
+
+pub const ACCESS_COOKIE: &str = "bth_access";
+pub const REFRESH_COOKIE: &str = "bth_refresh";
+pub const CSRF_COOKIE: &str = "bth_csrf";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Settings for the HTTP-only cookie transport, read from `AUTH_COOKIE_*`
+pub struct CookieConfig {
+    pub enabled: bool,
+    pub secure: bool,
+    pub same_site: SameSite,
+    pub domain: Option<String>,
+}
+
+impl CookieConfig {
+    fn from_env() -> Self {
+        let same_site = match env_or("AUTH_COOKIE_SAMESITE", "lax".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "strict" => SameSite::Strict,
+            "none" => SameSite::None,
+            _ => SameSite::Lax,
+        };
+
+        CookieConfig {
+            enabled: env_flag("AUTH_COOKIE_MODE", false),
+            secure: env_flag("AUTH_COOKIE_SECURE", true),
+            same_site,
+            domain: env::var("AUTH_COOKIE_DOMAIN").ok().filter(|d| !d.is_empty()),
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref COOKIE_CONFIG: CookieConfig = CookieConfig::from_env();
+}
+
+fn build_cookie(name: &'static str, value: String, path: &'static str, http_only: bool, max_age: time::Duration) -> Cookie<'static> {
+    let mut builder = Cookie::build(name, value)
+        .path(path)
+        .http_only(http_only)
+        .secure(COOKIE_CONFIG.secure)
+        .same_site(COOKIE_CONFIG.same_site)
+        .max_age(max_age);
+    if let Some(domain) = &COOKIE_CONFIG.domain {
+        builder = builder.domain(domain.clone());
+    }
+    builder.finish()
+}
+
+/// Set the access, refresh and CSRF cookies for a freshly issued session
+pub fn set_session_cookies(builder: &mut HttpResponseBuilder, access_token: &str, refresh_token: &str) {
+    let access_age = time::Duration::seconds(access_token_ttl().num_seconds());
+    let refresh_age = time::Duration::days(refresh_token_ttl_days() as i64);
+
+    builder
+        .cookie(build_cookie(ACCESS_COOKIE, access_token.to_string(), "/api", true, access_age))
+        .cookie(build_cookie(REFRESH_COOKIE, refresh_token.to_string(), "/api/public/users", true, refresh_age))
+        // Readable by the frontend so it can echo it back in the CSRF header
+        .cookie(build_cookie(CSRF_COOKIE, generate_token(), "/", false, refresh_age));
+}
+
+/// Expire all auth cookies
+pub fn clear_session_cookies(builder: &mut HttpResponseBuilder) {
+    builder
+        .cookie(build_cookie(ACCESS_COOKIE, String::new(), "/api", true, time::Duration::ZERO))
+        .cookie(build_cookie(REFRESH_COOKIE, String::new(), "/api/public/users", true, time::Duration::ZERO))
+        .cookie(build_cookie(CSRF_COOKIE, String::new(), "/", false, time::Duration::ZERO));
+}
+
+/// Double-submit check: state-changing requests authenticated by cookie must echo the CSRF
+/// cookie in the `X-CSRF-Token` header
+pub fn csrf_check_passes(req: &HttpRequest) -> bool {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return true;
+    }
+
+    let cookie = match req.cookie(CSRF_COOKIE) {
+        Some(c) => c.value().to_string(),
+        None => return false,
+    };
+    let header = match req.headers().get(CSRF_HEADER).and_then(|h| h.to_str().ok()) {
+        Some(h) => h,
+        None => return false,
+    };
+
+    !cookie.is_empty()
+        && cookie.len() == header.len()
+        && cookie
+            .bytes()
+            .zip(header.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
@@ -0,0 +1,410 @@
+use crate::handlers::validation::FieldError;
+use crate::models::all_models::{AvailabilitySlot, Weekday};
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Most windows one user can have in a week
+pub const MAX_SLOTS: usize = 50;
+
+const MINUTES_PER_DAY: i16 = 24 * 60;
+
+/// A window as clients send and receive it: `{"day": "tuesday", "start": "18:00", "end": "21:00"}`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SlotTimes {
+    pub day: String,
+    pub start: String,
+    pub end: String,
+}
+
+/// A concrete stretch of time, already converted out of the owner's timezone
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Window {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl Window {
+    pub fn hours(&self) -> f64 {
+        (self.end - self.start).num_seconds() as f64 / 3600.0
+    }
+}
+
+/// An IANA timezone name such as `Europe/London`
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+    Tz::from_str(name.trim()).ok()
+}
+
+/// A stored timezone name; one that no longer parses (say, after a tzdata removal) counts as UTC
+pub fn timezone_or_utc(name: &str) -> Tz {
+    parse_timezone(name).unwrap_or(Tz::UTC)
+}
+
+fn format_minute(minute: i16) -> String {
+    format!("{:02}:{:02}", minute / 60, minute % 60)
+}
+
+/// `HH:MM` as minutes after midnight; `24:00` is allowed as an end time
+fn parse_minute(value: &str) -> Option<i16> {
+    let (hours, minutes) = value.trim().split_once(':')?;
+    let (hours, minutes): (i16, i16) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if !(0..60).contains(&minutes) || !(0..=24).contains(&hours) {
+        return None;
+    }
+    let minute = hours * 60 + minutes;
+    (minute <= MINUTES_PER_DAY).then_some(minute)
+}
+
+impl From<&AvailabilitySlot> for SlotTimes {
+    fn from(slot: &AvailabilitySlot) -> Self {
+        SlotTimes {
+            day: slot.weekday.to_string(),
+            start: format_minute(slot.start_minute),
+            end: format_minute(slot.end_minute),
+        }
+    }
+}
+
+/// Check a week of windows: real days, `HH:MM` times, start before end and no overlaps.
+/// Windows can't run past midnight; one that does should be sent as two.
+pub fn parse_slots(slots: &[SlotTimes], errors: &mut Vec<FieldError>) -> Vec<AvailabilitySlot> {
+    if slots.len() > MAX_SLOTS {
+        errors.push(FieldError::new(
+            "slots",
+            "too_many",
+            format!("At most {} windows are allowed", MAX_SLOTS),
+        ));
+        return Vec::new();
+    }
+
+    let mut parsed = Vec::new();
+    for (i, slot) in slots.iter().enumerate() {
+        let field = format!("slots[{}]", i);
+        let weekday = Weekday::from_str(slot.day.trim());
+        let start = parse_minute(&slot.start).filter(|minute| *minute < MINUTES_PER_DAY);
+        let end = parse_minute(&slot.end);
+
+        if weekday.is_err() {
+            errors.push(FieldError::new(&field, "invalid_day", format!("\"{}\" is not a day of the week", slot.day)));
+        }
+        if start.is_none() || end.is_none() {
+            errors.push(FieldError::new(&field, "invalid_time", "Times must be HH:MM, from 00:00 to 24:00"));
+        }
+        if let (Ok(weekday), Some(start_minute), Some(end_minute)) = (weekday, start, end) {
+            if start_minute >= end_minute {
+                errors.push(FieldError::new(
+                    &field,
+                    "invalid_range",
+                    "A window must end after it starts; split windows that run past midnight",
+                ));
+            } else {
+                parsed.push(AvailabilitySlot { weekday, start_minute, end_minute });
+            }
+        }
+    }
+
+    parsed.sort_by_key(|slot| (slot.weekday, slot.start_minute));
+    if parsed
+        .windows(2)
+        .any(|pair| pair[0].weekday == pair[1].weekday && pair[1].start_minute < pair[0].end_minute)
+    {
+        errors.push(FieldError::new("slots", "overlapping", "Windows on the same day must not overlap"));
+    }
+    parsed
+}
+
+fn chrono_weekday(day: Weekday) -> chrono::Weekday {
+    match day {
+        Weekday::Monday => chrono::Weekday::Mon,
+        Weekday::Tuesday => chrono::Weekday::Tue,
+        Weekday::Wednesday => chrono::Weekday::Wed,
+        Weekday::Thursday => chrono::Weekday::Thu,
+        Weekday::Friday => chrono::Weekday::Fri,
+        Weekday::Saturday => chrono::Weekday::Sat,
+        Weekday::Sunday => chrono::Weekday::Sun,
+    }
+}
+
+/// Local wall-clock time to UTC. Ambiguous times (clocks going back) take the first occurrence;
+/// times skipped by clocks going forward move to just after the jump.
+fn to_utc(tz: Tz, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(time) => Some(time.with_timezone(&Utc)),
+        LocalResult::Ambiguous(earliest, _) => Some(earliest.with_timezone(&Utc)),
+        LocalResult::None => tz
+            .from_local_datetime(&(local + Duration::hours(1)))
+            .earliest()
+            .map(|time| time.with_timezone(&Utc)),
+    }
+}
+
+/// Sort and join touching or overlapping windows
+fn merge(mut windows: Vec<Window>) -> Vec<Window> {
+    windows.sort_by_key(|window| window.start);
+    let mut merged: Vec<Window> = Vec::with_capacity(windows.len());
+    for window in windows {
+        match merged.last_mut() {
+            Some(last) if window.start <= last.end => last.end = last.end.max(window.end),
+            _ => merged.push(window),
+        }
+    }
+    merged
+}
+
+/// The actual times a weekly schedule covers between `from` and `to`, following the owner's
+/// timezone through any DST changes in between
+pub fn concrete_windows(tz: Tz, slots: &[AvailabilitySlot], from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Window> {
+    let first_day = from.with_timezone(&tz).date_naive() - Duration::days(1);
+    let last_day = to.with_timezone(&tz).date_naive() + Duration::days(1);
+
+    let mut windows = Vec::new();
+    for date in first_day.iter_days().take_while(|date| *date <= last_day) {
+        let midnight = date.and_time(NaiveTime::MIN);
+        for slot in slots.iter().filter(|slot| chrono_weekday(slot.weekday) == date.weekday()) {
+            let start = to_utc(tz, midnight + Duration::minutes(slot.start_minute.into()));
+            let end = to_utc(tz, midnight + Duration::minutes(slot.end_minute.into()));
+            if let (Some(start), Some(end)) = (start, end) {
+                let window = Window { start: start.max(from), end: end.min(to) };
+                if window.start < window.end {
+                    windows.push(window);
+                }
+            }
+        }
+    }
+    merge(windows)
+}
+
+/// Times covered by both lists; both must be merged and sorted, as `concrete_windows` returns them
+pub fn intersect(a: &[Window], b: &[Window]) -> Vec<Window> {
+    let (mut i, mut j) = (0, 0);
+    let mut shared = Vec::new();
+    while i < a.len() && j < b.len() {
+        let start = a[i].start.max(b[j].start);
+        let end = a[i].end.min(b[j].end);
+        if start < end {
+            shared.push(Window { start, end });
+        }
+        if a[i].end < b[j].end {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    shared
+}
+
+pub fn total_hours(windows: &[Window]) -> f64 {
+    windows.iter().map(Window::hours).sum()
+}
+
+/// The seven days from now, the span schedules are compared over
+pub fn upcoming_week() -> (DateTime<Utc>, DateTime<Utc>) {
+    let now = Utc::now();
+    (now, now + Duration::days(7))
+}
+
+/// Timezone and weekly windows of each user that has an active account
+pub async fn load_schedules(
+    pool: &PgPool,
+    user_ids: &[Uuid],
+) -> Result<HashMap<Uuid, (Tz, Vec<AvailabilitySlot>)>, sqlx::Error> {
+    let users: Vec<(Uuid, String)> = sqlx::query_as(
+        "SELECT user_id, timezone FROM users
+         WHERE user_id = ANY($1) AND deletion_scheduled_for IS NULL AND purged_at IS NULL",
+    )
+    .bind(user_ids)
+    .fetch_all(pool)
+    .await?;
+
+    let mut schedules: HashMap<Uuid, (Tz, Vec<AvailabilitySlot>)> = users
+        .into_iter()
+        .map(|(user_id, timezone)| (user_id, (timezone_or_utc(&timezone), Vec::new())))
+        .collect();
+
+    let slots = sqlx::query_as::<_, (Uuid, Weekday, i16, i16)>(
+        "SELECT user_id, weekday, start_minute, end_minute FROM user_availability
+         WHERE user_id = ANY($1) ORDER BY weekday, start_minute",
+    )
+    .bind(user_ids)
+    .fetch_all(pool)
+    .await?;
+    for (user_id, weekday, start_minute, end_minute) in slots {
+        if let Some((_, user_slots)) = schedules.get_mut(&user_id) {
+            user_slots.push(AvailabilitySlot { weekday, start_minute, end_minute });
+        }
+    }
+    Ok(schedules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::{America, Europe};
+
+    fn slot(day: &str, start: &str, end: &str) -> SlotTimes {
+        SlotTimes { day: day.to_string(), start: start.to_string(), end: end.to_string() }
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn window(start: DateTime<Utc>, end: DateTime<Utc>) -> Window {
+        Window { start, end }
+    }
+
+    fn weekly(weekday: Weekday, start: &str, end: &str) -> AvailabilitySlot {
+        AvailabilitySlot {
+            weekday,
+            start_minute: parse_minute(start).unwrap(),
+            end_minute: parse_minute(end).unwrap(),
+        }
+    }
+
+    fn codes(errors: &[FieldError]) -> Vec<(&str, &str)> {
+        errors.iter().map(|e| (e.field.as_str(), e.code)).collect()
+    }
+
+    #[test]
+    fn slots_are_parsed_and_sorted() {
+        let mut errors = Vec::new();
+        let parsed = parse_slots(&[slot("Tue", "18:00", "21:00"), slot(" monday ", "09:30", "24:00")], &mut errors);
+        assert!(errors.is_empty());
+        let parsed: Vec<_> = parsed.iter().map(|s| (s.weekday, s.start_minute, s.end_minute)).collect();
+        assert_eq!(parsed, vec![(Weekday::Monday, 570, 1440), (Weekday::Tuesday, 1080, 1260)]);
+    }
+
+    #[test]
+    fn bad_slots_are_reported_by_index() {
+        let mut errors = Vec::new();
+        let parsed = parse_slots(
+            &[
+                slot("funday", "10:00", "11:00"),
+                slot("monday", "24:00", "24:00"),
+                slot("monday", "9:60", "10:00"),
+                slot("monday", "12:00", "11:00"),
+                slot("monday", "10:00", "10:00"),
+                slot("monday", "ten", "11:00"),
+            ],
+            &mut errors,
+        );
+        assert!(parsed.is_empty());
+        assert_eq!(
+            codes(&errors),
+            vec![
+                ("slots[0]", "invalid_day"),
+                ("slots[1]", "invalid_time"),
+                ("slots[2]", "invalid_time"),
+                ("slots[3]", "invalid_range"),
+                ("slots[4]", "invalid_range"),
+                ("slots[5]", "invalid_time"),
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_slots_are_rejected_but_touching_ones_are_not() {
+        let mut errors = Vec::new();
+        parse_slots(&[slot("monday", "10:00", "12:00"), slot("monday", "11:00", "13:00")], &mut errors);
+        assert_eq!(codes(&errors), vec![("slots", "overlapping")]);
+
+        let mut errors = Vec::new();
+        parse_slots(
+            &[slot("monday", "12:00", "14:00"), slot("monday", "10:00", "12:00"), slot("tuesday", "11:00", "13:00")],
+            &mut errors,
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn too_many_slots_are_rejected() {
+        let slots: Vec<_> = (0..=MAX_SLOTS).map(|_| slot("monday", "10:00", "11:00")).collect();
+        let mut errors = Vec::new();
+        assert!(parse_slots(&slots, &mut errors).is_empty());
+        assert_eq!(codes(&errors), vec![("slots", "too_many")]);
+    }
+
+    #[test]
+    fn weekly_slots_follow_the_owner_through_a_dst_change() {
+        // London moves from GMT to BST on Sunday 30 March 2025
+        let slots = [weekly(Weekday::Monday, "18:00", "21:00")];
+        let windows = concrete_windows(Europe::London, &slots, utc(2025, 3, 24, 0, 0), utc(2025, 4, 1, 0, 0));
+        assert_eq!(
+            windows,
+            vec![
+                window(utc(2025, 3, 24, 18, 0), utc(2025, 3, 24, 21, 0)),
+                window(utc(2025, 3, 31, 17, 0), utc(2025, 3, 31, 20, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn skipped_local_times_move_past_the_spring_forward_gap() {
+        // 01:00-02:00 on 30 March 2025 doesn't exist in London
+        let (from, to) = (utc(2025, 3, 24, 0, 0), utc(2025, 3, 31, 0, 0));
+        let windows = concrete_windows(Europe::London, &[weekly(Weekday::Sunday, "00:30", "02:30")], from, to);
+        assert_eq!(windows, vec![window(utc(2025, 3, 30, 0, 30), utc(2025, 3, 30, 1, 30))]);
+
+        let windows = concrete_windows(Europe::London, &[weekly(Weekday::Sunday, "01:00", "03:00")], from, to);
+        assert_eq!(windows, vec![window(utc(2025, 3, 30, 1, 0), utc(2025, 3, 30, 2, 0))]);
+        assert_eq!(total_hours(&windows), 1.0);
+    }
+
+    #[test]
+    fn repeated_local_times_take_the_first_occurrence_when_clocks_go_back() {
+        // 01:00-02:00 on 26 October 2025 happens twice in London
+        let (from, to) = (utc(2025, 10, 20, 0, 0), utc(2025, 10, 27, 0, 0));
+        let windows = concrete_windows(Europe::London, &[weekly(Weekday::Sunday, "00:00", "03:00")], from, to);
+        assert_eq!(windows, vec![window(utc(2025, 10, 25, 23, 0), utc(2025, 10, 26, 3, 0))]);
+        assert_eq!(total_hours(&windows), 4.0);
+
+        let windows = concrete_windows(Europe::London, &[weekly(Weekday::Sunday, "01:30", "02:30")], from, to);
+        assert_eq!(windows, vec![window(utc(2025, 10, 26, 0, 30), utc(2025, 10, 26, 2, 30))]);
+    }
+
+    #[test]
+    fn windows_are_clipped_to_the_range_and_merged_across_midnight() {
+        let slots = [weekly(Weekday::Monday, "18:00", "21:00")];
+        let windows = concrete_windows(Tz::UTC, &slots, utc(2025, 3, 24, 19, 0), utc(2025, 3, 24, 20, 0));
+        assert_eq!(windows, vec![window(utc(2025, 3, 24, 19, 0), utc(2025, 3, 24, 20, 0))]);
+
+        let slots = [weekly(Weekday::Monday, "22:00", "24:00"), weekly(Weekday::Tuesday, "00:00", "02:00")];
+        let windows = concrete_windows(Tz::UTC, &slots, utc(2025, 3, 24, 0, 0), utc(2025, 3, 31, 0, 0));
+        assert_eq!(windows, vec![window(utc(2025, 3, 24, 22, 0), utc(2025, 3, 25, 2, 0))]);
+    }
+
+    #[test]
+    fn overlap_between_timezones_shifts_while_only_one_has_changed_clocks() {
+        // New York moves to EDT on 9 March 2025, London stays on GMT until 30 March
+        let new_york = [weekly(Weekday::Monday, "12:00", "14:00")];
+        let london = [weekly(Weekday::Monday, "17:00", "20:00")];
+        let shared = |from, to| {
+            intersect(
+                &concrete_windows(America::New_York, &new_york, from, to),
+                &concrete_windows(Europe::London, &london, from, to),
+            )
+        };
+
+        let before = shared(utc(2025, 3, 3, 0, 0), utc(2025, 3, 10, 0, 0));
+        assert_eq!(before, vec![window(utc(2025, 3, 3, 17, 0), utc(2025, 3, 3, 19, 0))]);
+        let during = shared(utc(2025, 3, 10, 0, 0), utc(2025, 3, 17, 0, 0));
+        assert_eq!(during, vec![window(utc(2025, 3, 10, 17, 0), utc(2025, 3, 10, 18, 0))]);
+    }
+
+    #[test]
+    fn intersect_keeps_only_shared_time() {
+        let at = |hour| utc(2025, 3, 24, hour, 0);
+        let a = [window(at(1), at(3)), window(at(5), at(8))];
+        let b = [window(at(2), at(6)), window(at(7), at(9))];
+        let shared = intersect(&a, &b);
+        assert_eq!(shared, vec![window(at(2), at(3)), window(at(5), at(6)), window(at(7), at(8))]);
+        assert_eq!(total_hours(&shared), 3.0);
+
+        assert!(intersect(&[window(at(1), at(2))], &[window(at(2), at(3))]).is_empty());
+        assert!(intersect(&a, &[]).is_empty());
+    }
+}
@@ -0,0 +1,134 @@
+use crate::config::env_or;
+use crate::handlers::validation::FieldError;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits, Rgb, RgbImage};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use uuid::Uuid;
+
+/// Square sizes every upload is resized to, smallest first
+pub const AVATAR_SIZES: [u32; 2] = [128, 512];
+
+const ALLOWED_TYPES: &[&str] = &["image/jpeg", "image/png", "image/webp", "image/gif"];
+
+/// Largest accepted upload, from `AVATAR_MAX_BYTES`
+pub fn avatar_max_bytes() -> usize {
+    env_or("AVATAR_MAX_BYTES", 5 * 1024 * 1024)
+}
+
+/// Public URL of a user's avatar. `version` changes on every upload so caches pick it up.
+pub fn avatar_url(user_id: Uuid, version: Option<i64>) -> String {
+    match version {
+        Some(version) => format!("/api/public/avatars/{}?v={}", user_id, version),
+        None => format!("/api/public/avatars/{}", user_id),
+    }
+}
+
+/// Storage key of one resized copy of an uploaded avatar
+pub fn avatar_key(user_id: Uuid, version: i64, size: u32) -> String {
+    format!("avatars/{}/{}/{}.jpg", user_id, version, size)
+}
+
+/// The smallest standard size at least as large as `requested`
+pub fn pick_size(requested: Option<u32>) -> u32 {
+    let requested = requested.unwrap_or(AVATAR_SIZES[0]);
+    AVATAR_SIZES
+        .iter()
+        .copied()
+        .find(|size| *size >= requested)
+        .unwrap_or(AVATAR_SIZES[AVATAR_SIZES.len() - 1])
+}
+
+fn field_error(code: &'static str, message: &str) -> FieldError {
+    FieldError::new("avatar", code, message)
+}
+
+/// Check an upload really is a supported image, then produce a JPEG for each standard size.
+/// Re-encoding from pixels drops EXIF and any other metadata; orientation is applied first so
+/// phone photos stay upright.
+pub fn process_avatar(bytes: &[u8]) -> Result<Vec<(u32, Vec<u8>)>, FieldError> {
+    // Trust the file's magic bytes, not the client's Content-Type or filename
+    let format = infer::get(bytes)
+        .map(|kind| kind.mime_type())
+        .filter(|mime| ALLOWED_TYPES.contains(mime))
+        .and_then(ImageFormat::from_mime_type)
+        .ok_or_else(|| field_error("unsupported_type", "Avatar must be a JPEG, PNG, WebP or GIF image"))?;
+
+    let max_dimension: u32 = env_or("AVATAR_MAX_DIMENSION", 6000);
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(max_dimension);
+    limits.max_image_height = Some(max_dimension);
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let decoded = reader.into_decoder().and_then(|mut decoder| {
+        let orientation = decoder.orientation()?;
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+        Ok(image)
+    });
+    let image = decoded.map_err(|e| match e {
+        image::ImageError::Limits(_) => field_error("too_large", "Avatar dimensions are too large"),
+        _ => field_error("invalid_image", "Avatar could not be read as an image"),
+    })?;
+
+    AVATAR_SIZES
+        .iter()
+        .map(|&size| {
+            let resized = flatten(&image.resize_to_fill(size, size, FilterType::Lanczos3));
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, 85)
+                .encode_image(&resized)
+                .map_err(|_| field_error("invalid_image", "Avatar could not be processed"))?;
+            Ok((size, jpeg))
+        })
+        .collect()
+}
+
+/// Composite onto white, since JPEG has no transparency
+fn flatten(image: &DynamicImage) -> RgbImage {
+    let rgba = image.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u16 * a as u16 + 255 * (255 - a as u16)) / 255) as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+/// Up to two initials: the first letter of the first two words, or of the whole name
+fn initials(username: &str) -> String {
+    let words: Vec<&str> = username
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let initials: String = match words.as_slice() {
+        [] => "?".to_string(),
+        [one] => one.chars().take(1).collect(),
+        [first, second, ..] => first.chars().take(1).chain(second.chars().take(1)).collect(),
+    };
+    initials.to_uppercase()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Default avatar: the user's initials on a background colour derived from their username
+pub fn initials_svg(username: &str, size: u32) -> String {
+    const PALETTE: &[&str] = &[
+        "#1abc9c", "#2e86c1", "#8e44ad", "#c0392b", "#d35400", "#16a085", "#2c3e50", "#7d6608",
+    ];
+    let hash = Sha256::digest(username.to_lowercase().as_bytes());
+    let background = PALETTE[hash[0] as usize % PALETTE.len()];
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="0 0 100 100"><rect width="100" height="100" fill="{background}"/><text x="50" y="50" dy=".35em" text-anchor="middle" font-family="Helvetica, Arial, sans-serif" font-size="42" fill="#ffffff">{text}</text></svg>"##,
+        size = size,
+        background = background,
+        text = escape_xml(&initials(username)),
+    )
+}
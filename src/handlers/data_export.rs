@@ -0,0 +1,247 @@
+use crate::config::env_or;
+use crate::handlers::storage::StorageBackend;
+use chrono::{Duration, Utc};
+use hmac::{Hmac, KeyInit, Mac};
+use lazy_static::lazy_static;
+use rand::Rng;
+use serde_json::{Value, json};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::env;
+use std::io::{Cursor, Write};
+use std::sync::Arc;
+use uuid::Uuid;
+use zip::CompressionMethod;
+use zip::write::{SimpleFileOptions, ZipWriter};
+
+lazy_static! {
+    /// Key download links are signed with, from `EXPORT_LINK_SECRET`. Without it a random key is
+    /// used, so links stop working when the server restarts.
+    static ref EXPORT_LINK_KEY: Vec<u8> = match env::var("EXPORT_LINK_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+        _ => {
+            eprintln!("EXPORT_LINK_SECRET is not set; export download links won't survive a restart");
+            let mut key = vec![0u8; 32];
+            rand::rng().fill_bytes(&mut key);
+            key
+        }
+    };
+}
+
+/// What goes in the archive: one JSON-lines file per query, each bound to the user's id. Rows are
+/// serialized by Postgres so new columns show up without code changes; secrets are stripped here.
+const EXPORT_SECTIONS: &[(&str, &str)] = &[
+    (
+        "account.jsonl",
+        "SELECT to_jsonb(u) - 'password_hash' - 'email_verification_token' - 'forgot_password_token'
+                - 'forgot_password_expires_at'
+         FROM users u WHERE user_id = $1",
+    ),
+    ("profile_visibility.jsonl", "SELECT to_jsonb(v) FROM profile_visibility v WHERE user_id = $1"),
+    (
+        "availability.jsonl",
+        "SELECT to_jsonb(a) - 'user_id' FROM user_availability a WHERE user_id = $1 ORDER BY weekday, start_minute",
+    ),
+    ("username_history.jsonl", "SELECT to_jsonb(h) FROM username_history h WHERE user_id = $1 ORDER BY changed_at"),
+    (
+        "sessions.jsonl",
+        "SELECT to_jsonb(s) - 'refresh_token_hash' - 'previous_refresh_token_hash' FROM sessions s
+         WHERE user_id = $1 AND impersonator_id IS NULL ORDER BY created_at",
+    ),
+    (
+        "sponsor_applications.jsonl",
+        "SELECT to_jsonb(a) FROM sponsor_applications a WHERE user_id = $1 ORDER BY created_at",
+    ),
+    (
+        "sponsor_application_versions.jsonl",
+        "SELECT to_jsonb(v) FROM sponsor_application_versions v
+         JOIN sponsor_applications a ON a.application_id = v.application_id
+         WHERE a.user_id = $1 ORDER BY v.application_id, v.version",
+    ),
+    (
+        "sponsor_application_attachments.jsonl",
+        "SELECT to_jsonb(f) - 'storage_key' FROM sponsor_application_attachments f
+         JOIN sponsor_applications a ON a.application_id = f.application_id
+         WHERE a.user_id = $1 ORDER BY f.uploaded_at",
+    ),
+    (
+        // What references said is for reviewers only
+        "sponsor_application_references.jsonl",
+        "SELECT to_jsonb(r) - 'token_hash' - 'recommends' - 'known_for_years' - 'comments'
+         FROM sponsor_application_references r
+         JOIN sponsor_applications a ON a.application_id = r.application_id
+         WHERE a.user_id = $1 ORDER BY r.created_at",
+    ),
+    (
+        "matching_requests.jsonl",
+        "SELECT to_jsonb(m) FROM matching_requests m WHERE member_id = $1 OR sponsor_id = $1 ORDER BY created_at",
+    ),
+    (
+        "messages.jsonl",
+        "SELECT to_jsonb(m) FROM messages m WHERE sender_id = $1 OR receiver_id = $1 ORDER BY timestamp",
+    ),
+    (
+        "group_chat_messages.jsonl",
+        "SELECT to_jsonb(m) FROM group_chat_messages m WHERE sender_id = $1 ORDER BY timestamp",
+    ),
+    ("posts.jsonl", "SELECT to_jsonb(p) FROM posts p WHERE author_id = $1 ORDER BY created_at"),
+    ("comments.jsonl", "SELECT to_jsonb(c) FROM comments c WHERE author_id = $1 ORDER BY created_at"),
+    ("post_likes.jsonl", "SELECT to_jsonb(l) FROM post_likes l WHERE user_id = $1"),
+    (
+        "meetings.jsonl",
+        "SELECT to_jsonb(m) FROM group_meetings m
+         JOIN meeting_participants p ON p.meeting_id = m.meeting_id
+         WHERE p.user_id = $1 ORDER BY m.scheduled_time",
+    ),
+    ("reports_filed.jsonl", "SELECT to_jsonb(r) FROM reports r WHERE reporter_id = $1 ORDER BY created_at"),
+    ("blocks.jsonl", "SELECT to_jsonb(b) FROM user_blocks b WHERE blocker_id = $1 ORDER BY created_at"),
+    (
+        "announcements.jsonl",
+        "SELECT to_jsonb(a) FROM announcements a WHERE announcement_target_id = $1 ORDER BY created_at",
+    ),
+];
+
+/// Storage key of a finished archive
+pub fn export_key(user_id: Uuid, export_id: Uuid) -> String {
+    format!("exports/{}/{}.zip", user_id, export_id)
+}
+
+/// How long a finished archive is kept, from `EXPORT_RETENTION_HOURS`
+pub fn export_retention() -> Duration {
+    Duration::hours(env_or("EXPORT_RETENTION_HOURS", 48))
+}
+
+fn link_signature(export_id: Uuid, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&EXPORT_LINK_KEY).expect("HMAC accepts any key length");
+    mac.update(format!("{}:{}", export_id, expires).as_bytes());
+    mac
+}
+
+/// A download link valid for `EXPORT_LINK_TTL_MINUTES`. Anyone holding it can fetch the archive.
+pub fn signed_download_url(export_id: Uuid) -> String {
+    let expires = (Utc::now() + Duration::minutes(env_or("EXPORT_LINK_TTL_MINUTES", 60))).timestamp();
+    let signature = hex::encode(link_signature(export_id, expires).finalize().into_bytes());
+    format!(
+        "/api/public/users/exports/{}?expires={}&signature={}",
+        export_id, expires, signature
+    )
+}
+
+/// Whether a download link is genuine and hasn't expired
+pub fn verify_download_link(export_id: Uuid, expires: i64, signature: &str) -> bool {
+    if expires < Utc::now().timestamp() {
+        return false;
+    }
+    match hex::decode(signature) {
+        Ok(bytes) => link_signature(export_id, expires).verify_slice(&bytes).is_ok(),
+        Err(_) => false,
+    }
+}
+
+async fn collect_sections(pool: &PgPool, user_id: Uuid) -> Result<Vec<(&'static str, Vec<Value>)>, sqlx::Error> {
+    let mut sections = Vec::with_capacity(EXPORT_SECTIONS.len());
+    for (file_name, query) in EXPORT_SECTIONS {
+        let rows: Vec<Value> = sqlx::query_scalar(query).bind(user_id).fetch_all(pool).await?;
+        sections.push((*file_name, rows));
+    }
+    Ok(sections)
+}
+
+fn build_archive(user_id: Uuid, sections: Vec<(&'static str, Vec<Value>)>) -> Result<Vec<u8>, String> {
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+    let manifest = json!({
+        "user_id": user_id,
+        "generated_at": Utc::now().naive_utc(),
+        "files": sections
+            .iter()
+            .map(|(file_name, rows)| json!({ "name": file_name, "records": rows.len() }))
+            .collect::<Vec<_>>(),
+    });
+    zip.start_file("manifest.json", options).map_err(|e| e.to_string())?;
+    zip.write_all(manifest.to_string().as_bytes()).map_err(|e| e.to_string())?;
+
+    for (file_name, rows) in sections {
+        zip.start_file(file_name, options).map_err(|e| e.to_string())?;
+        for row in rows {
+            zip.write_all(row.to_string().as_bytes()).map_err(|e| e.to_string())?;
+            zip.write_all(b"\n").map_err(|e| e.to_string())?;
+        }
+    }
+
+    zip.finish().map(Cursor::into_inner).map_err(|e| e.to_string())
+}
+
+async fn run_export(
+    pool: &PgPool,
+    storage: &dyn StorageBackend,
+    export_id: Uuid,
+    user_id: Uuid,
+) -> Result<(String, usize), String> {
+    let sections = collect_sections(pool, user_id).await.map_err(|e| e.to_string())?;
+    let archive = tokio::task::spawn_blocking(move || build_archive(user_id, sections))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    let key = export_key(user_id, export_id);
+    let size = archive.len();
+    storage.put(&key, archive, "application/zip").await?;
+    Ok((key, size))
+}
+
+/// Build an export in the background and record the outcome on its `data_exports` row
+pub fn spawn_export(pool: PgPool, storage: Arc<dyn StorageBackend>, export_id: Uuid, user_id: Uuid) {
+    actix_web::rt::spawn(async move {
+        let result = match run_export(&pool, storage.as_ref(), export_id, user_id).await {
+            Ok((key, size)) => sqlx::query(
+                "UPDATE data_exports
+                 SET status = 'ready', storage_key = $1, size_bytes = $2, completed_at = NOW(),
+                     expires_at = NOW() + make_interval(hours => $3)
+                 WHERE export_id = $4",
+            )
+            .bind(key)
+            .bind(size as i64)
+            .bind(export_retention().num_hours() as i32)
+            .bind(export_id)
+            .execute(&pool)
+            .await,
+            Err(e) => {
+                eprintln!("Data export {} failed: {}", export_id, e);
+                sqlx::query("UPDATE data_exports SET status = 'failed', completed_at = NOW() WHERE export_id = $1")
+                    .bind(export_id)
+                    .execute(&pool)
+                    .await
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to record data export {}: {:?}", export_id, e);
+        }
+    });
+}
+
+/// Delete archives past their `expires_at`, and their rows. A row stays until its file is gone,
+/// so a failed delete is retried on the next run.
+pub async fn purge_expired_exports(pool: &PgPool, storage: &dyn StorageBackend) -> Result<usize, sqlx::Error> {
+    let expired: Vec<(Uuid, Option<String>)> = sqlx::query_as(
+        "SELECT export_id, storage_key FROM data_exports WHERE expires_at <= NOW() ORDER BY expires_at",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut purged = 0;
+    for (export_id, key) in expired {
+        if let Some(key) = key
+            && let Err(e) = storage.delete(&key).await
+        {
+            eprintln!("Failed to delete expired export {}: {}", key, e);
+            continue;
+        }
+        sqlx::query("DELETE FROM data_exports WHERE export_id = $1")
+            .bind(export_id)
+            .execute(pool)
+            .await?;
+        purged += 1;
+    }
+    Ok(purged)
+}
@@ -0,0 +1,55 @@
+use crate::config::env_list;
+use crate::handlers::mailer::{Mailer, OutgoingEmail, app_base_url};
+use actix_web::HttpResponse;
+use lazy_static::lazy_static;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+lazy_static! {
+    /// Actions that require a verified email, e.g. `request_sponsor,sponsor_application`
+    static ref VERIFIED_EMAIL_REQUIRED_FOR: Vec<String> =
+        env_list("EMAIL_VERIFICATION_REQUIRED_FOR");
+}
+
+/// Send the verification link for `token` to `email`
+pub async fn send_verification_email(
+    mailer: &dyn Mailer,
+    email: &str,
+    username: &str,
+    token: Uuid,
+) -> Result<(), String> {
+    let link = format!("{}/verify-email?token={}", app_base_url(), token);
+    mailer
+        .send(OutgoingEmail {
+            to: email.to_string(),
+            subject: "Verify your email address".to_string(),
+            body: format!(
+                "Hi {},\n\nPlease confirm your email address by opening the link below:\n\n{}\n\nIf you did not create an account, you can ignore this email.",
+                username, link
+            ),
+        })
+        .await
+}
+
+/// Returns a 403 response if `action` is gated on email verification and the user is not verified
+pub async fn ensure_email_verified(
+    pool: &PgPool,
+    user_id: Uuid,
+    action: &str,
+) -> Result<(), HttpResponse> {
+    if !VERIFIED_EMAIL_REQUIRED_FOR.iter().any(|a| a == action) {
+        return Ok(());
+    }
+
+    let verified: Result<Option<bool>, sqlx::Error> =
+        sqlx::query_scalar("SELECT email_verified FROM users WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await;
+
+    match verified {
+        Ok(Some(true)) => Ok(()),
+        Ok(_) => Err(HttpResponse::Forbidden().body("Please verify your email address first.")),
+        Err(_) => Err(HttpResponse::InternalServerError().body("Failed to fetch user data.")),
+    }
+}
@@ -0,0 +1,70 @@
+use crate::config::env_or;
+use crate::handlers::sessions::{generate_token, hash_refresh_token};
+use actix_web::http::Method;
+use chrono::Duration;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Lifetime of an impersonation token; there is no refresh
+pub fn impersonation_ttl() -> Duration {
+    Duration::minutes(env_or("IMPERSONATION_TTL_MINUTES", 15))
+}
+
+pub fn is_read_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Open a short-lived session for `impersonator_id` acting as `user_id`. Its refresh token is
+/// thrown away, so the session ends when the access token expires.
+pub async fn start_impersonation_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    impersonator_id: Uuid,
+    reason: &str,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query_scalar(
+        "INSERT INTO sessions (user_id, refresh_token_hash, expires_at, impersonator_id, impersonation_reason)
+         VALUES ($1, $2, NOW() + make_interval(mins => $3), $4, $5)
+         RETURNING session_id",
+    )
+    .bind(user_id)
+    .bind(hash_refresh_token(&generate_token()))
+    .bind(impersonation_ttl().num_minutes() as i32)
+    .bind(impersonator_id)
+    .bind(reason)
+    .fetch_one(pool)
+    .await
+}
+
+/// Who is impersonating whom, taken from the token before the request is handled
+pub struct ImpersonationContext {
+    pub session_id: Uuid,
+    pub impersonator_id: Uuid,
+    pub user_id: Uuid,
+}
+
+/// Write one request made under impersonation to the audit log
+pub async fn record_impersonated_request(
+    pool: &PgPool,
+    context: &ImpersonationContext,
+    method: &str,
+    path: &str,
+    status_code: u16,
+) {
+    let result = sqlx::query(
+        "INSERT INTO impersonation_audit_log (session_id, impersonator_id, user_id, method, path, status_code)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(context.session_id)
+    .bind(context.impersonator_id)
+    .bind(context.user_id)
+    .bind(method)
+    .bind(path)
+    .bind(status_code as i32)
+    .execute(pool)
+    .await;
+
+    if let Err(e) = result {
+        eprintln!("Failed to write impersonation audit log: {:?}", e);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::auth::access_token_ttl;
+use crate::config::env_or;
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::jwk::{Jwk, JwkSet, PublicKeyUse};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::{env, fs};
+
+/// Key id used for `JWT_SECRET` when no key file is configured, and for tokens without a `kid`
+pub const LEGACY_KID: &str = "default";
+
+/// One entry of the `JWT_KEYS_FILE` JSON array
+#[derive(Deserialize)]
+struct KeyConfig {
+    kid: String,
+    /// `HS256`, `RS256` or `EdDSA`
+    alg: Algorithm,
+    /// HS256 only: name of the environment variable holding the secret
+    secret_env: Option<String>,
+    /// PEM private key; only needed for the key we sign with
+    private_key_path: Option<String>,
+    /// PEM public key; published in the JWKS
+    public_key_path: Option<String>,
+    /// When set, the key can't sign and validates only until the grace period after this has passed.
+    /// Even a future date rules it out for signing, since the key set is only loaded at startup.
+    retired_at: Option<DateTime<Utc>>,
+}
+
+pub struct SigningKey {
+    pub kid: String,
+    pub alg: Algorithm,
+    pub key: EncodingKey,
+}
+
+pub struct VerificationKey {
+    pub alg: Algorithm,
+    pub key: DecodingKey,
+    pub retired_at: Option<DateTime<Utc>>,
+    /// Public form for the JWKS; `None` for shared secrets, which must never be published
+    jwk: Option<Jwk>,
+}
+
+/// Every key we accept, plus the one we sign new tokens with
+pub struct KeySet {
+    pub signing: SigningKey,
+    keys: HashMap<String, VerificationKey>,
+    grace: Duration,
+}
+
+lazy_static! {
+    /// Loaded once; `main` forces this at startup so bad key config fails fast
+    pub static ref KEY_SET: KeySet = KeySet::from_env().unwrap_or_else(|e| panic!("Invalid JWT key configuration: {}", e));
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e))
+}
+
+impl KeySet {
+    /// Reads `JWT_KEYS_FILE` and `JWT_SIGNING_KID`, or falls back to a single HS256 `JWT_SECRET`
+    fn from_env() -> Result<Self, String> {
+        let grace = Duration::minutes(env_or("JWT_KEY_GRACE_MINUTES", access_token_ttl().num_minutes()));
+
+        let path = match env::var("JWT_KEYS_FILE") {
+            Ok(path) if !path.is_empty() => path,
+            _ => {
+                let secret = env::var("JWT_SECRET").map_err(|_| "JWT_KEYS_FILE or JWT_SECRET must be set")?;
+                let mut keys = HashMap::new();
+                keys.insert(
+                    LEGACY_KID.to_string(),
+                    VerificationKey {
+                        alg: Algorithm::HS256,
+                        key: DecodingKey::from_secret(secret.as_bytes()),
+                        retired_at: None,
+                        jwk: None,
+                    },
+                );
+                return Ok(KeySet {
+                    signing: SigningKey {
+                        kid: LEGACY_KID.to_string(),
+                        alg: Algorithm::HS256,
+                        key: EncodingKey::from_secret(secret.as_bytes()),
+                    },
+                    keys,
+                    grace,
+                });
+            }
+        };
+
+        let configs: Vec<KeyConfig> = serde_json::from_slice(&read_file(&path)?)
+            .map_err(|e| format!("cannot parse {}: {}", path, e))?;
+        let signing_kid = env::var("JWT_SIGNING_KID").ok().filter(|k| !k.is_empty());
+
+        let mut keys = HashMap::new();
+        let mut signing = None;
+        for config in configs {
+            let (encoding, decoding, jwk) = load_key(&config)?;
+            let retiring = config.retired_at.is_some();
+
+            let wanted = match &signing_kid {
+                Some(kid) => *kid == config.kid,
+                None => signing.is_none() && !retiring && encoding.is_some(),
+            };
+            if wanted {
+                if retiring {
+                    return Err(format!("signing key {} has a retired_at date", config.kid));
+                }
+                let key = encoding.ok_or_else(|| format!("signing key {} has no private key", config.kid))?;
+                signing = Some(SigningKey { kid: config.kid.clone(), alg: config.alg, key });
+            }
+
+            let previous = keys.insert(
+                config.kid.clone(),
+                VerificationKey { alg: config.alg, key: decoding, retired_at: config.retired_at, jwk },
+            );
+            if previous.is_some() {
+                return Err(format!("duplicate kid {}", config.kid));
+            }
+        }
+
+        let signing = signing.ok_or("no usable signing key")?;
+        Ok(KeySet { signing, keys, grace })
+    }
+
+    /// The key for `kid`, unless it was retired longer ago than the grace period
+    pub fn verification_key(&self, kid: Option<&str>) -> Option<&VerificationKey> {
+        let key = self.keys.get(kid.unwrap_or(LEGACY_KID))?;
+        match key.retired_at {
+            Some(retired_at) if retired_at + self.grace <= Utc::now() => None,
+            _ => Some(key),
+        }
+    }
+
+    /// Public keys other services may verify our tokens with
+    pub fn jwks(&self) -> JwkSet {
+        let keys = self
+            .keys
+            .keys()
+            .filter_map(|kid| self.verification_key(Some(kid)))
+            .filter_map(|key| key.jwk.clone())
+            .collect();
+        JwkSet { keys }
+    }
+}
+
+/// Build the signing and verification halves of one configured key
+fn load_key(config: &KeyConfig) -> Result<(Option<EncodingKey>, DecodingKey, Option<Jwk>), String> {
+    let kid = &config.kid;
+    let err = |e: jsonwebtoken::errors::Error| format!("key {}: {}", kid, e);
+
+    match config.alg {
+        Algorithm::HS256 => {
+            let var = config.secret_env.as_deref().ok_or_else(|| format!("key {}: secret_env is required", kid))?;
+            let secret = env::var(var).map_err(|_| format!("key {}: {} is not set", kid, var))?;
+            Ok((Some(EncodingKey::from_secret(secret.as_bytes())), DecodingKey::from_secret(secret.as_bytes()), None))
+        }
+        Algorithm::RS256 | Algorithm::EdDSA => {
+            let public_path = config
+                .public_key_path
+                .as_deref()
+                .ok_or_else(|| format!("key {}: public_key_path is required", kid))?;
+            let public_pem = read_file(public_path)?;
+            let private_pem = config.private_key_path.as_deref().map(read_file).transpose()?;
+
+            let (encoding, decoding) = if config.alg == Algorithm::RS256 {
+                (
+                    private_pem.map(|pem| EncodingKey::from_rsa_pem(&pem)).transpose().map_err(err)?,
+                    DecodingKey::from_rsa_pem(&public_pem).map_err(err)?,
+                )
+            } else {
+                (
+                    private_pem.map(|pem| EncodingKey::from_ed_pem(&pem)).transpose().map_err(err)?,
+                    DecodingKey::from_ed_pem(&public_pem).map_err(err)?,
+                )
+            };
+
+            let mut jwk = Jwk::from_decoding_key(&decoding, Some(config.alg)).map_err(err)?;
+            jwk.common.key_id = Some(kid.clone());
+            jwk.common.public_key_use = Some(PublicKeyUse::Signature);
+            Ok((encoding, decoding, Some(jwk)))
+        }
+        other => Err(format!("key {}: unsupported algorithm {:?}", kid, other)),
+    }
+}
@@ -0,0 +1,251 @@
+use crate::config::{env_flag, env_or};
+use crate::models::all_models::SecurityEventType;
+use actix_web::HttpRequest;
+use chrono::{Duration, NaiveDateTime, Utc};
+use lazy_static::lazy_static;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Thresholds for login throttling, read from `LOGIN_*`
+struct ThrottleConfig {
+    max_account_failures: i64,
+    max_ip_failures: i64,
+    window_minutes: i64,
+    lockout_base_seconds: i64,
+    lockout_max_seconds: i64,
+    trust_proxy_headers: bool,
+}
+
+lazy_static! {
+    static ref THROTTLE_CONFIG: ThrottleConfig = ThrottleConfig {
+        max_account_failures: env_or("LOGIN_MAX_FAILURES_PER_ACCOUNT", 5),
+        max_ip_failures: env_or("LOGIN_MAX_FAILURES_PER_IP", 20),
+        window_minutes: env_or("LOGIN_FAILURE_WINDOW_MINUTES", 15),
+        lockout_base_seconds: env_or("LOGIN_LOCKOUT_BASE_SECONDS", 30),
+        lockout_max_seconds: env_or("LOGIN_LOCKOUT_MAX_SECONDS", 3600),
+        trust_proxy_headers: env_flag("TRUST_PROXY_HEADERS", false),
+    };
+}
+
+/// Client IP address; forwarded headers are only honoured when `TRUST_PROXY_HEADERS` is set
+pub fn client_ip(req: &HttpRequest) -> String {
+    let info = req.connection_info();
+    let addr = if THROTTLE_CONFIG.trust_proxy_headers {
+        info.realip_remote_addr()
+    } else {
+        info.peer_addr()
+    };
+    addr.unwrap_or("unknown").to_string()
+}
+
+/// Lockout length doubles with every failure past the threshold
+fn lockout_duration(failures: i64, threshold: i64) -> Duration {
+    let cfg = &*THROTTLE_CONFIG;
+    let exponent = (failures - threshold).clamp(0, 20) as u32;
+    let seconds = cfg
+        .lockout_base_seconds
+        .saturating_mul(2i64.saturating_pow(exponent))
+        .min(cfg.lockout_max_seconds);
+    Duration::seconds(seconds)
+}
+
+/// What failed attempts are counted against
+#[derive(Clone, Copy)]
+enum ThrottleKey {
+    Account,
+    Ip,
+}
+
+impl ThrottleKey {
+    fn predicate(self) -> &'static str {
+        match self {
+            ThrottleKey::Account => "lower(username) = lower($1)",
+            ThrottleKey::Ip => "ip_address = $1",
+        }
+    }
+
+    /// A successful login clears an account's failures but not an address's; otherwise logging
+    /// into an account of one's own every few guesses would keep an address from ever locking
+    fn resets_on_success(self) -> bool {
+        matches!(self, ThrottleKey::Account)
+    }
+}
+
+/// Failures counted against `key` among the window's attempts (oldest first), and the latest
+/// failure time
+fn count_failures(key: ThrottleKey, attempts: &[(bool, NaiveDateTime)]) -> (i64, Option<NaiveDateTime>) {
+    let counted = match attempts.iter().rposition(|(succeeded, _)| *succeeded) {
+        Some(last_success) if key.resets_on_success() => &attempts[last_success + 1..],
+        _ => attempts,
+    };
+    let failures: Vec<NaiveDateTime> = counted
+        .iter()
+        .filter(|(succeeded, _)| !succeeded)
+        .map(|(_, attempted_at)| *attempted_at)
+        .collect();
+    (failures.len() as i64, failures.iter().max().copied())
+}
+
+/// Failures within the window counted against `key`, and the latest failure time
+async fn recent_failures(
+    pool: &PgPool,
+    key: ThrottleKey,
+    value: &str,
+) -> Result<(i64, Option<NaiveDateTime>), sqlx::Error> {
+    let query = format!(
+        "SELECT succeeded, attempted_at FROM login_attempts
+         WHERE {} AND attempted_at > NOW() - make_interval(mins => $2)
+         ORDER BY attempted_at",
+        key.predicate()
+    );
+
+    let attempts: Vec<(bool, NaiveDateTime)> = sqlx::query_as(&query)
+        .bind(value)
+        .bind(THROTTLE_CONFIG.window_minutes as i32)
+        .fetch_all(pool)
+        .await?;
+    Ok(count_failures(key, &attempts))
+}
+
+fn locked_until(failures: i64, threshold: i64, last_failure: Option<NaiveDateTime>) -> Option<NaiveDateTime> {
+    if failures < threshold {
+        return None;
+    }
+    last_failure.map(|last| last + lockout_duration(failures, threshold))
+}
+
+/// Returns the number of seconds to wait if the account or IP is currently locked out
+pub async fn check_login_allowed(pool: &PgPool, username: &str, ip: &str) -> Result<Option<i64>, sqlx::Error> {
+    let cfg = &*THROTTLE_CONFIG;
+    let now = Utc::now().naive_utc();
+
+    let (account_failures, account_last) = recent_failures(pool, ThrottleKey::Account, username).await?;
+    let (ip_failures, ip_last) = recent_failures(pool, ThrottleKey::Ip, ip).await?;
+
+    let wait = [
+        locked_until(account_failures, cfg.max_account_failures, account_last),
+        locked_until(ip_failures, cfg.max_ip_failures, ip_last),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|until| *until > now)
+    .map(|until| (until - now).num_seconds().max(1))
+    .max();
+
+    Ok(wait)
+}
+
+/// Record a login attempt, logging a security event when a failure triggers a lockout
+pub async fn record_login_attempt(
+    pool: &PgPool,
+    username: &str,
+    ip: &str,
+    user_id: Option<Uuid>,
+    succeeded: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO login_attempts (username, ip_address, succeeded) VALUES ($1, $2, $3)")
+        .bind(username)
+        .bind(ip)
+        .bind(succeeded)
+        .execute(pool)
+        .await?;
+
+    if succeeded {
+        return Ok(());
+    }
+
+    let cfg = &*THROTTLE_CONFIG;
+    let (account_failures, account_last) = recent_failures(pool, ThrottleKey::Account, username).await?;
+    if let Some(until) = locked_until(account_failures, cfg.max_account_failures, account_last) {
+        record_security_event(
+            pool,
+            SecurityEventType::AccountLockout,
+            user_id,
+            Some(username),
+            Some(ip),
+            &format!("{} consecutive failed logins", account_failures),
+            Some(until),
+        )
+        .await?;
+    }
+
+    let (ip_failures, ip_last) = recent_failures(pool, ThrottleKey::Ip, ip).await?;
+    if let Some(until) = locked_until(ip_failures, cfg.max_ip_failures, ip_last) {
+        record_security_event(
+            pool,
+            SecurityEventType::IpLockout,
+            None,
+            None,
+            Some(ip),
+            &format!("{} failed logins from this address", ip_failures),
+            Some(until),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Store an event in the admin-visible security log
+pub async fn record_security_event(
+    pool: &PgPool,
+    event_type: SecurityEventType,
+    user_id: Option<Uuid>,
+    username: Option<&str>,
+    ip: Option<&str>,
+    details: &str,
+    locked_until: Option<NaiveDateTime>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO security_events (event_type, user_id, username, ip_address, details, locked_until)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(event_type)
+    .bind(user_id)
+    .bind(username)
+    .bind(ip)
+    .bind(details)
+    .bind(locked_until)
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn history(outcomes: &[bool]) -> Vec<(bool, NaiveDateTime)> {
+        let start = NaiveDate::from_ymd_opt(2025, 3, 24).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        outcomes
+            .iter()
+            .enumerate()
+            .map(|(i, succeeded)| (*succeeded, start + Duration::minutes(i as i64)))
+            .collect()
+    }
+
+    #[test]
+    fn a_success_clears_account_failures() {
+        let attempts = history(&[false, false, true, false]);
+        assert_eq!(count_failures(ThrottleKey::Account, &attempts), (1, Some(attempts[3].1)));
+
+        let attempts = history(&[false, false, true]);
+        assert_eq!(count_failures(ThrottleKey::Account, &attempts), (0, None));
+    }
+
+    #[test]
+    fn a_success_does_not_clear_ip_failures() {
+        let attempts = history(&[false, false, true, false, false, true, false, true]);
+        assert_eq!(count_failures(ThrottleKey::Ip, &attempts), (5, Some(attempts[6].1)));
+    }
+
+    #[test]
+    fn every_failure_counts_without_a_success() {
+        let attempts = history(&[false, false, false]);
+        for key in [ThrottleKey::Account, ThrottleKey::Ip] {
+            assert_eq!(count_failures(key, &attempts), (3, Some(attempts[2].1)));
+        }
+        assert_eq!(count_failures(ThrottleKey::Ip, &[]), (0, None));
+    }
+}
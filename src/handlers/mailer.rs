@@ -0,0 +1,129 @@
+use crate::config::{env_flag, env_or};
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+/// An email ready to be handed to a mailer
+#[derive(Debug, Clone)]
+pub struct OutgoingEmail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Pluggable transport for outgoing email
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: OutgoingEmail) -> Result<(), String>;
+}
+
+/// Sends email over SMTP (a relay in production, or a local catcher such as MailHog in development)
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn from_env() -> Result<Self, String> {
+        let host = env::var("SMTP_HOST").unwrap_or_else(|_| "localhost".to_string());
+        let port: u16 = env_or("SMTP_PORT", 1025);
+        let from = env::var("MAIL_FROM")
+            .unwrap_or_else(|_| "BTH <no-reply@localhost>".to_string())
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid MAIL_FROM: {}", e))?;
+
+        let mut builder = if env_flag("SMTP_TLS", false) {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&host).map_err(|e| e.to_string())?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host)
+        }
+        .port(port);
+
+        if let (Ok(user), Ok(pass)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            builder = builder.credentials(Credentials::new(user, pass));
+        }
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: OutgoingEmail) -> Result<(), String> {
+        let to = email
+            .to
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid recipient: {}", e))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject)
+            .body(email.body)
+            .map_err(|e| e.to_string())?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Writes emails to a file (or stdout when no path is set) instead of sending them
+pub struct LogMailer {
+    path: Option<PathBuf>,
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: OutgoingEmail) -> Result<(), String> {
+        let entry = format!(
+            "To: {}\nSubject: {}\n\n{}\n----\n",
+            email.to, email.subject, email.body
+        );
+
+        match &self.path {
+            Some(path) => {
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                file.write_all(entry.as_bytes())
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+            None => {
+                println!("{}", entry);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Builds the mailer selected by `MAILER` (`smtp` or `log`)
+pub fn mailer_from_env() -> Arc<dyn Mailer> {
+    match env::var("MAILER").unwrap_or_default().as_str() {
+        "smtp" => match SmtpMailer::from_env() {
+            Ok(mailer) => Arc::new(mailer),
+            Err(e) => panic!("Failed to configure SMTP mailer: {}", e),
+        },
+        _ => Arc::new(LogMailer {
+            path: env::var("MAIL_LOG_PATH").ok().map(PathBuf::from),
+        }),
+    }
+}
+
+/// Base URL of the frontend, used to build links in emails
+pub fn app_base_url() -> String {
+    env::var("APP_BASE_URL").unwrap_or_else(|_| "http://localhost:3001".to_string())
+}
@@ -1,4 +1,6 @@
 use chrono::{Datelike, Utc};
+use crate::handlers::availability::{concrete_windows, intersect, timezone_or_utc, total_hours, upcoming_week};
+use crate::handlers::profile_fields::primary_language;
 use crate::models::all_models::MatchUser;
 use geoutils::Location;
 
@@ -42,14 +44,22 @@ pub fn calculate_match_score(member: &MatchUser, sponsor: &MatchUser) -> f32 {
         score += exp_score;
     }
 
-    if let (Some(member_avail), Some(sponsor_avail)) = (&member.available_days, &sponsor.available_days) {
-        let common = member_avail.iter().filter(|d| sponsor_avail.contains(d)).count();
-        let avail_score = 10.0 * (common as f32 / member_avail.len().max(1) as f32);
+    // Hours both are actually free over the coming week, after converting out of each timezone
+    let (from, to) = upcoming_week();
+    let member_free = concrete_windows(timezone_or_utc(&member.timezone), &member.availability, from, to);
+    if !member_free.is_empty() {
+        let sponsor_free = concrete_windows(timezone_or_utc(&sponsor.timezone), &sponsor.availability, from, to);
+        let shared = total_hours(&intersect(&member_free, &sponsor_free));
+        let avail_score = 10.0 * (shared / total_hours(&member_free)) as f32;
         score += avail_score;
     }
 
     if let (Some(member_lang), Some(sponsor_lang)) = (&member.languages, &sponsor.languages) {
-        let common = member_lang.iter().filter(|l| sponsor_lang.contains(l)).count();
+        // Regional variants of a language are mutually intelligible for our purposes
+        let common = member_lang
+            .iter()
+            .filter(|l| sponsor_lang.iter().any(|s| primary_language(s) == primary_language(l)))
+            .count();
         let lang_score = 5.0 * (common as f32 / member_lang.len().max(1) as f32);
         score += lang_score;
     }
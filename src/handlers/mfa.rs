@@ -0,0 +1,189 @@
+use crate::handlers::password::{hash_password, verify_password};
+use rand::RngExt;
+use sqlx::PgPool;
+use std::env;
+use totp_rs::{Builder, Secret, Totp};
+use uuid::Uuid;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Issuer label shown in authenticator apps
+fn issuer() -> String {
+    env::var("MFA_ISSUER").unwrap_or_else(|_| "BTH".to_string())
+}
+
+/// Build a TOTP (RFC 6238, SHA-1, 6 digits, 30s steps) from a base32 secret
+pub fn build_totp(secret_base32: &str, username: &str) -> Result<Totp, String> {
+    let secret = Secret::try_from_base32(secret_base32).map_err(|_| "Invalid TOTP secret".to_string())?;
+    Builder::new()
+        .with_secret(secret)
+        .with_account_name(username)
+        .with_issuer(Some(issuer()))
+        .build()
+        .map_err(|e| format!("{:?}", e))
+}
+
+/// Whether the user has enrolled and whether their role requires them to
+pub struct MfaRequirement {
+    pub enrolled: bool,
+    pub required: bool,
+}
+
+pub async fn mfa_requirement(pool: &PgPool, user_id: Uuid, role: &str) -> Result<MfaRequirement, sqlx::Error> {
+    let enrolled: Option<bool> = sqlx::query_scalar("SELECT enabled FROM user_mfa WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    let required: Option<bool> = sqlx::query_scalar("SELECT required FROM mfa_policy WHERE role::TEXT = $1")
+        .bind(role)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(MfaRequirement {
+        enrolled: enrolled.unwrap_or(false),
+        required: required.unwrap_or(false),
+    })
+}
+
+/// Start (or restart) enrolment with a fresh secret. Returns `None` if MFA is already enabled.
+pub async fn begin_enrolment(pool: &PgPool, user_id: Uuid, username: &str) -> Result<Option<(String, String)>, String> {
+    let secret = Secret::generate().to_base32();
+    let uri = build_totp(&secret, username)?
+        .to_url()
+        .map_err(|e| format!("{:?}", e))?;
+
+    let result = sqlx::query(
+        "INSERT INTO user_mfa (user_id, totp_secret, enabled) VALUES ($1, $2, FALSE)
+         ON CONFLICT (user_id) DO UPDATE SET totp_secret = EXCLUDED.totp_secret, created_at = NOW(), last_used_step = NULL
+         WHERE user_mfa.enabled = FALSE",
+    )
+    .bind(user_id)
+    .bind(&secret)
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+    Ok(Some((secret, uri)))
+}
+
+/// Check a TOTP code against the user's secret (enabled or pending). Each time step is only
+/// accepted once, as RFC 6238 requires.
+pub async fn verify_totp_code(pool: &PgPool, user_id: Uuid, username: &str, code: &str) -> Result<bool, String> {
+    let row: Option<(String, Option<i64>)> =
+        sqlx::query_as("SELECT totp_secret, last_used_step FROM user_mfa WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+    let (secret, last_used_step) = match row {
+        Some(row) => row,
+        None => return Ok(false),
+    };
+
+    let step = match build_totp(&secret, username)?.check_current(code.trim()) {
+        Some(step) => step as i64,
+        None => return Ok(false),
+    };
+    if last_used_step.is_some_and(|last| step <= last) {
+        return Ok(false);
+    }
+
+    let updated = sqlx::query(
+        "UPDATE user_mfa SET last_used_step = $2
+         WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+    )
+    .bind(user_id)
+    .bind(step)
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(updated.rows_affected() > 0)
+}
+
+/// Turn a pending enrolment on
+pub async fn enable_mfa(pool: &PgPool, user_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE user_mfa SET enabled = TRUE, enabled_at = NOW() WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map(|_| ())
+}
+
+/// Remove MFA and any recovery codes
+pub async fn disable_mfa(pool: &PgPool, user_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM user_mfa WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map(|_| ())
+}
+
+/// Replace the user's recovery codes, returning the new plaintext codes (shown once)
+pub async fn regenerate_recovery_codes(pool: &PgPool, user_id: Uuid) -> Result<Vec<String>, String> {
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = rand::rng();
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let raw: String = (0..10)
+                .map(|_| ALPHABET[rng.random_range(0..ALPHABET.len())] as char)
+                .collect();
+            format!("{}-{}", &raw[..5], &raw[5..])
+        })
+        .collect();
+
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for code in &codes {
+        let code_hash = hash_password(code).map_err(|e| e.to_string())?;
+        sqlx::query("INSERT INTO mfa_recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(code_hash)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    Ok(codes)
+}
+
+/// Use up a recovery code if it matches one of the user's unused codes
+pub async fn consume_recovery_code(pool: &PgPool, user_id: Uuid, code: &str) -> Result<bool, String> {
+    let candidates: Vec<(Uuid, String)> = sqlx::query_as(
+        "SELECT code_id, code_hash FROM mfa_recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let normalized = code.trim().to_lowercase();
+    for (code_id, code_hash) in candidates {
+        if verify_password(&normalized, &code_hash).unwrap_or(false) {
+            let used = sqlx::query(
+                "UPDATE mfa_recovery_codes SET used_at = NOW() WHERE code_id = $1 AND used_at IS NULL",
+            )
+            .bind(code_id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+            return Ok(used.rows_affected() > 0);
+        }
+    }
+    Ok(false)
+}
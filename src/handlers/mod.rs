@@ -1,3 +1,26 @@
 pub mod ws;
 pub mod match_algo;
 pub mod password;
+pub mod mailer;
+pub mod email_verification;
+pub mod sessions;
+pub mod auth_cookies;
+pub mod login_throttle;
+pub mod mfa;
+pub mod validation;
+pub mod password_policy;
+pub mod jwt_keys;
+pub mod impersonation;
+pub mod storage;
+pub mod avatars;
+pub mod profile_view;
+pub mod blocks;
+pub mod data_export;
+pub mod account_deletion;
+pub mod profile_fields;
+pub mod availability;
+pub mod notifications;
+pub mod sponsor_applications;
+pub mod questionnaire;
+pub mod virus_scan;
+pub mod application_documents;
@@ -0,0 +1,25 @@
+use crate::handlers::ws::send_to_user;
+use serde_json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Tell one user something: kept as an announcement for when they next look, and pushed over the
+/// websocket straight away if they're connected. `payload` gets the message added as `message`.
+pub async fn notify_user(pool: &PgPool, user_id: Uuid, message: &str, mut payload: Value) {
+    let result = sqlx::query(
+        "INSERT INTO announcements (announcement_type, announcement_target_id, message)
+         VALUES ('general', $1, $2)",
+    )
+    .bind(user_id)
+    .bind(message)
+    .execute(pool)
+    .await;
+    if let Err(e) = result {
+        eprintln!("Failed to create announcement: {:?}", e);
+    }
+
+    if let Some(fields) = payload.as_object_mut() {
+        fields.insert("message".to_string(), Value::from(message));
+    }
+    send_to_user(pool, None, &user_id, payload).await;
+}
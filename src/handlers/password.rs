@@ -1,11 +1,38 @@
 
-use argon2::{Argon2, PasswordHasher, PasswordVerifier,password_hash};
+use crate::config::env_or;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, PasswordVerifier, Version, password_hash};
 use password_hash::{SaltString, PasswordHash, rand_core::OsRng};
+use lazy_static::lazy_static;
+use sqlx::PgPool;
+use uuid::Uuid;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    /// Argon2id costs from `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM`
+    static ref ARGON2_PARAMS: Params = {
+        let memory = env_or("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST);
+        let iterations = env_or("ARGON2_ITERATIONS", Params::DEFAULT_T_COST);
+        let parallelism = env_or("ARGON2_PARALLELISM", Params::DEFAULT_P_COST);
+        Params::new(memory, iterations, parallelism, None).unwrap_or_else(|e| {
+            eprintln!("Invalid Argon2 parameters ({}), using defaults", e);
+            Params::default()
+        })
+    };
+
+    /// Hash used to spend the same verification time on unknown usernames
+    static ref DUMMY_HASH: String =
+        hash_password("not-a-real-password").expect("Failed to hash dummy password");
+}
+
+fn argon2_with(params: Params) -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
 /// Hash a password using Argon2
 pub fn hash_password(password: &str) -> Result<String, password_hash::Error> {
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    
+    let argon2 = argon2_with(ARGON2_PARAMS.clone());
+
     let password_hash = argon2.hash_password(password.as_bytes(), &salt)?;
     Ok(password_hash.to_string())
 }
@@ -13,7 +40,81 @@ pub fn hash_password(password: &str) -> Result<String, password_hash::Error> {
 /// Verify a password
 pub fn verify_password(password: &str, hash: &str) -> Result<bool, password_hash::Error> {
     let parsed_hash = PasswordHash::new(hash)?;
+    // The hash string carries its own parameters, so this works for hashes of any cost
     let argon2 = Argon2::default();
-    
+
     Ok(argon2.verify_password(password.as_bytes(), &parsed_hash).is_ok())
 }
+
+/// Whether a stored hash was made with another algorithm or weaker costs than currently
+/// configured, and should be replaced the next time the password is known
+pub fn needs_rehash(hash: &str) -> bool {
+    let parsed = match PasswordHash::new(hash) {
+        Ok(parsed) => parsed,
+        Err(_) => return true,
+    };
+    if parsed.algorithm != Algorithm::Argon2id.ident() || parsed.version != Some(Version::V0x13.into()) {
+        return true;
+    }
+
+    match Params::try_from(&parsed) {
+        Ok(params) => {
+            params.m_cost() < ARGON2_PARAMS.m_cost()
+                || params.t_cost() < ARGON2_PARAMS.t_cost()
+                || params.p_cost() < ARGON2_PARAMS.p_cost()
+        }
+        Err(_) => true,
+    }
+}
+
+/// After a successful login, replace an outdated hash with one using the current parameters.
+/// The update is skipped if the password changed in the meantime.
+pub async fn rehash_if_needed(pool: &PgPool, user_id: Uuid, password: &str, current_hash: &str) {
+    if !needs_rehash(current_hash) {
+        return;
+    }
+    let new_hash = match hash_password(password) {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("Failed to rehash password: {}", e);
+            return;
+        }
+    };
+
+    let result = sqlx::query("UPDATE users SET password_hash = $1 WHERE user_id = $2 AND password_hash = $3")
+        .bind(new_hash)
+        .bind(user_id)
+        .bind(current_hash)
+        .execute(pool)
+        .await;
+    if let Err(e) = result {
+        eprintln!("Failed to store upgraded password hash: {:?}", e);
+    }
+}
+
+/// Run a full verification against a throwaway hash so failed logins for unknown users take
+/// as long as those for real ones
+pub fn dummy_verify_password(password: &str) {
+    let _ = verify_password(password, &DUMMY_HASH);
+}
+
+/// Find the smallest iteration count that takes at least `target` per hash with the configured
+/// memory and parallelism. Returns the parameters and the measured time.
+pub fn benchmark_params(target: Duration) -> Result<(Params, Duration), password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let mut iterations = 1;
+
+    loop {
+        let params = Params::new(ARGON2_PARAMS.m_cost(), iterations, ARGON2_PARAMS.p_cost(), None)?;
+        let argon2 = argon2_with(params.clone());
+
+        let started = Instant::now();
+        argon2.hash_password(b"benchmark-password", &salt)?;
+        let elapsed = started.elapsed();
+
+        if elapsed >= target || iterations >= 64 {
+            return Ok((params, elapsed));
+        }
+        iterations += 1;
+    }
+}
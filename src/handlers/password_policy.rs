@@ -0,0 +1,116 @@
+use crate::config::env_or;
+use crate::handlers::validation::FieldError;
+use lazy_static::lazy_static;
+use sha1::{Digest, Sha1};
+use std::env;
+use std::path::PathBuf;
+use zxcvbn::zxcvbn;
+
+/// Password rules, read from `PASSWORD_*` and `BREACHED_PASSWORDS_*`
+struct PasswordPolicy {
+    min_length: usize,
+    max_length: usize,
+    /// Minimum zxcvbn score, 0 (weakest) to 4
+    min_score: u8,
+    /// Directory of k-anonymity range files: `ABCDE.txt` holds `SUFFIX:COUNT` lines for every
+    /// breached SHA-1 hash starting with `ABCDE`
+    breached_dir: Option<PathBuf>,
+    breached_min_count: u64,
+}
+
+lazy_static! {
+    static ref PASSWORD_POLICY: PasswordPolicy = PasswordPolicy {
+        min_length: env_or("PASSWORD_MIN_LENGTH", 10),
+        max_length: env_or("PASSWORD_MAX_LENGTH", 128),
+        min_score: env_or("PASSWORD_MIN_SCORE", 3u8).min(4),
+        breached_dir: env::var("BREACHED_PASSWORDS_DIR")
+            .ok()
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from),
+        breached_min_count: env_or("BREACHED_PASSWORDS_MIN_COUNT", 1),
+    };
+}
+
+/// Look the password up in the local breached-password list. Only the range file for the first
+/// five hex digits of the hash is read.
+async fn is_breached(password: &str) -> bool {
+    let dir = match &PASSWORD_POLICY.breached_dir {
+        Some(dir) => dir,
+        None => return false,
+    };
+
+    let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = hash.split_at(5);
+
+    let contents = match tokio::fs::read_to_string(dir.join(format!("{}.txt", prefix))).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return false,
+        Err(e) => {
+            eprintln!("Failed to read breached password range {}: {}", prefix, e);
+            return false;
+        }
+    };
+
+    contents.lines().any(|line| {
+        let (line_suffix, count) = line.trim().split_once(':').unwrap_or((line.trim(), "1"));
+        line_suffix.eq_ignore_ascii_case(suffix)
+            && count.trim().parse::<u64>().unwrap_or(1) >= PASSWORD_POLICY.breached_min_count
+    })
+}
+
+/// Check a new password against the policy. `field` is the request field the errors refer to;
+/// `username` and `email` are the account's, since passwords may not contain either.
+pub async fn validate_password(field: &str, password: &str, username: &str, email: &str) -> Vec<FieldError> {
+    let policy = &*PASSWORD_POLICY;
+    let mut errors = Vec::new();
+    let length = password.chars().count();
+
+    if length < policy.min_length {
+        errors.push(FieldError::new(
+            field,
+            "too_short",
+            format!("Password must be at least {} characters", policy.min_length),
+        ));
+    }
+    if length > policy.max_length {
+        // Also keeps zxcvbn and Argon2 away from huge inputs
+        errors.push(FieldError::new(
+            field,
+            "too_long",
+            format!("Password must be at most {} characters", policy.max_length),
+        ));
+        return errors;
+    }
+
+    let lowered = password.to_lowercase();
+    let username = username.trim().to_lowercase();
+    let email = email.trim().to_lowercase();
+    let email_local = email.split('@').next().unwrap_or_default().to_string();
+
+    if username.chars().count() >= 3 && lowered.contains(&username) {
+        errors.push(FieldError::new(field, "contains_username", "Password must not contain your username"));
+    }
+    if email_local.chars().count() >= 3 && lowered.contains(&email_local) {
+        errors.push(FieldError::new(field, "contains_email", "Password must not contain your email address"));
+    }
+
+    let entropy = zxcvbn(password, &[&username, &email, &email_local]);
+    if u8::from(entropy.score()) < policy.min_score {
+        let message = entropy
+            .feedback()
+            .and_then(|feedback| feedback.warning())
+            .map(|warning| format!("Password is too weak: {}", warning))
+            .unwrap_or_else(|| "Password is too weak".to_string());
+        errors.push(FieldError::new(field, "too_weak", message));
+    }
+
+    if is_breached(password).await {
+        errors.push(FieldError::new(
+            field,
+            "breached",
+            "This password has appeared in a data breach; please choose another",
+        ));
+    }
+
+    errors
+}
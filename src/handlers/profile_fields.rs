@@ -0,0 +1,220 @@
+use crate::handlers::validation::FieldError;
+use crate::models::all_models::{Location, ProfileTagKind};
+use sqlx::PgPool;
+
+/// Most entries accepted in any one list field
+pub const MAX_LIST_ITEMS: usize = 20;
+
+const MAX_PLACE_NAME_LENGTH: usize = 100;
+
+/// Same rules as the `slugify` used when the taxonomy was seeded: lowercase words joined by `-`
+pub fn slugify(value: &str) -> String {
+    value
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn check_list_length(field: &str, len: usize, errors: &mut Vec<FieldError>) -> bool {
+    if len > MAX_LIST_ITEMS {
+        errors.push(FieldError::new(
+            field,
+            "too_many",
+            format!("At most {} entries are allowed", MAX_LIST_ITEMS),
+        ));
+        return false;
+    }
+    true
+}
+
+fn place_name(field: &str, value: Option<String>, errors: &mut Vec<FieldError>) -> Option<String> {
+    let value = value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())?;
+    if value.chars().count() > MAX_PLACE_NAME_LENGTH {
+        errors.push(FieldError::new(
+            field,
+            "too_long",
+            format!("Must be at most {} characters", MAX_PLACE_NAME_LENGTH),
+        ));
+    }
+    Some(value)
+}
+
+/// Check coordinates are real and in range, and tidy the place names
+pub fn normalize_location(location: Location, errors: &mut Vec<FieldError>) -> Location {
+    if !location.latitude.is_finite() || !(-90.0..=90.0).contains(&location.latitude) {
+        errors.push(FieldError::new(
+            "location.latitude",
+            "out_of_range",
+            "Latitude must be between -90 and 90",
+        ));
+    }
+    if !location.longitude.is_finite() || !(-180.0..=180.0).contains(&location.longitude) {
+        errors.push(FieldError::new(
+            "location.longitude",
+            "out_of_range",
+            "Longitude must be between -180 and 180",
+        ));
+    }
+
+    Location {
+        latitude: location.latitude,
+        longitude: location.longitude,
+        city: place_name("location.city", location.city, errors),
+        country: place_name("location.country", location.country, errors),
+    }
+}
+
+/// Well-formed BCP 47 tag (language, optional script, region and variants) in canonical case,
+/// e.g. `en`, `en-GB`, `zh-Hant-TW`. Extensions and private-use tags aren't accepted.
+pub fn normalize_language_tag(tag: &str) -> Option<String> {
+    let mut subtags = tag.trim().split(['-', '_']);
+    let language = subtags.next()?;
+    // 5-8 letter primary subtags are reserved and never assigned; allowing them would accept "english"
+    if !matches!(language.len(), 2 | 3) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let mut canonical = vec![language.to_ascii_lowercase()];
+    // 0 = script may follow, 1 = region may follow, 2 = only variants may follow
+    let mut position = 0;
+    for subtag in subtags {
+        let alpha = subtag.chars().all(|c| c.is_ascii_alphabetic());
+        let digits = subtag.chars().all(|c| c.is_ascii_digit());
+        let alphanumeric = subtag.chars().all(|c| c.is_ascii_alphanumeric());
+
+        if position == 0 && subtag.len() == 4 && alpha {
+            let mut script = subtag.to_ascii_lowercase();
+            script[..1].make_ascii_uppercase();
+            canonical.push(script);
+            position = 1;
+        } else if position <= 1 && ((subtag.len() == 2 && alpha) || (subtag.len() == 3 && digits)) {
+            canonical.push(subtag.to_ascii_uppercase());
+            position = 2;
+        } else if alphanumeric
+            && ((5..=8).contains(&subtag.len())
+                || (subtag.len() == 4 && subtag.starts_with(|c: char| c.is_ascii_digit())))
+        {
+            canonical.push(subtag.to_ascii_lowercase());
+            position = 2;
+        } else {
+            return None;
+        }
+    }
+    Some(canonical.join("-"))
+}
+
+/// The language part of a tag, so `en-GB` and `en-US` count as the same language
+pub fn primary_language(tag: &str) -> &str {
+    tag.split('-').next().unwrap_or(tag)
+}
+
+pub fn parse_languages(values: &[String], errors: &mut Vec<FieldError>) -> Vec<String> {
+    if !check_list_length("languages", values.len(), errors) {
+        return Vec::new();
+    }
+
+    let mut languages = Vec::new();
+    for value in values {
+        match normalize_language_tag(value) {
+            Some(tag) if !languages.contains(&tag) => languages.push(tag),
+            Some(_) => {}
+            None => errors.push(FieldError::new(
+                "languages",
+                "invalid_language",
+                format!("\"{}\" is not a language code such as \"en\" or \"pt-BR\"", value),
+            )),
+        }
+    }
+    languages
+}
+
+/// Map entries to taxonomy slugs. Only active tags can be picked, except ones already on the
+/// profile (`current`), so retiring a tag doesn't make existing profiles impossible to save.
+pub async fn resolve_tags(
+    pool: &PgPool,
+    kind: ProfileTagKind,
+    field: &str,
+    values: &[String],
+    current: &[String],
+    errors: &mut Vec<FieldError>,
+) -> Result<Vec<String>, sqlx::Error> {
+    if !check_list_length(field, values.len(), errors) {
+        return Ok(Vec::new());
+    }
+
+    let mut slugs: Vec<String> = values.iter().map(|value| slugify(value)).collect();
+    slugs.sort();
+    slugs.dedup();
+
+    let known: Vec<String> = sqlx::query_scalar(
+        "SELECT slug FROM profile_tags WHERE kind = $1 AND slug = ANY($2) AND (active OR slug = ANY($3))",
+    )
+    .bind(kind)
+    .bind(&slugs)
+    .bind(current)
+    .fetch_all(pool)
+    .await?;
+
+    let unknown: Vec<&str> = slugs
+        .iter()
+        .filter(|slug| !known.contains(slug))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        errors.push(FieldError::new(
+            field,
+            "unknown_tag",
+            format!("Not an available option: {}", unknown.join(", ")),
+        ));
+    }
+    Ok(slugs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_tags_are_canonicalised() {
+        assert_eq!(normalize_language_tag("EN").as_deref(), Some("en"));
+        assert_eq!(normalize_language_tag(" en_gb ").as_deref(), Some("en-GB"));
+        assert_eq!(normalize_language_tag("zh-hant-tw").as_deref(), Some("zh-Hant-TW"));
+        assert_eq!(normalize_language_tag("es-419").as_deref(), Some("es-419"));
+        assert_eq!(normalize_language_tag("sr-LATN").as_deref(), Some("sr-Latn"));
+        assert_eq!(normalize_language_tag("de-ch-1996").as_deref(), Some("de-CH-1996"));
+        assert_eq!(normalize_language_tag("sl-Rozaj-Biske").as_deref(), Some("sl-rozaj-biske"));
+    }
+
+    #[test]
+    fn language_subtags_must_come_in_order() {
+        // script, then region, then variants
+        assert_eq!(normalize_language_tag("en-GB-Latn"), None);
+        assert_eq!(normalize_language_tag("en-Latn-Cyrl"), None);
+        assert_eq!(normalize_language_tag("en-GB-US"), None);
+        assert_eq!(normalize_language_tag("de-1996-CH"), None);
+    }
+
+    #[test]
+    fn malformed_language_tags_are_rejected() {
+        for tag in ["", "e", "english", "1en", "en-", "en--GB", "en-x-private", "en-u-ca-gregory", "en-GB-oed", "fr-12"] {
+            assert_eq!(normalize_language_tag(tag), None, "{:?}", tag);
+        }
+    }
+
+    #[test]
+    fn primary_language_ignores_region() {
+        assert_eq!(primary_language("en-GB"), "en");
+        assert_eq!(primary_language("pt"), "pt");
+    }
+
+    #[test]
+    fn duplicate_languages_are_dropped_and_bad_ones_reported() {
+        let mut errors = Vec::new();
+        let languages = parse_languages(&["en-gb".to_string(), "EN-GB".to_string(), "english".to_string()], &mut errors);
+        assert_eq!(languages, vec!["en-GB"]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, "invalid_language");
+    }
+}
@@ -0,0 +1,238 @@
+use crate::auth::logged_in_user_id;
+use crate::handlers::blocks::blocked_user_ids;
+use crate::middleware::role_guard::has_current_role;
+use crate::models::all_models::{FieldVisibility, Location, LocationPrecision, ProfileVisibility, UserRole};
+use actix_web::HttpRequest;
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Columns a `ProfileRow` is read from; select them `FROM users`
+pub const PROFILE_COLUMNS: &str = "user_id, username, role::TEXT AS role, avatar_url, user_profile, bio, dob, \
+                                   location, interests, experience, languages";
+
+/// Everything another user could ever see of a profile, before visibility is applied
+#[derive(sqlx::FromRow)]
+pub struct ProfileRow {
+    pub user_id: Uuid,
+    pub username: String,
+    pub role: String,
+    pub avatar_url: String,
+    pub user_profile: String,
+    pub bio: Option<String>,
+    pub dob: NaiveDate,
+    pub location: Option<Value>,
+    pub interests: Option<Vec<String>>,
+    pub experience: Option<Vec<String>>,
+    pub languages: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+pub struct LocationView {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+}
+
+/// A profile as one particular viewer is allowed to see it. Hidden fields are left out entirely.
+#[derive(Serialize)]
+pub struct ProfileView {
+    pub user_id: Uuid,
+    pub username: String,
+    pub role: String,
+    pub avatar_url: String,
+    pub user_profile: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<LocationView>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interests: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experience: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub languages: Option<Vec<String>>,
+}
+
+/// How the viewer relates to the profile's owner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Relation {
+    Owner,
+    Admin,
+    Matched,
+    Member,
+    Anonymous,
+}
+
+impl Relation {
+    pub fn can_see(self, visibility: FieldVisibility) -> bool {
+        match self {
+            Relation::Owner | Relation::Admin => true,
+            Relation::Matched => visibility != FieldVisibility::Private,
+            Relation::Member => matches!(visibility, FieldVisibility::Public | FieldVisibility::Members),
+            Relation::Anonymous => visibility == FieldVisibility::Public,
+        }
+    }
+}
+
+/// The user looking at profiles, who they have an accepted match with and who they're blocked from
+pub struct Viewer {
+    user_id: Option<Uuid>,
+    is_admin: bool,
+    matched: HashSet<Uuid>,
+    blocked: HashSet<Uuid>,
+}
+
+impl Viewer {
+    /// The viewer behind a request; requests without an access token view anonymously
+    pub async fn from_request(pool: &PgPool, req: &HttpRequest) -> Result<Viewer, sqlx::Error> {
+        let Some(user_id) = logged_in_user_id(req) else {
+            return Ok(Viewer { user_id: None, is_admin: false, matched: HashSet::new(), blocked: HashSet::new() });
+        };
+        // By the current role, not the token's, so a demoted admin loses access straight away
+        let is_admin = has_current_role(pool, user_id, UserRole::Admin).await?;
+
+        let matched: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT CASE WHEN member_id = $1 THEN sponsor_id ELSE member_id END
+             FROM matching_requests
+             WHERE status = 'accepted' AND (member_id = $1 OR sponsor_id = $1)
+               AND member_id IS NOT NULL AND sponsor_id IS NOT NULL",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        // Admins moderate, so blocks never hide anyone from them
+        let blocked = if is_admin { HashSet::new() } else { blocked_user_ids(pool, user_id).await? };
+
+        Ok(Viewer { user_id: Some(user_id), is_admin, matched: matched.into_iter().collect(), blocked })
+    }
+
+    /// Blocked pairs can't see each other's profiles at all
+    pub fn is_blocked_from(&self, user_id: Uuid) -> bool {
+        self.blocked.contains(&user_id)
+    }
+
+    pub fn relation_to(&self, user_id: Uuid) -> Relation {
+        if self.user_id == Some(user_id) {
+            Relation::Owner
+        } else if self.is_admin {
+            Relation::Admin
+        } else if self.matched.contains(&user_id) {
+            Relation::Matched
+        } else if self.user_id.is_some() {
+            Relation::Member
+        } else {
+            Relation::Anonymous
+        }
+    }
+}
+
+/// Cut a stored location down to the owner's chosen precision; the owner always sees all of it
+fn location_view(location: Value, precision: LocationPrecision, relation: Relation) -> Option<LocationView> {
+    let location: Location = serde_json::from_value(location).ok()?;
+    let precision = if relation == Relation::Owner { LocationPrecision::Exact } else { precision };
+
+    let view = match precision {
+        LocationPrecision::Exact => LocationView {
+            latitude: Some(location.latitude),
+            longitude: Some(location.longitude),
+            city: location.city,
+            country: location.country,
+        },
+        LocationPrecision::City => LocationView {
+            latitude: None,
+            longitude: None,
+            city: location.city,
+            country: location.country,
+        },
+        LocationPrecision::Country => LocationView {
+            latitude: None,
+            longitude: None,
+            city: None,
+            country: location.country,
+        },
+    };
+    if view.latitude.is_none() && view.city.is_none() && view.country.is_none() {
+        return None;
+    }
+    Some(view)
+}
+
+/// The single place profile visibility is enforced. Every endpoint that shows one user's profile
+/// to another must build its response through this.
+pub fn profile_view(row: ProfileRow, visibility: &ProfileVisibility, relation: Relation) -> ProfileView {
+    let show = |field: FieldVisibility| relation.can_see(field);
+
+    ProfileView {
+        user_id: row.user_id,
+        username: row.username,
+        role: row.role,
+        avatar_url: row.avatar_url,
+        user_profile: row.user_profile,
+        bio: row.bio.filter(|_| show(visibility.bio)),
+        age: if show(visibility.age) { Utc::now().date_naive().years_since(row.dob) } else { None },
+        location: row
+            .location
+            .filter(|_| show(visibility.location))
+            .and_then(|location| location_view(location, visibility.location_precision, relation)),
+        interests: row.interests.filter(|_| show(visibility.interests)),
+        experience: row.experience.filter(|_| show(visibility.experience)),
+        languages: row.languages.filter(|_| show(visibility.languages)),
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct VisibilityRow {
+    user_id: Uuid,
+    #[sqlx(flatten)]
+    settings: ProfileVisibility,
+}
+
+/// Visibility settings for each user; users who never changed theirs are missing from the map
+pub async fn load_visibility(
+    pool: &PgPool,
+    user_ids: &[Uuid],
+) -> Result<HashMap<Uuid, ProfileVisibility>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, VisibilityRow>(
+        "SELECT user_id, bio, interests, experience, languages, location, location_precision, age
+         FROM profile_visibility WHERE user_id = ANY($1)",
+    )
+    .bind(user_ids)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.user_id, row.settings)).collect())
+}
+
+/// Apply each owner's settings to a batch of profiles, keeping their order. Profiles the viewer is
+/// blocked from are dropped.
+pub async fn profile_views(
+    pool: &PgPool,
+    viewer: &Viewer,
+    rows: Vec<ProfileRow>,
+) -> Result<Vec<ProfileView>, sqlx::Error> {
+    let rows: Vec<ProfileRow> = rows.into_iter().filter(|row| !viewer.is_blocked_from(row.user_id)).collect();
+    let ids: Vec<Uuid> = rows.iter().map(|row| row.user_id).collect();
+    let settings = load_visibility(pool, &ids).await?;
+    let defaults = ProfileVisibility::default();
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let visibility = settings.get(&row.user_id).unwrap_or(&defaults);
+            let relation = viewer.relation_to(row.user_id);
+            profile_view(row, visibility, relation)
+        })
+        .collect())
+}
@@ -0,0 +1,443 @@
+use crate::handlers::profile_fields::slugify;
+use crate::handlers::validation::FieldError;
+use crate::models::all_models::{Question, QuestionKind, Questionnaire};
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+use std::collections::HashSet;
+
+pub const MAX_QUESTIONS: usize = 50;
+const MAX_OPTIONS: usize = 30;
+const MAX_PROMPT_LENGTH: usize = 500;
+const MAX_TEXT_ANSWER: usize = 5000;
+const MAX_YEARS: f64 = 100.0;
+
+const QUESTIONNAIRE_COLUMNS: &str = "version, questions, created_by, created_at, active";
+
+/// Tidy a new version's questions: keys and option values become slugs, and every key and option
+/// must be unique. Choice questions need at least two options; other kinds take none.
+pub fn validate_questions(questions: Vec<Question>, errors: &mut Vec<FieldError>) -> Vec<Question> {
+    if questions.is_empty() || questions.len() > MAX_QUESTIONS {
+        errors.push(FieldError::new(
+            "questions",
+            "invalid_count",
+            format!("Give between 1 and {} questions", MAX_QUESTIONS),
+        ));
+        return Vec::new();
+    }
+
+    let mut keys = HashSet::new();
+    let mut cleaned = Vec::with_capacity(questions.len());
+    for (i, mut question) in questions.into_iter().enumerate() {
+        let field = format!("questions[{}]", i);
+
+        question.key = slugify(&question.key);
+        if question.key.is_empty() {
+            errors.push(FieldError::new(&format!("{}.key", field), "required", "A key is required"));
+        } else if !keys.insert(question.key.clone()) {
+            errors.push(FieldError::new(
+                &format!("{}.key", field),
+                "duplicate",
+                format!("\"{}\" is used by another question", question.key),
+            ));
+        }
+
+        question.prompt = question.prompt.trim().to_string();
+        if question.prompt.is_empty() || question.prompt.chars().count() > MAX_PROMPT_LENGTH {
+            errors.push(FieldError::new(
+                &format!("{}.prompt", field),
+                "invalid_length",
+                format!("A prompt of 1 to {} characters is required", MAX_PROMPT_LENGTH),
+            ));
+        }
+
+        let is_choice = matches!(question.kind, QuestionKind::SingleChoice | QuestionKind::MultiChoice);
+        if !is_choice && !question.options.is_empty() {
+            errors.push(FieldError::new(
+                &format!("{}.options", field),
+                "unexpected",
+                "Only choice questions have options",
+            ));
+        }
+        if is_choice && !(2..=MAX_OPTIONS).contains(&question.options.len()) {
+            errors.push(FieldError::new(
+                &format!("{}.options", field),
+                "invalid_count",
+                format!("Choice questions need between 2 and {} options", MAX_OPTIONS),
+            ));
+        }
+
+        let mut values = HashSet::new();
+        for (j, option) in question.options.iter_mut().enumerate() {
+            option.label = option.label.trim().to_string();
+            option.value = slugify(if option.value.trim().is_empty() { &option.label } else { &option.value });
+            if option.label.is_empty() || option.value.is_empty() {
+                errors.push(FieldError::new(
+                    &format!("{}.options[{}]", field, j),
+                    "required",
+                    "Options need a label",
+                ));
+            } else if !values.insert(option.value.clone()) {
+                errors.push(FieldError::new(
+                    &format!("{}.options[{}]", field, j),
+                    "duplicate",
+                    format!("\"{}\" is listed twice", option.value),
+                ));
+            }
+        }
+
+        cleaned.push(question);
+    }
+    cleaned
+}
+
+/// The version applicants currently answer, if any
+pub async fn active_questionnaire(pool: &PgPool) -> Result<Option<Questionnaire>, sqlx::Error> {
+    let query = format!("SELECT {} FROM sponsor_questionnaires WHERE active", QUESTIONNAIRE_COLUMNS);
+    sqlx::query_as::<_, Questionnaire>(&query).fetch_optional(pool).await
+}
+
+pub async fn list_questionnaires(pool: &PgPool) -> Result<Vec<Questionnaire>, sqlx::Error> {
+    let query = format!("SELECT {} FROM sponsor_questionnaires ORDER BY version DESC", QUESTIONNAIRE_COLUMNS);
+    sqlx::query_as::<_, Questionnaire>(&query).fetch_all(pool).await
+}
+
+fn answer_error(key: &str, code: &'static str, message: impl Into<String>) -> FieldError {
+    FieldError::new(&format!("answers.{}", key), code, message)
+}
+
+fn option_values(question: &Question) -> Vec<&str> {
+    question.options.iter().map(|option| option.value.as_str()).collect()
+}
+
+/// Check answers against a questionnaire and return them as they are stored. Blank answers count
+/// as not given, so they're dropped, or reported if the question is required.
+pub fn validate_answers(questions: &[Question], answers: &Map<String, Value>, errors: &mut Vec<FieldError>) -> Value {
+    for key in answers.keys() {
+        if !questions.iter().any(|question| &question.key == key) {
+            errors.push(answer_error(key, "unknown_question", "There is no such question"));
+        }
+    }
+
+    let mut stored = Map::new();
+    for question in questions {
+        let key = question.key.as_str();
+        let answer = match answers.get(key) {
+            Some(Value::String(text)) if text.trim().is_empty() => None,
+            Some(Value::Array(items)) if items.is_empty() => None,
+            Some(Value::Null) | None => None,
+            Some(answer) => Some(answer),
+        };
+        let Some(answer) = answer else {
+            if question.required {
+                errors.push(answer_error(key, "required", "This question must be answered"));
+            }
+            continue;
+        };
+
+        let value = match (question.kind, answer) {
+            (QuestionKind::Text, Value::String(text)) => {
+                if text.trim().chars().count() > MAX_TEXT_ANSWER {
+                    errors.push(answer_error(
+                        key,
+                        "too_long",
+                        format!("Must be at most {} characters", MAX_TEXT_ANSWER),
+                    ));
+                }
+                Some(Value::from(text.trim()))
+            }
+            (QuestionKind::SingleChoice, Value::String(choice)) => {
+                if !option_values(question).contains(&choice.as_str()) {
+                    errors.push(answer_error(key, "invalid_choice", format!("\"{}\" is not an option", choice)));
+                }
+                Some(answer.clone())
+            }
+            (QuestionKind::MultiChoice, Value::Array(items)) => {
+                let picked: Vec<&str> = items.iter().filter_map(Value::as_str).collect();
+                if picked.len() != items.len() {
+                    errors.push(answer_error(key, "invalid_type", "Expected a list of options"));
+                }
+                for choice in picked.iter().filter(|choice| !option_values(question).contains(*choice)) {
+                    errors.push(answer_error(key, "invalid_choice", format!("\"{}\" is not an option", choice)));
+                }
+                // Kept in the questionnaire's order, once each
+                let chosen: Vec<&str> =
+                    option_values(question).into_iter().filter(|value| picked.contains(value)).collect();
+                Some(Value::from(chosen))
+            }
+            (QuestionKind::Years, Value::Number(years)) => {
+                if !years.as_f64().is_some_and(|years| (0.0..=MAX_YEARS).contains(&years)) {
+                    errors.push(answer_error(
+                        key,
+                        "out_of_range",
+                        format!("Must be between 0 and {} years", MAX_YEARS),
+                    ));
+                }
+                Some(answer.clone())
+            }
+            (QuestionKind::Attestation, Value::Bool(agreed)) => {
+                if question.required && !agreed {
+                    errors.push(answer_error(key, "must_attest", "This statement must be confirmed"));
+                }
+                Some(answer.clone())
+            }
+            (kind, _) => {
+                let expected = match kind {
+                    QuestionKind::Text | QuestionKind::SingleChoice => "text",
+                    QuestionKind::MultiChoice => "a list of options",
+                    QuestionKind::Years => "a number",
+                    QuestionKind::Attestation => "true or false",
+                };
+                errors.push(answer_error(key, "invalid_type", format!("Expected {}", expected)));
+                None
+            }
+        };
+        if let Some(value) = value {
+            stored.insert(key.to_string(), value);
+        }
+    }
+    Value::Object(stored)
+}
+
+/// Parsed `answers` filter for the review queue, split into what SQL can check by JSONB
+/// containment and numeric comparisons on years
+#[derive(Debug, Default)]
+pub struct AnswerFilters {
+    pub contains: Option<Value>,
+    pub number_keys: Vec<String>,
+    pub number_ops: Vec<&'static str>,
+    pub number_values: Vec<f64>,
+}
+
+const FILTER_OPS: [&str; 5] = [">=", "<=", ">", "<", "="];
+
+/// Parse filters such as `years-in-recovery>=2,program=aa,agrees-to-code=yes`. Keys are looked up
+/// in `questions`, newest version first, so filters keep working for keys a later version dropped.
+pub fn parse_answer_filters(raw: &str, questions: &[Question]) -> Result<AnswerFilters, Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let mut filters = AnswerFilters::default();
+    let mut contains = Map::new();
+
+    for clause in raw.split(',').map(str::trim).filter(|clause| !clause.is_empty()) {
+        let parsed = FILTER_OPS
+            .iter()
+            .find_map(|op| clause.split_once(op).map(|(key, value)| (slugify(key), *op, value.trim())));
+        let Some((key, op, value)) = parsed else {
+            errors.push(FieldError::new(
+                "answers",
+                "invalid_filter",
+                format!("\"{}\" should look like key=value or key>=number", clause),
+            ));
+            continue;
+        };
+        let Some(question) = questions.iter().find(|question| question.key == key) else {
+            errors.push(FieldError::new("answers", "unknown_question", format!("No question has the key \"{}\"", key)));
+            continue;
+        };
+
+        match question.kind {
+            QuestionKind::Years => match value.parse::<f64>() {
+                Ok(years) if years.is_finite() => {
+                    filters.number_keys.push(key);
+                    filters.number_ops.push(op);
+                    filters.number_values.push(years);
+                }
+                _ => errors.push(FieldError::new(
+                    "answers",
+                    "invalid_filter",
+                    format!("\"{}\" needs a number", key),
+                )),
+            },
+            _ if op != "=" => errors.push(FieldError::new(
+                "answers",
+                "invalid_filter",
+                format!("Only years questions can be compared; use {}=value", key),
+            )),
+            QuestionKind::Text => errors.push(FieldError::new(
+                "answers",
+                "invalid_filter",
+                format!("\"{}\" is a free-text question and can't be filtered on", key),
+            )),
+            QuestionKind::Attestation => match value.to_lowercase().as_str() {
+                "true" | "yes" => {
+                    contains.insert(key, Value::Bool(true));
+                }
+                "false" | "no" => {
+                    contains.insert(key, Value::Bool(false));
+                }
+                _ => errors.push(FieldError::new("answers", "invalid_filter", format!("\"{}\" takes yes or no", key))),
+            },
+            QuestionKind::SingleChoice | QuestionKind::MultiChoice => {
+                let choice = slugify(value);
+                if !option_values(question).contains(&choice.as_str()) {
+                    errors.push(FieldError::new(
+                        "answers",
+                        "invalid_choice",
+                        format!("\"{}\" is not an option of \"{}\"", value, key),
+                    ));
+                } else if question.kind == QuestionKind::MultiChoice {
+                    // Several values for one multi-choice question must all have been picked
+                    let picked = contains.entry(key).or_insert_with(|| Value::Array(Vec::new()));
+                    if let Value::Array(items) = picked {
+                        items.push(Value::from(choice));
+                    }
+                } else {
+                    contains.insert(key, Value::from(choice));
+                }
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    filters.contains = (!contains.is_empty()).then_some(Value::Object(contains));
+    Ok(filters)
+}
+
+/// Questions from every version, newest first, one per key
+pub fn known_questions(questionnaires: Vec<Questionnaire>) -> Vec<Question> {
+    let mut seen = HashSet::new();
+    questionnaires
+        .into_iter()
+        .flat_map(|questionnaire| questionnaire.questions.0)
+        .filter(|question| seen.insert(question.key.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn questions() -> Vec<Question> {
+        serde_json::from_value(json!([
+            {"key": "story", "prompt": "Tell us about yourself", "type": "text", "required": true},
+            {"key": "program", "prompt": "Main program", "type": "single_choice", "options": [
+                {"value": "aa", "label": "AA"}, {"value": "na", "label": "NA"}
+            ]},
+            {"key": "fellowships", "prompt": "Fellowships", "type": "multi_choice", "options": [
+                {"value": "aa", "label": "AA"}, {"value": "na", "label": "NA"}, {"value": "ca", "label": "CA"}
+            ]},
+            {"key": "years-in-recovery", "prompt": "Years in recovery", "type": "years", "required": true},
+            {"key": "agrees-to-code", "prompt": "I agree to the code", "type": "attestation", "required": true}
+        ]))
+        .unwrap()
+    }
+
+    fn validate(answers: Value) -> (Value, Vec<(String, &'static str)>) {
+        let mut errors = Vec::new();
+        let stored = validate_answers(&questions(), answers.as_object().unwrap(), &mut errors);
+        let mut codes: Vec<_> = errors.into_iter().map(|e| (e.field, e.code)).collect();
+        codes.sort();
+        (stored, codes)
+    }
+
+    fn filter_codes(raw: &str) -> Vec<&'static str> {
+        parse_answer_filters(raw, &questions()).unwrap_err().into_iter().map(|e| e.code).collect()
+    }
+
+    #[test]
+    fn answers_are_tidied_for_storage() {
+        let (stored, codes) = validate(json!({
+            "story": "  Five years sober  ",
+            "fellowships": ["ca", "aa", "aa"],
+            "years-in-recovery": 5.5,
+            "agrees-to-code": true
+        }));
+        assert!(codes.is_empty(), "{:?}", codes);
+        assert_eq!(
+            stored,
+            json!({
+                "story": "Five years sober",
+                "fellowships": ["aa", "ca"],
+                "years-in-recovery": 5.5,
+                "agrees-to-code": true
+            })
+        );
+    }
+
+    #[test]
+    fn blank_answers_count_as_not_given() {
+        let (stored, codes) = validate(json!({
+            "story": "   ",
+            "program": null,
+            "fellowships": [],
+            "years-in-recovery": 0,
+            "agrees-to-code": true
+        }));
+        assert_eq!(codes, vec![("answers.story".to_string(), "required")]);
+        assert_eq!(stored, json!({"years-in-recovery": 0, "agrees-to-code": true}));
+
+        let (stored, codes) = validate(json!({}));
+        assert_eq!(stored, json!({}));
+        assert_eq!(codes.len(), 3);
+        assert!(codes.iter().all(|(_, code)| *code == "required"));
+    }
+
+    #[test]
+    fn wrong_answers_are_reported_per_question() {
+        let (_, codes) = validate(json!({
+            "story": 5,
+            "program": "xyz",
+            "fellowships": ["aa", 3],
+            "years-in-recovery": 150,
+            "agrees-to-code": false,
+            "favourite-colour": "blue"
+        }));
+        let expected = [
+            ("answers.agrees-to-code", "must_attest"),
+            ("answers.favourite-colour", "unknown_question"),
+            ("answers.fellowships", "invalid_type"),
+            ("answers.program", "invalid_choice"),
+            ("answers.story", "invalid_type"),
+            ("answers.years-in-recovery", "out_of_range"),
+        ];
+        let expected: Vec<_> = expected.iter().map(|(field, code)| (field.to_string(), *code)).collect();
+        assert_eq!(codes, expected);
+    }
+
+    #[test]
+    fn filters_split_into_containment_and_year_comparisons() {
+        let filters = parse_answer_filters(
+            "years-in-recovery>=2, Program=AA, agrees-to-code=yes, fellowships=aa, fellowships=ca",
+            &questions(),
+        )
+        .unwrap();
+        assert_eq!(filters.number_keys, vec!["years-in-recovery"]);
+        assert_eq!(filters.number_ops, vec![">="]);
+        assert_eq!(filters.number_values, vec![2.0]);
+        assert_eq!(
+            filters.contains,
+            Some(json!({"program": "aa", "agrees-to-code": true, "fellowships": ["aa", "ca"]}))
+        );
+    }
+
+    #[test]
+    fn year_filters_accept_every_comparison() {
+        let filters = parse_answer_filters(
+            "years-in-recovery<=10,years-in-recovery>1.5,years-in-recovery<8,Years In Recovery=3",
+            &questions(),
+        )
+        .unwrap();
+        assert_eq!(filters.number_ops, vec!["<=", ">", "<", "="]);
+        assert_eq!(filters.number_values, vec![10.0, 1.5, 8.0, 3.0]);
+        assert!(filters.number_keys.iter().all(|key| key == "years-in-recovery"));
+        assert_eq!(filters.contains, None);
+
+        let filters = parse_answer_filters(" , ", &questions()).unwrap();
+        assert!(filters.contains.is_none() && filters.number_keys.is_empty());
+    }
+
+    #[test]
+    fn bad_filters_are_all_reported() {
+        assert_eq!(filter_codes("nonsense"), vec!["invalid_filter"]);
+        assert_eq!(filter_codes("shoe-size>=9"), vec!["unknown_question"]);
+        assert_eq!(filter_codes("years-in-recovery>=two"), vec!["invalid_filter"]);
+        assert_eq!(filter_codes("years-in-recovery>=inf"), vec!["invalid_filter"]);
+        assert_eq!(filter_codes("program>=2"), vec!["invalid_filter"]);
+        assert_eq!(filter_codes("story=sober"), vec!["invalid_filter"]);
+        assert_eq!(filter_codes("agrees-to-code=maybe"), vec!["invalid_filter"]);
+        assert_eq!(filter_codes("program=xyz,fellowships=aa"), vec!["invalid_choice"]);
+        assert_eq!(filter_codes("nonsense,shoe-size=9"), vec!["invalid_filter", "unknown_question"]);
+    }
+}
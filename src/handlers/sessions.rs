@@ -0,0 +1,203 @@
+use crate::auth::Claims;
+use crate::config::env_or;
+use crate::handlers::login_throttle::client_ip;
+use crate::handlers::ws::disconnect_sessions;
+use actix_web::HttpRequest;
+use actix_web::http::header;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Lifetime of a session (and of its refresh token) in days
+pub fn refresh_token_ttl_days() -> i32 {
+    env_or("REFRESH_TOKEN_TTL_DAYS", 30)
+}
+
+/// Generate a random opaque token (used for refresh and CSRF tokens)
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Refresh tokens are only ever stored as SHA-256 hashes
+pub fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Device details recorded with a session so users can recognise it in their sessions list
+pub struct SessionClient {
+    pub user_agent: Option<String>,
+    pub ip_address: String,
+}
+
+impl SessionClient {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        SessionClient {
+            user_agent: req
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|h| h.to_str().ok())
+                .map(|ua| ua.chars().take(512).collect()),
+            ip_address: client_ip(req),
+        }
+    }
+}
+
+/// Start a new session for the user, returning its id and refresh token
+pub async fn create_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    client: &SessionClient,
+) -> Result<(Uuid, String), sqlx::Error> {
+    let refresh_token = generate_token();
+
+    let session_id: Uuid = sqlx::query_scalar(
+        "INSERT INTO sessions (user_id, refresh_token_hash, expires_at, user_agent, ip_address, last_seen_at)
+         VALUES ($1, $2, NOW() + make_interval(days => $3), $4, $5, NOW())
+         RETURNING session_id",
+    )
+    .bind(user_id)
+    .bind(hash_refresh_token(&refresh_token))
+    .bind(refresh_token_ttl_days())
+    .bind(&client.user_agent)
+    .bind(&client.ip_address)
+    .fetch_one(pool)
+    .await?;
+
+    Ok((session_id, refresh_token))
+}
+
+/// Outcome of presenting a refresh token
+pub enum RefreshOutcome {
+    /// The token was valid and has been replaced by a new one
+    Rotated {
+        session_id: Uuid,
+        user_id: Uuid,
+        refresh_token: String,
+    },
+    /// An already-rotated token was presented again, so the session has been revoked
+    Reused,
+    Invalid,
+}
+
+/// Swap a refresh token for a new one. Presenting a token that was already rotated revokes the
+/// whole session, since that means someone else holds a copy of it.
+pub async fn rotate_refresh_token(pool: &PgPool, token: &str) -> Result<RefreshOutcome, sqlx::Error> {
+    let presented_hash = hash_refresh_token(token);
+    let new_token = generate_token();
+
+    let rotated: Option<(Uuid, Uuid)> = sqlx::query_as(
+        "UPDATE sessions
+         SET previous_refresh_token_hash = refresh_token_hash, refresh_token_hash = $2, last_seen_at = NOW()
+         WHERE refresh_token_hash = $1 AND revoked_at IS NULL AND expires_at > NOW()
+         RETURNING session_id, user_id",
+    )
+    .bind(&presented_hash)
+    .bind(hash_refresh_token(&new_token))
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some((session_id, user_id)) = rotated {
+        return Ok(RefreshOutcome::Rotated {
+            session_id,
+            user_id,
+            refresh_token: new_token,
+        });
+    }
+
+    let reused: Option<(Uuid, Uuid)> = sqlx::query_as(
+        "UPDATE sessions SET revoked_at = NOW()
+         WHERE previous_refresh_token_hash = $1 AND revoked_at IS NULL
+         RETURNING session_id, user_id",
+    )
+    .bind(&presented_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    match reused {
+        Some((session_id, user_id)) => {
+            disconnect_sessions(&user_id, &[session_id]);
+            Ok(RefreshOutcome::Reused)
+        }
+        None => Ok(RefreshOutcome::Invalid),
+    }
+}
+
+/// Revoke a single session belonging to the user
+pub async fn revoke_session(pool: &PgPool, user_id: Uuid, session_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE sessions SET revoked_at = NOW()
+         WHERE session_id = $1 AND user_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    disconnect_sessions(&user_id, &[session_id]);
+    Ok(result.rows_affected() > 0)
+}
+
+/// Revoke every session the user has
+pub async fn revoke_all_sessions(pool: &PgPool, user_id: Uuid) -> Result<(), sqlx::Error> {
+    revoke_other_sessions(pool, user_id, None).await
+}
+
+/// Revoke every session except `keep`, e.g. the one that just changed the user's credentials
+pub async fn revoke_other_sessions(pool: &PgPool, user_id: Uuid, keep: Option<Uuid>) -> Result<(), sqlx::Error> {
+    let revoked: Vec<Uuid> = sqlx::query_scalar(
+        "UPDATE sessions SET revoked_at = NOW()
+         WHERE user_id = $1 AND revoked_at IS NULL AND session_id IS DISTINCT FROM $2
+         RETURNING session_id",
+    )
+    .bind(user_id)
+    .bind(keep)
+    .fetch_all(pool)
+    .await?;
+
+    disconnect_sessions(&user_id, &revoked);
+    Ok(())
+}
+
+/// Record activity on a session, at most once a minute
+pub async fn touch_session(pool: &PgPool, session_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE sessions SET last_seen_at = NOW()
+         WHERE session_id = $1 AND (last_seen_at IS NULL OR last_seen_at < NOW() - INTERVAL '1 minute')",
+    )
+    .bind(session_id)
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// Check that the session behind `claims` is still live and its user isn't banned
+pub async fn is_session_active(pool: &PgPool, claims: &Claims) -> Result<bool, sqlx::Error> {
+    let (user_id, session_id) = match (
+        Uuid::parse_str(&claims.id),
+        claims.sid.as_deref().map(Uuid::parse_str),
+    ) {
+        (Ok(user_id), Some(Ok(session_id))) => (user_id, session_id),
+        _ => return Ok(false),
+    };
+
+    let active: Option<bool> = sqlx::query_scalar(
+        "SELECT TRUE FROM sessions s
+         JOIN users u ON u.user_id = s.user_id
+         WHERE s.session_id = $1 AND s.user_id = $2
+           AND s.revoked_at IS NULL AND s.expires_at > NOW()
+           AND (u.banned_until IS NULL OR u.banned_until <= NOW())",
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    if active.is_none() {
+        return Ok(false);
+    }
+    touch_session(pool, session_id).await?;
+    Ok(true)
+}
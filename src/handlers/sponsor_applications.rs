@@ -0,0 +1,125 @@
+use crate::config::env_or;
+use crate::handlers::validation::FieldError;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use serde_json::Value;
+use similar::{ChangeTag, TextDiff};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+/// Free text must be longer than this unless at least one question was answered; the table checks it too
+pub const MIN_APPLICATION_INFO_CHARS: usize = 20;
+
+pub fn check_application_info(application_info: &str, answers: Option<&Value>) -> Option<FieldError> {
+    let answered = answers.and_then(Value::as_object).is_some_and(|answers| !answers.is_empty());
+    (!answered && application_info.chars().count() <= MIN_APPLICATION_INFO_CHARS).then(|| {
+        FieldError::new(
+            "application_info",
+            "too_short",
+            format!(
+                "Tell us why you'd like to be a sponsor in more than {} characters",
+                MIN_APPLICATION_INFO_CHARS
+            ),
+        )
+    })
+}
+
+/// Days after a rejection before the applicant can apply again, from `SPONSOR_REAPPLY_COOLDOWN_DAYS`
+pub fn reapply_cooldown_days() -> i32 {
+    env_or("SPONSOR_REAPPLY_COOLDOWN_DAYS", 30)
+}
+
+/// When the user may next apply, if their latest rejection is still within the cooldown.
+/// Withdrawn applications count, so withdrawing doesn't skip the wait.
+pub async fn reapply_blocked_until(pool: &PgPool, user_id: Uuid) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT until FROM (
+             SELECT MAX(reviewed_at) + make_interval(days => $2) AS until
+             FROM sponsor_applications WHERE user_id = $1 AND status = 'rejected'
+         ) latest
+         WHERE until > NOW()",
+    )
+    .bind(user_id)
+    .bind(reapply_cooldown_days())
+    .fetch_optional(pool)
+    .await
+}
+
+/// Snapshot the application's current text and answers as its next version; call with the
+/// application row locked so version numbers don't race
+pub async fn record_version(conn: &mut PgConnection, application_id: Uuid) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar(
+        "INSERT INTO sponsor_application_versions
+             (application_id, version, application_info, questionnaire_version, answers)
+         SELECT a.application_id,
+                COALESCE((SELECT MAX(version) FROM sponsor_application_versions WHERE application_id = $1), 0) + 1,
+                a.application_info, a.questionnaire_version, a.answers
+         FROM sponsor_applications a WHERE a.application_id = $1
+         RETURNING version",
+    )
+    .bind(application_id)
+    .fetch_one(conn)
+    .await
+}
+
+/// A version as one text for diffing: the free text, then one `key: answer` line per answer
+pub fn version_text(application_info: &str, answers: Option<&Value>) -> String {
+    let mut text = format!("{}\n", application_info.trim_end());
+    if let Some(Value::Object(answers)) = answers
+        && !answers.is_empty()
+    {
+        text.push('\n');
+        for (key, answer) in answers {
+            let rendered = match answer {
+                Value::String(answer) => answer.clone(),
+                Value::Array(items) => {
+                    items.iter().map(|item| item.as_str().unwrap_or_default()).collect::<Vec<_>>().join(", ")
+                }
+                other => other.to_string(),
+            };
+            text.push_str(&format!("{}: {}\n", key, rendered));
+        }
+    }
+    text
+}
+
+/// One run of unchanged, removed or added words
+#[derive(Debug, Serialize)]
+pub struct DiffChange {
+    pub op: &'static str,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApplicationDiff {
+    pub from_version: i32,
+    pub to_version: i32,
+    /// Line-based unified diff
+    pub unified: String,
+    /// Word-level changes, for highlighting inline
+    pub changes: Vec<DiffChange>,
+}
+
+pub fn diff_versions(from_version: i32, old: &str, to_version: i32, new: &str) -> ApplicationDiff {
+    let unified = TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("version {}", from_version), &format!("version {}", to_version))
+        .to_string();
+
+    let words = TextDiff::from_words(old, new);
+    let mut changes: Vec<DiffChange> = Vec::new();
+    for change in words.iter_all_changes() {
+        let op = match change.tag() {
+            ChangeTag::Equal => "equal",
+            ChangeTag::Delete => "delete",
+            ChangeTag::Insert => "insert",
+        };
+        match changes.last_mut() {
+            Some(last) if last.op == op => last.text.push_str(change.value()),
+            _ => changes.push(DiffChange { op, text: change.value().to_string() }),
+        }
+    }
+
+    ApplicationDiff { from_version, to_version, unified, changes }
+}
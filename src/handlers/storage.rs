@@ -0,0 +1,163 @@
+use crate::config::env_flag;
+use async_trait::async_trait;
+use s3::creds::Credentials;
+use s3::error::S3Error;
+use s3::{Bucket, Region};
+use std::env;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// A stored object and its content type
+pub struct StoredObject {
+    pub bytes: Vec<u8>,
+    pub content_type: String,
+}
+
+/// Pluggable blob storage for user uploads. Keys are `/`-separated relative paths.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<(), String>;
+    /// `Ok(None)` when nothing is stored under `key`
+    async fn get(&self, key: &str) -> Result<Option<StoredObject>, String>;
+    /// Deleting a missing key is not an error
+    async fn delete(&self, key: &str) -> Result<(), String>;
+}
+
+/// Stores objects as files under a root directory
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    /// Keys come from our own code, but never let one climb out of the root
+    fn path_for(&self, key: &str) -> Result<PathBuf, String> {
+        let relative = Path::new(key);
+        if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+            return Err(format!("Invalid storage key: {}", key));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    fn content_type_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".content-type");
+        PathBuf::from(name)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<(), String> {
+        let path = self.path_for(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(|e| e.to_string())?;
+        }
+        tokio::fs::write(&path, bytes).await.map_err(|e| e.to_string())?;
+        tokio::fs::write(Self::content_type_path(&path), content_type)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<StoredObject>, String> {
+        let path = self.path_for(key)?;
+        let bytes = match tokio::fs::read(&path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+        let content_type = tokio::fs::read_to_string(Self::content_type_path(&path))
+            .await
+            .unwrap_or_else(|_| "application/octet-stream".to_string());
+        Ok(Some(StoredObject { bytes, content_type }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        let path = self.path_for(key)?;
+        for file in [Self::content_type_path(&path), path] {
+            match tokio::fs::remove_file(&file).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stores objects in an S3-compatible bucket (AWS, or MinIO in development)
+pub struct S3Storage {
+    bucket: Box<Bucket>,
+}
+
+impl S3Storage {
+    /// Reads `S3_BUCKET`, `S3_REGION`, `S3_ENDPOINT`, `S3_ACCESS_KEY`, `S3_SECRET_KEY` and
+    /// `S3_PATH_STYLE` (needed for MinIO)
+    pub fn from_env() -> Result<Self, String> {
+        let name = env::var("S3_BUCKET").map_err(|_| "S3_BUCKET must be set".to_string())?;
+        let region_name = env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+        let region = match env::var("S3_ENDPOINT") {
+            Ok(endpoint) => Region::Custom { region: region_name, endpoint },
+            Err(_) => region_name.parse().map_err(|e| format!("Invalid S3_REGION: {}", e))?,
+        };
+        let credentials = Credentials::new(
+            env::var("S3_ACCESS_KEY").ok().as_deref(),
+            env::var("S3_SECRET_KEY").ok().as_deref(),
+            None,
+            None,
+            None,
+        )
+        .map_err(|e| e.to_string())?;
+
+        let mut bucket = Bucket::new(&name, region, credentials).map_err(|e| e.to_string())?;
+        if env_flag("S3_PATH_STYLE", false) {
+            bucket = bucket.with_path_style();
+        }
+        Ok(S3Storage { bucket })
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Storage {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<(), String> {
+        self.bucket
+            .put_object_with_content_type(key, &bytes, content_type)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<StoredObject>, String> {
+        match self.bucket.get_object(key).await {
+            Ok(response) => {
+                let content_type = response
+                    .headers()
+                    .get("content-type")
+                    .cloned()
+                    .unwrap_or_else(|| "application/octet-stream".to_string());
+                Ok(Some(StoredObject { bytes: response.to_vec(), content_type }))
+            }
+            Err(S3Error::HttpFailWithBody(404, _)) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        match self.bucket.delete_object(key).await {
+            Ok(_) | Err(S3Error::HttpFailWithBody(404, _)) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+/// Builds the backend selected by `STORAGE_BACKEND` (`s3` or `local`, rooted at `STORAGE_DIR`)
+pub fn storage_from_env() -> Arc<dyn StorageBackend> {
+    match env::var("STORAGE_BACKEND").unwrap_or_default().as_str() {
+        "s3" => match S3Storage::from_env() {
+            Ok(storage) => Arc::new(storage),
+            Err(e) => panic!("Failed to configure S3 storage: {}", e),
+        },
+        _ => Arc::new(LocalStorage {
+            root: PathBuf::from(env::var("STORAGE_DIR").unwrap_or_else(|_| "./storage".to_string())),
+        }),
+    }
+}
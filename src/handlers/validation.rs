@@ -0,0 +1,31 @@
+use actix_web::HttpResponse;
+use serde::Serialize;
+
+/// A single problem with one field of a request body
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    /// Stable machine-readable code, e.g. `too_short`
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: &'static str, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.to_string(),
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ValidationErrors<'a> {
+    errors: &'a [FieldError],
+}
+
+/// 400 response with a body of `{"errors": [{"field", "code", "message"}]}`
+pub fn validation_error_response(errors: &[FieldError]) -> HttpResponse {
+    HttpResponse::BadRequest().json(ValidationErrors { errors })
+}
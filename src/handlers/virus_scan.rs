@@ -0,0 +1,142 @@
+use crate::config::env_or;
+use async_trait::async_trait;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// What a scanner made of an upload
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanVerdict {
+    Clean,
+    /// Carries the signature name the scanner reported
+    Infected(String),
+    /// No scanner is configured
+    NotScanned,
+}
+
+/// Pluggable malware scanning for user uploads. An `Err` means the file couldn't be checked;
+/// callers should refuse the upload rather than store it unchecked.
+#[async_trait]
+pub trait VirusScanner: Send + Sync {
+    /// Recorded against stored files, e.g. `clamav`
+    fn name(&self) -> &'static str;
+    async fn scan(&self, bytes: &[u8]) -> Result<ScanVerdict, String>;
+}
+
+/// Accepts everything without looking; for development or when scanning happens elsewhere
+pub struct NoopScanner;
+
+#[async_trait]
+impl VirusScanner for NoopScanner {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    async fn scan(&self, _bytes: &[u8]) -> Result<ScanVerdict, String> {
+        Ok(ScanVerdict::NotScanned)
+    }
+}
+
+/// Streams files to a clamd daemon over TCP with the INSTREAM command
+pub struct ClamAvScanner {
+    address: String,
+    timeout: Duration,
+}
+
+/// clamd's default StreamMaxLength is 25 MB; chunks just have to stay under it
+const CLAMAV_CHUNK_BYTES: usize = 64 * 1024;
+
+impl ClamAvScanner {
+    /// Reads `CLAMAV_ADDRESS` (default `127.0.0.1:3310`) and `CLAMAV_TIMEOUT_SECONDS`
+    pub fn from_env() -> Self {
+        ClamAvScanner {
+            address: env::var("CLAMAV_ADDRESS").unwrap_or_else(|_| "127.0.0.1:3310".to_string()),
+            timeout: Duration::from_secs(env_or("CLAMAV_TIMEOUT_SECONDS", 30)),
+        }
+    }
+
+    async fn instream(&self, bytes: &[u8]) -> Result<String, String> {
+        let mut stream = TcpStream::connect(&self.address)
+            .await
+            .map_err(|e| format!("Failed to connect to clamd at {}: {}", self.address, e))?;
+
+        stream.write_all(b"zINSTREAM\0").await.map_err(|e| e.to_string())?;
+        for chunk in bytes.chunks(CLAMAV_CHUNK_BYTES) {
+            stream.write_all(&(chunk.len() as u32).to_be_bytes()).await.map_err(|e| e.to_string())?;
+            stream.write_all(chunk).await.map_err(|e| e.to_string())?;
+        }
+        // A zero-length chunk ends the stream
+        stream.write_all(&0u32.to_be_bytes()).await.map_err(|e| e.to_string())?;
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await.map_err(|e| e.to_string())?;
+        Ok(String::from_utf8_lossy(&reply).trim_end_matches('\0').trim().to_string())
+    }
+}
+
+/// Turn clamd's reply (`stream: OK`, `stream: <signature> FOUND` or `... ERROR`) into a verdict
+fn parse_clamd_reply(reply: &str) -> Result<ScanVerdict, String> {
+    let result = reply.strip_prefix("stream:").map(str::trim).unwrap_or(reply);
+    if result == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = result.strip_suffix("FOUND") {
+        Ok(ScanVerdict::Infected(signature.trim().to_string()))
+    } else {
+        Err(format!("clamd could not scan the file: {}", reply))
+    }
+}
+
+#[async_trait]
+impl VirusScanner for ClamAvScanner {
+    fn name(&self) -> &'static str {
+        "clamav"
+    }
+
+    async fn scan(&self, bytes: &[u8]) -> Result<ScanVerdict, String> {
+        let reply = tokio::time::timeout(self.timeout, self.instream(bytes))
+            .await
+            .map_err(|_| "Timed out waiting for clamd".to_string())??;
+        parse_clamd_reply(&reply)
+    }
+}
+
+/// Builds the scanner selected by `VIRUS_SCANNER` (`clamav` or `none`)
+pub fn scanner_from_env() -> Arc<dyn VirusScanner> {
+    match env::var("VIRUS_SCANNER").unwrap_or_default().as_str() {
+        "clamav" => Arc::new(ClamAvScanner::from_env()),
+        _ => Arc::new(NoopScanner),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_streams_pass() {
+        assert_eq!(parse_clamd_reply("stream: OK"), Ok(ScanVerdict::Clean));
+        assert_eq!(parse_clamd_reply("OK"), Ok(ScanVerdict::Clean));
+    }
+
+    #[test]
+    fn infected_streams_carry_the_signature() {
+        assert_eq!(
+            parse_clamd_reply("stream: Win.Test.EICAR_HDB-1 FOUND"),
+            Ok(ScanVerdict::Infected("Win.Test.EICAR_HDB-1".to_string()))
+        );
+    }
+
+    #[test]
+    fn errors_and_unexpected_replies_are_failures() {
+        for reply in [
+            "INSTREAM size limit exceeded. ERROR",
+            "stream: Can't allocate memory ERROR",
+            "stream: OK?",
+            "",
+        ] {
+            assert!(parse_clamd_reply(reply).is_err(), "{:?}", reply);
+        }
+    }
+}
@@ -1,25 +1,41 @@
-use crate::auth::Claims;
-use actix::{Actor, AsyncContext, StreamHandler};
+use crate::auth::{Claims, logged_in_user_id};
+use crate::handlers::blocks::blocked_user_ids;
+use crate::middleware::auth_middleware::AuthMiddleware;
+use crate::middleware::role_guard::RequireRole;
+use crate::models::all_models::UserRole;
+use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
 use actix_web::{Error, HttpMessage, HttpRequest, HttpResponse, Responder, web};
 use actix_web_actors::ws;
 use futures_channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use futures_util::StreamExt; 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 use uuid::Uuid;
 use lazy_static::lazy_static;
 
 /// WebSocket session struct
 struct WebSocketSession {
+    connection_id: Uuid,
     user_id: Uuid,
+    /// Login session the socket was opened with, so revoking it can close the socket
+    session_id: Option<Uuid>,
     role: String,
     tx: Option<UnboundedSender<ws::Message>>, 
 }
 
-/// Shared map of active WebSocket connections.
-type UserSocketMap = Arc<Mutex<HashMap<Uuid, (String, UnboundedSender<ws::Message>)>>>;
+/// One open connection
+struct SocketEntry {
+    session_id: Option<Uuid>,
+    role: String,
+    tx: UnboundedSender<ws::Message>,
+}
+
+/// Shared map of active WebSocket connections: user id -> connection id -> connection.
+/// A user can be connected from several devices at once.
+type UserSocketMap = Arc<Mutex<HashMap<Uuid, HashMap<Uuid, SocketEntry>>>>;
 lazy_static! {
     static ref USER_SOCKETS: UserSocketMap = Arc::new(Mutex::new(HashMap::new()));
 }
@@ -32,20 +48,37 @@ impl Actor for WebSocketSession {
         self.tx = Some(tx.clone());
         {
             let mut sockets = USER_SOCKETS.lock().unwrap();
-            sockets.insert(self.user_id, (self.role.clone(), tx));
+            sockets.entry(self.user_id).or_default().insert(
+                self.connection_id,
+                SocketEntry { session_id: self.session_id, role: self.role.clone(), tx },
+            );
         }
         ctx.add_stream(rx.map(|m| Ok(m)));
     }
     fn stopped(&mut self, _: &mut Self::Context) {
         println!("WebSocket disconnected: {}", self.user_id);
-        USER_SOCKETS.lock().unwrap().remove(&self.user_id);
+        let mut sockets = USER_SOCKETS.lock().unwrap();
+        if let Some(connections) = sockets.get_mut(&self.user_id) {
+            connections.remove(&self.connection_id);
+            if connections.is_empty() {
+                sockets.remove(&self.user_id);
+            }
+        }
     }
 }
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
-        if let Ok(ws::Message::Text(text)) = msg {
-            println!("Received from {}: {}", self.user_id, text);
-            ctx.text(format!("Echo: {}", text));
+        match msg {
+            Ok(ws::Message::Text(text)) => {
+                println!("Received from {}: {}", self.user_id, text);
+                ctx.text(format!("Echo: {}", text));
+            }
+            // Sent by the client, or queued by `disconnect_sessions` when the session is revoked
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => {}
         }
     }
 }
@@ -57,37 +90,91 @@ async fn ws_connect(req: HttpRequest, stream: web::Payload) -> Result<HttpRespon
             Ok(id) => id,
             Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid user ID format")),
         };
-        let role = claims.role.clone();
-        let session = WebSocketSession { user_id, role, tx: None };
+        // An impersonator would receive the member's live messages
+        if claims.impersonator.is_some() {
+            return Ok(HttpResponse::Forbidden().body("Live updates are not available while impersonating"));
+        }
+        let session = WebSocketSession {
+            connection_id: Uuid::new_v4(),
+            user_id,
+            session_id: claims.sid.as_deref().and_then(|sid| Uuid::parse_str(sid).ok()),
+            role: claims.role.clone(),
+            tx: None,
+        };
         ws::start(session, &req, stream)
     } else {
         Ok(HttpResponse::Unauthorized().body("Authentication required"))
     }
 }
 
-///  Send a payload to a single user
-pub async fn send_to_user(user_id: &Uuid, payload: Value) {
+/// Close the user's sockets that were opened with any of the given (now revoked) sessions
+pub fn disconnect_sessions(user_id: &Uuid, session_ids: &[Uuid]) {
+    let sockets = USER_SOCKETS.lock().unwrap();
+    if let Some(connections) = sockets.get(user_id) {
+        for entry in connections.values() {
+            if entry.session_id.is_some_and(|sid| session_ids.contains(&sid)) {
+                let reason = ws::CloseReason {
+                    code: ws::CloseCode::Policy,
+                    description: Some("Session revoked".to_string()),
+                };
+                let _ = entry.tx.unbounded_send(ws::Message::Close(Some(reason)));
+            }
+        }
+    }
+}
+
+/// Users a delivery from `sender` must skip because of a block; system messages (`None`) skip
+/// nobody. `None` when the blocks can't be loaded, so nothing is delivered rather than risk
+/// reaching someone who blocked the sender.
+async fn blocked_recipients(pool: &PgPool, sender: Option<Uuid>) -> Option<HashSet<Uuid>> {
+    let Some(sender) = sender else {
+        return Some(HashSet::new());
+    };
+    match blocked_user_ids(pool, sender).await {
+        Ok(blocked) => Some(blocked),
+        Err(e) => {
+            eprintln!("Failed to load blocks, dropping delivery: {:?}", e);
+            None
+        }
+    }
+}
+
+///  Send a payload to a single user, unless they and `sender` have blocked each other
+pub async fn send_to_user(pool: &PgPool, sender: Option<Uuid>, user_id: &Uuid, payload: Value) {
     let msg_str = match serde_json::to_string(&payload) {
         Ok(s) => s,
         Err(_) => return,
     };
+    let Some(blocked) = blocked_recipients(pool, sender).await else {
+        return;
+    };
+    if blocked.contains(user_id) {
+        return;
+    }
     let sockets = USER_SOCKETS.lock().unwrap();
-    if let Some((_, tx)) = sockets.get(user_id) {
-      
-        let _ = tx.unbounded_send(ws::Message::Text(msg_str.clone().into()));
+    for entry in sockets.get(user_id).into_iter().flat_map(|c| c.values()) {
+        let _ = entry.tx.unbounded_send(ws::Message::Text(msg_str.clone().into()));
     }
 }
 
-///  Send a payload to all users with a specific role
-pub async fn send_to_role(role: &str, payload: Value) {
+///  Send a payload to all users with a specific role, skipping anyone in a block with `sender`
+pub async fn send_to_role(pool: &PgPool, sender: Option<Uuid>, role: &str, payload: Value) {
     let msg_str = match serde_json::to_string(&payload) {
         Ok(s) => s,
         Err(_) => return,
     };
+    let Some(blocked) = blocked_recipients(pool, sender).await else {
+        return;
+    };
     let sockets = USER_SOCKETS.lock().unwrap();
-    for (_, (user_role, tx)) in sockets.iter() {
-        if user_role == role {
-            let _ = tx.unbounded_send(ws::Message::Text(msg_str.clone().into()));
+    for (user_id, connections) in sockets.iter() {
+        if blocked.contains(user_id) {
+            continue;
+        }
+        for entry in connections.values() {
+            if entry.role == role {
+                let _ = entry.tx.unbounded_send(ws::Message::Text(msg_str.clone().into()));
+            }
         }
     }
 }
@@ -104,15 +191,23 @@ struct SendToRoleRequest {
 }
 
 /// Handler to send a payload to a single user
-async fn send_to_user_handler(req: web::Json<SendToUserRequest>) -> impl Responder {
+async fn send_to_user_handler(
+    pool: web::Data<PgPool>,
+    http_req: HttpRequest,
+    req: web::Json<SendToUserRequest>,
+) -> impl Responder {
     // Call the existing function (payload is cloned so that you can reuse it)
-    send_to_user(&req.user_id, req.payload.clone()).await;
+    send_to_user(pool.get_ref(), logged_in_user_id(&http_req), &req.user_id, req.payload.clone()).await;
     HttpResponse::Ok().json("Message sent to specified user")
 }
 
 /// Handler to send a payload to all users with a specific role
-async fn send_to_role_handler(req: web::Json<SendToRoleRequest>) -> impl Responder {
-    send_to_role(&req.role, req.payload.clone()).await;
+async fn send_to_role_handler(
+    pool: web::Data<PgPool>,
+    http_req: HttpRequest,
+    req: web::Json<SendToRoleRequest>,
+) -> impl Responder {
+    send_to_role(pool.get_ref(), logged_in_user_id(&http_req), &req.role, req.payload.clone()).await;
     HttpResponse::Ok().json("Message sent to users with specified role")
 }
 ///  Send a payload to multiple users
@@ -121,15 +216,22 @@ struct SendToUsersRequest {
     user_ids: Vec<Uuid>,
     payload: Value, 
 }
-async fn send_to_users_handler(payload: web::Json<SendToUsersRequest>) -> impl Responder {
+async fn send_to_users_handler(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<SendToUsersRequest>,
+) -> impl Responder {
     let msg_str = match serde_json::to_string(&payload.payload) {
         Ok(s) => s,
         Err(_) => return HttpResponse::BadRequest().body("Invalid JSON payload"),
     };
+    let Some(blocked) = blocked_recipients(pool.get_ref(), logged_in_user_id(&req)).await else {
+        return HttpResponse::InternalServerError().body("Failed to send payload");
+    };
     let sockets = USER_SOCKETS.lock().unwrap();
-    for user_id in &payload.user_ids {
-        if let Some((_, tx)) = sockets.get(user_id) {
-            let _ = tx.unbounded_send(ws::Message::Text(msg_str.clone().into()));
+    for user_id in payload.user_ids.iter().filter(|id| !blocked.contains(id)) {
+        for entry in sockets.get(user_id).into_iter().flat_map(|c| c.values()) {
+            let _ = entry.tx.unbounded_send(ws::Message::Text(msg_str.clone().into()));
         }
     }
     HttpResponse::Ok().json("Custom payload sent to specified users")
@@ -140,14 +242,22 @@ async fn send_to_users_handler(payload: web::Json<SendToUsersRequest>) -> impl R
 struct SendToAllRequest {
     payload: Value, 
 }
-async fn send_to_all_handler(payload: web::Json<SendToAllRequest>) -> impl Responder {
+async fn send_to_all_handler(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<SendToAllRequest>,
+) -> impl Responder {
     let msg_str = match serde_json::to_string(&payload.payload) {
         Ok(s) => s,
         Err(_) => return HttpResponse::BadRequest().body("Invalid JSON payload"),
     };
+    let Some(blocked) = blocked_recipients(pool.get_ref(), logged_in_user_id(&req)).await else {
+        return HttpResponse::InternalServerError().body("Failed to send payload");
+    };
     let sockets = USER_SOCKETS.lock().unwrap();
-    for (_, (_, tx)) in sockets.iter() {
-        let _ = tx.unbounded_send(ws::Message::Text(msg_str.clone().into()));
+    let recipients = sockets.iter().filter(|(user_id, _)| !blocked.contains(user_id));
+    for entry in recipients.flat_map(|(_, c)| c.values()) {
+        let _ = entry.tx.unbounded_send(ws::Message::Text(msg_str.clone().into()));
     }
     HttpResponse::Ok().json("Custom payload broadcasted to all users")
 }
@@ -156,11 +266,28 @@ async fn send_to_all_handler(payload: web::Json<SendToAllRequest>) -> impl Respo
 pub fn init_ws_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/ws")
+            .wrap(AuthMiddleware)
             .route("/connect", web::get().to(ws_connect))
-            .route("send-user", web::post().to(send_to_user_handler))
-            .route("/send-users", web::post().to(send_to_users_handler))
-            .route("/send-role", web::post().to(send_to_role_handler))
-            .route("/send-all", web::post().to(send_to_all_handler))
+            .service(
+                web::resource("/send-user")
+                    .wrap(RequireRole(UserRole::Admin))
+                    .route(web::post().to(send_to_user_handler)),
+            )
+            .service(
+                web::resource("/send-users")
+                    .wrap(RequireRole(UserRole::Admin))
+                    .route(web::post().to(send_to_users_handler)),
+            )
+            .service(
+                web::resource("/send-role")
+                    .wrap(RequireRole(UserRole::Admin))
+                    .route(web::post().to(send_to_role_handler)),
+            )
+            .service(
+                web::resource("/send-all")
+                    .wrap(RequireRole(UserRole::Admin))
+                    .route(web::post().to(send_to_all_handler)),
+            )
             
     );
 }
@@ -1,4 +1,5 @@
 mod auth;
+mod config;
 mod db;
 mod middleware;
 mod handlers;
@@ -6,25 +7,63 @@ mod models;
 mod routes;
 
 use actix_web::{App, HttpServer, web};
+use handlers::account_deletion::spawn_purge_job;
+use handlers::mailer::{Mailer, mailer_from_env};
+use handlers::storage::{StorageBackend, storage_from_env};
+use handlers::virus_scan::{VirusScanner, scanner_from_env};
 use handlers::ws::init_ws_routes;
 use middleware::auth_middleware::AuthMiddleware;
-use routes::{user_auth::config_user_auth_routes,user_info::config_user_info_routes,sponsor::config_sponsor_routes,matching::config_matching_routes};
+use routes::{avatars::config_public_avatar_routes,sponsor_documents::config_public_reference_routes,jwks::config_jwks_routes,user_auth::config_user_auth_routes,user_info::config_user_info_routes,sponsor::config_sponsor_routes,matching::config_matching_routes,admin::config_admin_routes};
 use std::io::Result as IoResult;
+use std::sync::Arc;
+use std::time::Duration;
 use crate::db::connect_db;
 
+fn run_argon2_benchmark(target: Duration) -> IoResult<()> {
+    match handlers::password::benchmark_params(target) {
+        Ok((params, elapsed)) => {
+            println!("Target {:?}, measured {:?} per hash", target, elapsed);
+            println!("ARGON2_MEMORY_KIB={}", params.m_cost());
+            println!("ARGON2_ITERATIONS={}", params.t_cost());
+            println!("ARGON2_PARALLELISM={}", params.p_cost());
+            Ok(())
+        }
+        Err(e) => Err(std::io::Error::other(e.to_string())),
+    }
+}
+
 #[actix_web::main]
 async fn main() -> IoResult<()> {
     dotenvy::dotenv().ok();
+
+    // `serv argon2-bench [target_ms]` suggests Argon2 costs for this machine instead of serving
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("argon2-bench") {
+        let target_ms: u64 = args.get(2).and_then(|v| v.parse().ok()).unwrap_or(250);
+        return run_argon2_benchmark(Duration::from_millis(target_ms));
+    }
+
+    lazy_static::initialize(&handlers::jwt_keys::KEY_SET);
     let pool = connect_db().await;
+    let mailer: Arc<dyn Mailer> = mailer_from_env();
+    let storage: Arc<dyn StorageBackend> = storage_from_env();
+    let scanner: Arc<dyn VirusScanner> = scanner_from_env();
+    spawn_purge_job(pool.clone(), storage.clone());
 
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(mailer.clone()))
+            .app_data(web::Data::from(storage.clone()))
+            .app_data(web::Data::from(scanner.clone()))
+            .configure(config_jwks_routes)
             .service(
                 web::scope("/api")
                     .service(
                         web::scope("/public")
                             .configure(config_user_auth_routes)
+                            .configure(config_public_avatar_routes)
+                            .configure(config_public_reference_routes)
                             
                     )
                     .service(
@@ -33,8 +72,8 @@ async fn main() -> IoResult<()> {
                             .configure(config_user_info_routes)
                             .configure(config_sponsor_routes)
                             .configure(config_matching_routes)
+                            .configure(config_admin_routes)
                     )
-                .wrap(AuthMiddleware)
                 .configure(init_ws_routes)
             )
     })
@@ -1,16 +1,30 @@
-use crate::auth::validate_jwt;
+use crate::auth::{Claims, TokenType, validate_jwt};
+use crate::handlers::auth_cookies::{ACCESS_COOKIE, COOKIE_CONFIG, csrf_check_passes};
+use crate::handlers::impersonation::{ImpersonationContext, is_read_method, record_impersonated_request};
+use crate::handlers::sessions::is_session_active;
 use actix_web::{
     Error, HttpMessage,
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
     http::header,
+    web,
 };
 use futures_util::future::{Ready, ok};
+use sqlx::PgPool;
 use std::{
     future::Future,
     pin::Pin,
     rc::Rc,
     task::{Context, Poll},
 };
+use uuid::Uuid;
+
+fn impersonation_context(claims: &Claims, impersonator: &str) -> Option<ImpersonationContext> {
+    Some(ImpersonationContext {
+        session_id: Uuid::parse_str(claims.sid.as_deref()?).ok()?,
+        impersonator_id: Uuid::parse_str(impersonator).ok()?,
+        user_id: Uuid::parse_str(&claims.id).ok()?,
+    })
+}
 
 /// Middleware for JWT authentication
 pub struct AuthMiddleware;
@@ -59,7 +73,16 @@ where
             .and_then(|h| h.to_str().ok())
             .map(String::from);
 
+        // In cookie mode the access token may come from an HTTP-only cookie instead
+        let cookie_token = if COOKIE_CONFIG.enabled {
+            req.cookie(ACCESS_COOKIE).map(|c| c.value().to_string())
+        } else {
+            None
+        };
+        let csrf_ok = csrf_check_passes(req.request());
+
         let service = self.service.clone();
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
 
         // Move cloned auth_header into the async block
         Box::pin(async move {
@@ -76,15 +99,83 @@ where
                         }
                     }
                 }
-                None => return Err(actix_web::error::ErrorUnauthorized("Missing auth header")),
+                None => match cookie_token {
+                    Some(token) => {
+                        if !csrf_ok {
+                            return Err(actix_web::error::ErrorForbidden(
+                                "Missing or invalid CSRF token",
+                            ));
+                        }
+                        token
+                    }
+                    None => return Err(actix_web::error::ErrorUnauthorized("Missing auth header")),
+                },
             };
 
             // Validate the JWT token
             match validate_jwt(&token) {
+                Ok(claims) if claims.token_type != TokenType::Access => Err(
+                    actix_web::error::ErrorUnauthorized("Two-factor verification required"),
+                ),
                 Ok(claims) => {
-                    // Store claims in request extensions
+                    // Reject tokens whose session was revoked or whose user has been banned
+                    let pool = match pool {
+                        Some(pool) => pool,
+                        None => {
+                            return Err(actix_web::error::ErrorInternalServerError(
+                                "Database unavailable",
+                            ));
+                        }
+                    };
+                    match is_session_active(pool.get_ref(), &claims).await {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            return Err(actix_web::error::ErrorUnauthorized(
+                                "Session has been revoked",
+                            ));
+                        }
+                        Err(e) => {
+                            eprintln!("Session lookup error: {:?}", e);
+                            return Err(actix_web::error::ErrorInternalServerError(
+                                "Failed to validate session",
+                            ));
+                        }
+                    }
+
+                    let impersonation = match claims.impersonator.as_deref() {
+                        Some(impersonator) => match impersonation_context(&claims, impersonator) {
+                            Some(context) => Some(context),
+                            None => return Err(actix_web::error::ErrorUnauthorized("Invalid token")),
+                        },
+                        None => None,
+                    };
+
+                    let context = match impersonation {
+                        Some(context) => context,
+                        None => {
+                            // Store claims in request extensions
+                            req.extensions_mut().insert(claims);
+                            return service.call(req).await;
+                        }
+                    };
+
+                    // Impersonated requests are audited, including the ones we refuse. Sensitive
+                    // resources refuse them with `DenyImpersonation`, which shows up here as a 403.
+                    let method = req.method().to_string();
+                    let path = req.path().to_string();
+                    if claims.read_only && !is_read_method(req.method()) {
+                        record_impersonated_request(pool.get_ref(), &context, &method, &path, 403).await;
+                        return Err(actix_web::error::ErrorForbidden("Impersonation session is read-only"));
+                    }
+
                     req.extensions_mut().insert(claims);
-                    service.call(req).await
+                    let result = service.call(req).await;
+                    let status = match &result {
+                        Ok(res) => res.status(),
+                        Err(e) => e.as_response_error().status_code(),
+                    };
+                    record_impersonated_request(pool.get_ref(), &context, &method, &path, status.as_u16()).await;
+                    result
                 }
                 Err(_) => Err(actix_web::error::ErrorUnauthorized("Invalid token")),
             }
@@ -0,0 +1,69 @@
+use crate::auth::Claims;
+use actix_web::{
+    Error, HttpMessage,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+};
+use futures_util::future::{Ready, ok};
+use std::{
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+/// Refuses impersonation tokens, even writable ones. Wrap the resources that change credentials,
+/// sessions or personal data with it. Must be mounted inside `AuthMiddleware`, which audits the 403.
+pub struct DenyImpersonation;
+
+impl<S, B> Transform<S, ServiceRequest> for DenyImpersonation
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = DenyImpersonationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(DenyImpersonationMiddleware { service: Rc::new(service) })
+    }
+}
+
+pub struct DenyImpersonationMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for DenyImpersonationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let impersonated = req
+            .extensions()
+            .get::<Claims>()
+            .map(|claims| claims.impersonator.is_some());
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            match impersonated {
+                Some(false) => service.call(req).await,
+                Some(true) => Err(actix_web::error::ErrorForbidden(
+                    "This action is not allowed while impersonating",
+                )),
+                None => Err(actix_web::error::ErrorUnauthorized("Authentication required")),
+            }
+        })
+    }
+}
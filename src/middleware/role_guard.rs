@@ -0,0 +1,127 @@
+use crate::auth::logged_in_user_id;
+use crate::models::all_models::UserRole;
+use actix_web::{
+    Error,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    web,
+};
+use chrono::{NaiveDateTime, Utc};
+use futures_util::future::{Ready, ok};
+use sqlx::PgPool;
+use std::{
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+use uuid::Uuid;
+
+/// The user's role and ban as stored now; the role claim in a token may be stale
+async fn current_role(pool: &PgPool, user_id: Uuid) -> Result<Option<(UserRole, Option<NaiveDateTime>)>, sqlx::Error> {
+    sqlx::query_as("SELECT role, banned_until FROM users WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Whether the user currently holds `role` and isn't banned, the same check `RequireRole` makes,
+/// for handlers that only treat some roles differently
+pub async fn has_current_role(pool: &PgPool, user_id: Uuid, role: UserRole) -> Result<bool, sqlx::Error> {
+    Ok(current_role(pool, user_id).await?.is_some_and(|(current, banned_until)| {
+        current == role && banned_until.is_none_or(|until| until <= Utc::now().naive_utc())
+    }))
+}
+
+/// Only lets through users whose current role is the wrapped `UserRole`. Must be mounted inside
+/// `AuthMiddleware`.
+pub struct RequireRole(pub UserRole);
+
+/// Only lets through users whose current role is one of the listed roles.
+/// Must be mounted inside `AuthMiddleware`.
+pub struct RequireAnyRole(pub &'static [UserRole]);
+
+impl<S, B> Transform<S, ServiceRequest> for RequireRole
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RoleGuardMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RoleGuardMiddleware {
+            service: Rc::new(service),
+            roles: Rc::new(vec![self.0]),
+        })
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireAnyRole
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RoleGuardMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RoleGuardMiddleware {
+            service: Rc::new(service),
+            roles: Rc::new(self.0.to_vec()),
+        })
+    }
+}
+
+pub struct RoleGuardMiddleware<S> {
+    service: Rc<S>,
+    roles: Rc<Vec<UserRole>>,
+}
+
+impl<S, B> Service<ServiceRequest> for RoleGuardMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let user_id = logged_in_user_id(req.request());
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
+        let service = self.service.clone();
+        let roles = self.roles.clone();
+
+        Box::pin(async move {
+            let (user_id, pool) = match (user_id, pool) {
+                (Some(user_id), Some(pool)) => (user_id, pool),
+                _ => return Err(actix_web::error::ErrorUnauthorized("Authentication required")),
+            };
+
+            match current_role(pool.get_ref(), user_id).await {
+                Ok(Some((_, Some(banned_until)))) if banned_until > Utc::now().naive_utc() => {
+                    Err(actix_web::error::ErrorForbidden("Your account is currently banned."))
+                }
+                Ok(Some((role, _))) if roles.contains(&role) => service.call(req).await,
+                Ok(Some(_)) => Err(actix_web::error::ErrorForbidden("Insufficient permissions")),
+                Ok(None) => Err(actix_web::error::ErrorUnauthorized("User not found")),
+                Err(e) => {
+                    eprintln!("Role lookup error: {:?}", e);
+                    Err(actix_web::error::ErrorInternalServerError("Failed to check permissions"))
+                }
+            }
+        })
+    }
+}
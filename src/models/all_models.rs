@@ -1,5 +1,6 @@
 use chrono::{NaiveDate, NaiveDateTime};
 use serde::{Serialize, Deserialize};
+use sqlx::types::Json;
 use sqlx::{Decode, FromRow};
 use uuid::Uuid;
 use serde_json::Value;
@@ -7,7 +8,7 @@ use strum_macros::{EnumString,Display};
 
 
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type,Display,EnumString,PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type,Display,EnumString,PartialEq)]
 //  USER & AUTHENTICATION STRUCTS
 #[sqlx(type_name = "user_role", rename_all = "lowercase")] 
 pub enum UserRole {
@@ -35,20 +36,22 @@ pub struct User {
     pub location: Option<Value>, 
     pub interests: Option<Vec<String>>,
     pub experience: Option<Vec<String>>,
-    pub available_days: Option<Vec<String>>,
     pub languages: Option<Vec<String>>,
-    pub privacy:bool,
+    pub timezone: String,
 }
 
 
 //  SPONSOR APPLICATION
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type ,PartialEq,Display,EnumString)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type ,PartialEq,Display,EnumString)]
 #[sqlx(type_name = "application_status", rename_all = "lowercase")] 
 pub enum ApplicationStatus {
     Pending,
     Approved,
     Rejected,
+    /// Sent back to the applicant with questions; goes back to pending when they update it
+    #[sqlx(rename = "needs_info")]
+    NeedsInfo,
 }
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct SponsorApplication {
@@ -59,11 +62,119 @@ pub struct SponsorApplication {
     pub reviewed_by: Option<Uuid>, 
     pub admin_comments: Option<String>, 
     pub created_at: NaiveDateTime, 
+    pub reviewed_at: Option<NaiveDateTime>,
+    /// Set when the applicant withdrew it; admins still see it
+    pub withdrawn_at: Option<NaiveDateTime>,
+    /// The questionnaire version `answers` were given for
+    pub questionnaire_version: Option<i32>,
+    /// Answers keyed by question key
+    pub answers: Option<Value>,
+}
+
+/// One submitted text of an application; never changed once written
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct SponsorApplicationVersion {
+    pub application_id: Uuid,
+    pub version: i32,
+    pub application_info: String,
+    pub questionnaire_version: Option<i32>,
+    pub answers: Option<Value>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type, PartialEq, EnumString)]
+#[sqlx(type_name = "attachment_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case", ascii_case_insensitive)]
+pub enum AttachmentKind {
+    Certificate,
+    BackgroundCheck,
+    Other,
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "scan_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ScanStatus {
+    Clean,
+    /// Stored while no virus scanner was configured
+    Unscanned,
+}
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct SponsorApplicationAttachment {
+    pub attachment_id: Uuid,
+    pub application_id: Uuid,
+    pub kind: AttachmentKind,
+    pub file_name: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub sha256: String,
+    #[serde(skip_serializing)]
+    pub storage_key: String,
+    pub scan_status: ScanStatus,
+    pub scanned_by: Option<String>,
+    pub uploaded_at: NaiveDateTime,
+}
+/// A reference and, once they've used their link, what they said
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct SponsorReference {
+    pub reference_id: Uuid,
+    pub application_id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub relationship: String,
+    pub sent_at: NaiveDateTime,
+    pub token_expires_at: Option<NaiveDateTime>,
+    pub responded_at: Option<NaiveDateTime>,
+    pub recommends: Option<bool>,
+    pub known_for_years: Option<f32>,
+    pub comments: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+//  SPONSOR QUESTIONNAIRE
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QuestionKind {
+    Text,
+    SingleChoice,
+    MultiChoice,
+    /// A number of years, such as time in recovery
+    Years,
+    /// A yes/no statement; a required one must be answered yes
+    Attestation,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChoiceOption {
+    /// Stored in answers and used in filters
+    pub value: String,
+    pub label: String,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Question {
+    /// Stable id the answer is stored under
+    pub key: String,
+    pub prompt: String,
+    #[serde(rename = "type")]
+    pub kind: QuestionKind,
+    #[serde(default)]
+    pub required: bool,
+    /// Only for choice questions
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<ChoiceOption>,
+}
+/// A published set of questions; never edited, a change is a new version
+#[derive(Debug, Serialize, FromRow)]
+pub struct Questionnaire {
+    pub version: i32,
+    pub questions: Json<Vec<Question>>,
+    pub created_by: Option<Uuid>,
+    pub created_at: NaiveDateTime,
+    pub active: bool,
 }
 //  LOCATION STRUCT (For Matching & Users)
 
-#[derive(Debug, Serialize, Deserialize, Clone,sqlx::Type)]
-#[sqlx(type_name="jsonb")]
+/// Stored as JSONB; read it through `sqlx::types::Json<Location>`
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Location {
     pub latitude: f64,
     pub longitude: f64,
@@ -96,11 +207,12 @@ pub struct MatchingRequest {
 pub struct MatchUser {
     pub id: Uuid,
     pub dob: NaiveDate,
-    pub location: Option<Location>, 
+    pub location: Option<Json<Location>>, 
     pub interests: Option<Vec<String>>,
     pub experience: Option<Vec<String>>,
-    pub available_days: Option<Vec<String>>,
     pub languages: Option<Vec<String>>,
+    pub timezone: String,
+    pub availability: Json<Vec<AvailabilitySlot>>,
 }
 
 
@@ -255,4 +367,149 @@ pub struct Announcement {
     pub announcement_target_id:Option<Uuid>,
     pub message:String,
     pub created_at:NaiveDateTime,
-}
\ No newline at end of file
+}
+//  SECURITY EVENTS
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "security_event_type", rename_all = "lowercase")]
+pub enum SecurityEventType {
+    AccountLockout,
+    IpLockout,
+}
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct SecurityEvent {
+    pub event_id: Uuid,
+    pub event_type: SecurityEventType,
+    pub user_id: Option<Uuid>,
+    pub username: Option<String>,
+    pub ip_address: Option<String>,
+    pub details: Option<String>,
+    pub locked_until: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+//  MFA POLICY
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct MfaPolicy {
+    pub role: UserRole,
+    pub required: bool,
+    pub updated_by: Option<Uuid>,
+    pub updated_at: NaiveDateTime,
+}
+//  IMPERSONATION AUDIT LOG
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ImpersonationAuditEntry {
+    pub log_id: Uuid,
+    pub session_id: Uuid,
+    pub impersonator_id: Uuid,
+    pub user_id: Uuid,
+    pub method: String,
+    pub path: String,
+    pub status_code: i32,
+    pub created_at: NaiveDateTime,
+}
+//  PROFILE VISIBILITY
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "field_visibility", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum FieldVisibility {
+    Public,
+    Members,
+    MatchedOnly,
+    Private,
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "location_precision", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum LocationPrecision {
+    Exact,
+    City,
+    Country,
+}
+/// Who may see each optional profile field. Defaults match the `profile_visibility` columns.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ProfileVisibility {
+    pub bio: FieldVisibility,
+    pub interests: FieldVisibility,
+    pub experience: FieldVisibility,
+    pub languages: FieldVisibility,
+    pub location: FieldVisibility,
+    pub location_precision: LocationPrecision,
+    pub age: FieldVisibility,
+}
+impl Default for ProfileVisibility {
+    fn default() -> Self {
+        ProfileVisibility {
+            bio: FieldVisibility::Members,
+            interests: FieldVisibility::Members,
+            experience: FieldVisibility::Members,
+            languages: FieldVisibility::Members,
+            location: FieldVisibility::MatchedOnly,
+            location_precision: LocationPrecision::City,
+            age: FieldVisibility::MatchedOnly,
+        }
+    }
+}
+//  PERSONAL DATA EXPORTS
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "export_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct DataExport {
+    pub export_id: Uuid,
+    pub user_id: Uuid,
+    pub status: ExportStatus,
+    #[serde(skip)]
+    pub storage_key: Option<String>,
+    pub size_bytes: Option<i64>,
+    pub created_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+//  STRUCTURED PROFILE FIELDS
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type, PartialEq, Eq, Hash, PartialOrd, Ord, Display, EnumString)]
+#[sqlx(type_name = "weekday", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+#[strum(ascii_case_insensitive)]
+pub enum Weekday {
+    #[strum(to_string = "monday", serialize = "mon")]
+    Monday,
+    #[strum(to_string = "tuesday", serialize = "tue", serialize = "tues")]
+    Tuesday,
+    #[strum(to_string = "wednesday", serialize = "wed")]
+    Wednesday,
+    #[strum(to_string = "thursday", serialize = "thu", serialize = "thur", serialize = "thurs")]
+    Thursday,
+    #[strum(to_string = "friday", serialize = "fri")]
+    Friday,
+    #[strum(to_string = "saturday", serialize = "sat")]
+    Saturday,
+    #[strum(to_string = "sunday", serialize = "sun")]
+    Sunday,
+}
+/// A weekly window in the user's own timezone; minutes count from local midnight, 1440 being midnight at the end
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AvailabilitySlot {
+    pub weekday: Weekday,
+    pub start_minute: i16,
+    pub end_minute: i16,
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "profile_tag_kind", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileTagKind {
+    Interest,
+    Experience,
+}
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ProfileTag {
+    pub tag_id: Uuid,
+    pub kind: ProfileTagKind,
+    pub slug: String,
+    pub label: String,
+    pub active: bool,
+    pub created_at: NaiveDateTime,
+}
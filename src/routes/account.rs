@@ -0,0 +1,343 @@
+use crate::auth::current_session;
+use crate::config::env_or;
+use crate::handlers::email_verification::send_verification_email;
+use crate::handlers::mailer::{Mailer, OutgoingEmail};
+use crate::handlers::password::{hash_password, verify_password};
+use crate::handlers::password_policy::validate_password;
+use crate::handlers::sessions::revoke_other_sessions;
+use crate::handlers::validation::{FieldError, validation_error_response};
+use crate::middleware::impersonation_guard::DenyImpersonation;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeEmailRequest {
+    pub current_password: String,
+    pub new_email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeUsernameRequest {
+    pub new_username: String,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ChangedUsernameResponse {
+    pub username: String,
+    pub avatar_url: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct AccountCredentials {
+    pub username: String,
+    pub email: String,
+    pub password_hash: String,
+}
+
+async fn fetch_credentials(pool: &PgPool, user_id: Uuid) -> Result<AccountCredentials, sqlx::Error> {
+    sqlx::query_as::<_, AccountCredentials>(
+        "SELECT username, email, password_hash FROM users WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Sign out everywhere else after a credential change; the current session stays logged in
+async fn end_other_sessions(pool: &PgPool, user_id: Uuid, session_id: Option<Uuid>) {
+    if let Err(e) = revoke_other_sessions(pool, user_id, session_id).await {
+        eprintln!("Failed to revoke sessions: {:?}", e);
+    }
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(db) if db.is_unique_violation())
+}
+
+pub async fn change_password(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<ChangePasswordRequest>,
+) -> impl Responder {
+    let (user_id, session_id) = match current_session(&req) {
+        Some(session) => session,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let account = match fetch_credentials(pool.get_ref(), user_id).await {
+        Ok(account) => account,
+        Err(_) => return HttpResponse::NotFound().body("User not found"),
+    };
+
+    if !verify_password(&payload.current_password, &account.password_hash).unwrap_or(false) {
+        return HttpResponse::Unauthorized().body("Current password is incorrect");
+    }
+
+    let mut errors = validate_password("new_password", &payload.new_password, &account.username, &account.email).await;
+    if payload.new_password == payload.current_password {
+        errors.push(FieldError::new(
+            "new_password",
+            "unchanged",
+            "New password must be different from the current one",
+        ));
+    }
+    if !errors.is_empty() {
+        return validation_error_response(&errors);
+    }
+
+    let password_hash = match hash_password(&payload.new_password) {
+        Ok(hash) => hash,
+        Err(_) => return HttpResponse::InternalServerError().body("Failed to hash password"),
+    };
+
+    let result = sqlx::query("UPDATE users SET password_hash = $1 WHERE user_id = $2")
+        .bind(password_hash)
+        .bind(user_id)
+        .execute(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(_) => {
+            end_other_sessions(pool.get_ref(), user_id, session_id).await;
+            HttpResponse::Ok().body("Password changed")
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to change password")
+        }
+    }
+}
+
+/// Switch to a new, unverified address and send it a verification link. The old address is told
+/// about the change in case it wasn't the account owner who made it.
+pub async fn change_email(
+    pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    req: HttpRequest,
+    payload: web::Json<ChangeEmailRequest>,
+) -> impl Responder {
+    let (user_id, session_id) = match current_session(&req) {
+        Some(session) => session,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let account = match fetch_credentials(pool.get_ref(), user_id).await {
+        Ok(account) => account,
+        Err(_) => return HttpResponse::NotFound().body("User not found"),
+    };
+
+    if !verify_password(&payload.current_password, &account.password_hash).unwrap_or(false) {
+        return HttpResponse::Unauthorized().body("Current password is incorrect");
+    }
+
+    let new_email = payload.new_email.trim();
+    if new_email.find('@').is_none_or(|at| at == 0 || at == new_email.len() - 1) {
+        return validation_error_response(&[FieldError::new("new_email", "invalid", "Enter a valid email address")]);
+    }
+    if new_email.eq_ignore_ascii_case(&account.email) {
+        return validation_error_response(&[FieldError::new(
+            "new_email",
+            "unchanged",
+            "New email must be different from the current one",
+        )]);
+    }
+
+    let taken: Result<Option<bool>, sqlx::Error> =
+        sqlx::query_scalar("SELECT TRUE FROM users WHERE lower(email) = lower($1) AND user_id <> $2")
+            .bind(new_email)
+            .bind(user_id)
+            .fetch_optional(pool.get_ref())
+            .await;
+    match taken {
+        Ok(Some(_)) => {
+            return validation_error_response(&[FieldError::new("new_email", "taken", "That email is already in use")]);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to change email");
+        }
+    }
+
+    let token = Uuid::new_v4();
+    let result = sqlx::query(
+        "UPDATE users SET email = $1, email_verified = FALSE, email_verification_token = $2 WHERE user_id = $3",
+    )
+    .bind(new_email)
+    .bind(token)
+    .bind(user_id)
+    .execute(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(_) => {}
+        Err(e) if is_unique_violation(&e) => {
+            return validation_error_response(&[FieldError::new("new_email", "taken", "That email is already in use")]);
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to change email");
+        }
+    }
+
+    end_other_sessions(pool.get_ref(), user_id, session_id).await;
+
+    if let Err(e) = send_verification_email(mailer.get_ref(), new_email, &account.username, token).await {
+        eprintln!("Failed to send verification email: {}", e);
+    }
+
+    let notice = OutgoingEmail {
+        to: account.email,
+        subject: "Your email address was changed".to_string(),
+        body: format!(
+            "Hi {},\n\nThe email address on your account was just changed to {}. If you did not make this change, please reset your password and contact support immediately.",
+            account.username, new_email
+        ),
+    };
+    if let Err(e) = mailer.send(notice).await {
+        eprintln!("Failed to send email change notice: {}", e);
+    }
+
+    HttpResponse::Ok().body("Email changed. Please check your new address for a verification link.")
+}
+
+/// Rename the account. Usernames are unique regardless of case, changes are recorded in
+/// `username_history` and limited by `USERNAME_CHANGE_COOLDOWN_DAYS`.
+pub async fn change_username(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<ChangeUsernameRequest>,
+) -> impl Responder {
+    let (user_id, session_id) = match current_session(&req) {
+        Some(session) => session,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let new_username = payload.new_username.trim();
+    if new_username.chars().count() < 3 {
+        return validation_error_response(&[FieldError::new(
+            "new_username",
+            "too_short",
+            "Username must be at least 3 characters",
+        )]);
+    }
+
+    let cooldown_days: i32 = env_or("USERNAME_CHANGE_COOLDOWN_DAYS", 30);
+    let last_change: Result<Option<NaiveDateTime>, sqlx::Error> =
+        sqlx::query_scalar("SELECT MAX(changed_at) FROM username_history WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(pool.get_ref())
+            .await;
+    match last_change {
+        Ok(Some(changed_at))
+            if changed_at + chrono::Duration::days(cooldown_days as i64) > chrono::Utc::now().naive_utc() =>
+        {
+            return HttpResponse::TooManyRequests()
+                .body(format!("You can only change your username once every {} days", cooldown_days));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to change username");
+        }
+    }
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to change username");
+        }
+    };
+
+    let old_username: Result<String, sqlx::Error> =
+        sqlx::query_scalar("SELECT username FROM users WHERE user_id = $1 FOR UPDATE")
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await;
+    let old_username = match old_username {
+        Ok(name) => name,
+        Err(_) => return HttpResponse::NotFound().body("User not found"),
+    };
+    if old_username == new_username {
+        return validation_error_response(&[FieldError::new(
+            "new_username",
+            "unchanged",
+            "New username must be different from the current one",
+        )]);
+    }
+
+    let taken: Result<Option<bool>, sqlx::Error> =
+        sqlx::query_scalar("SELECT TRUE FROM users WHERE lower(username) = lower($1) AND user_id <> $2")
+            .bind(new_username)
+            .bind(user_id)
+            .fetch_optional(&mut *tx)
+            .await;
+    match taken {
+        Ok(Some(_)) => {
+            return validation_error_response(&[FieldError::new("new_username", "taken", "That username is taken")]);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to change username");
+        }
+    }
+
+    // The default avatar is drawn from the current username, so it follows without an update
+    let updated = sqlx::query_as::<_, ChangedUsernameResponse>(
+        "UPDATE users SET username = $1 WHERE user_id = $2 RETURNING username, avatar_url",
+    )
+    .bind(new_username)
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await;
+
+    let updated = match updated {
+        Ok(updated) => updated,
+        Err(e) if is_unique_violation(&e) => {
+            return validation_error_response(&[FieldError::new("new_username", "taken", "That username is taken")]);
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to change username");
+        }
+    };
+
+    let history = sqlx::query(
+        "INSERT INTO username_history (user_id, old_username, new_username) VALUES ($1, $2, $3)",
+    )
+    .bind(user_id)
+    .bind(&old_username)
+    .bind(new_username)
+    .execute(&mut *tx)
+    .await;
+
+    if let Err(e) = history {
+        eprintln!("Database error: {:?}", e);
+        return HttpResponse::InternalServerError().body("Failed to change username");
+    }
+    if let Err(e) = tx.commit().await {
+        eprintln!("Database error: {:?}", e);
+        return HttpResponse::InternalServerError().body("Failed to change username");
+    }
+
+    end_other_sessions(pool.get_ref(), user_id, session_id).await;
+    HttpResponse::Ok().json(updated)
+}
+
+/// Credential changes; mounted inside the protected `/users` scope
+pub fn config_account_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/change-password").wrap(DenyImpersonation).route(web::post().to(change_password)))
+        .service(web::resource("/change-email").wrap(DenyImpersonation).route(web::post().to(change_email)))
+        .service(web::resource("/change-username").wrap(DenyImpersonation).route(web::post().to(change_username)));
+}
@@ -0,0 +1,353 @@
+use crate::auth::{generate_impersonation_jwt, logged_in_user_id};
+use crate::handlers::impersonation::{impersonation_ttl, start_impersonation_session};
+use crate::handlers::profile_fields::slugify;
+use crate::middleware::role_guard::RequireRole;
+use crate::models::all_models::{ImpersonationAuditEntry, MfaPolicy, ProfileTag, ProfileTagKind, SecurityEvent, UserRole};
+use crate::routes::questionnaire::config_questionnaire_routes;
+use crate::routes::sponsor_review::config_sponsor_review_routes;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct PaginationQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+impl PaginationQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(50).clamp(1, 200)
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset.unwrap_or(0).max(0)
+    }
+}
+
+pub async fn list_security_events(
+    pool: web::Data<PgPool>,
+    query: web::Query<PaginationQuery>,
+) -> impl Responder {
+    let sql = "
+        SELECT event_id, event_type, user_id, username, ip_address, details, locked_until, created_at
+        FROM security_events
+        ORDER BY created_at DESC
+        LIMIT $1 OFFSET $2";
+
+    let result = sqlx::query_as::<_, SecurityEvent>(sql)
+        .bind(query.limit())
+        .bind(query.offset())
+        .fetch_all(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(events) => HttpResponse::Ok().json(events),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch security events")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaPolicyUpdate {
+    pub role: UserRole,
+    pub required: bool,
+}
+
+pub async fn get_mfa_policy(pool: web::Data<PgPool>) -> impl Responder {
+    let result = sqlx::query_as::<_, MfaPolicy>(
+        "SELECT role, required, updated_by, updated_at FROM mfa_policy ORDER BY role",
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(policy) => HttpResponse::Ok().json(policy),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch MFA policy")
+        }
+    }
+}
+
+/// Turn the 2FA requirement on or off for a role. Users of that role who haven't enrolled
+/// are made to enrol at their next login.
+pub async fn update_mfa_policy(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<MfaPolicyUpdate>,
+) -> impl Responder {
+    let admin_id = logged_in_user_id(&req);
+
+    let result = sqlx::query_as::<_, MfaPolicy>(
+        "INSERT INTO mfa_policy (role, required, updated_by, updated_at) VALUES ($1, $2, $3, NOW())
+         ON CONFLICT (role) DO UPDATE
+         SET required = EXCLUDED.required, updated_by = EXCLUDED.updated_by, updated_at = NOW()
+         RETURNING role, required, updated_by, updated_at",
+    )
+    .bind(payload.role)
+    .bind(payload.required)
+    .bind(admin_id)
+    .fetch_one(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(policy) => HttpResponse::Ok().json(policy),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to update MFA policy")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImpersonationRequest {
+    /// Why support needs to see the account; kept with the session
+    pub reason: String,
+    /// Defaults to a read-only session
+    pub read_only: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct ImpersonationResponse {
+    pub token: String,
+    pub expires_in: i64,
+    pub read_only: bool,
+    pub user_id: Uuid,
+    pub username: String,
+}
+
+/// Mint a short-lived token for viewing the app as another user. Admin accounts can't be
+/// impersonated, and the token is never set as a cookie so it can't replace the admin's own.
+pub async fn impersonate_user(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    payload: web::Json<ImpersonationRequest>,
+) -> impl Responder {
+    let admin_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+    let user_id = path.into_inner();
+
+    let reason = payload.reason.trim();
+    if reason.is_empty() {
+        return HttpResponse::BadRequest().body("A reason is required");
+    }
+
+    let target: Result<Option<(String, UserRole)>, sqlx::Error> =
+        sqlx::query_as("SELECT username, role FROM users WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(pool.get_ref())
+            .await;
+
+    let (username, role) = match target {
+        Ok(Some((_, UserRole::Admin))) => {
+            return HttpResponse::Forbidden().body("Admin accounts cannot be impersonated");
+        }
+        Ok(Some(target)) => target,
+        Ok(None) => return HttpResponse::NotFound().body("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to start impersonation");
+        }
+    };
+
+    let session_id = match start_impersonation_session(pool.get_ref(), user_id, admin_id, reason).await {
+        Ok(id) => id,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to start impersonation");
+        }
+    };
+
+    let read_only = payload.read_only.unwrap_or(true);
+    let ttl = impersonation_ttl();
+    let token = generate_impersonation_jwt(
+        &user_id.to_string(),
+        &username,
+        &role.to_string().to_lowercase(),
+        &session_id.to_string(),
+        &admin_id.to_string(),
+        read_only,
+        ttl,
+    );
+
+    match token {
+        Ok(token) => HttpResponse::Ok().json(ImpersonationResponse {
+            token,
+            expires_in: ttl.num_seconds(),
+            read_only,
+            user_id,
+            username,
+        }),
+        Err(e) => {
+            eprintln!("Token generation error: {:?}", e);
+            HttpResponse::InternalServerError().body("Token generation failed")
+        }
+    }
+}
+
+pub async fn list_impersonation_log(
+    pool: web::Data<PgPool>,
+    query: web::Query<PaginationQuery>,
+) -> impl Responder {
+    let sql = "
+        SELECT log_id, session_id, impersonator_id, user_id, method, path, status_code, created_at
+        FROM impersonation_audit_log
+        ORDER BY created_at DESC
+        LIMIT $1 OFFSET $2";
+
+    let result = sqlx::query_as::<_, ImpersonationAuditEntry>(sql)
+        .bind(query.limit())
+        .bind(query.offset())
+        .fetch_all(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch impersonation log")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TaxonomyQuery {
+    pub kind: Option<ProfileTagKind>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewProfileTag {
+    pub kind: ProfileTagKind,
+    /// Derived from the label when left out
+    pub slug: Option<String>,
+    pub label: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProfileTagUpdate {
+    pub label: Option<String>,
+    pub active: Option<bool>,
+}
+
+/// Every tag, retired ones included
+pub async fn list_profile_tags(pool: web::Data<PgPool>, query: web::Query<TaxonomyQuery>) -> impl Responder {
+    let result = sqlx::query_as::<_, ProfileTag>(
+        "SELECT tag_id, kind, slug, label, active, created_at FROM profile_tags
+         WHERE $1::profile_tag_kind IS NULL OR kind = $1
+         ORDER BY kind, label",
+    )
+    .bind(query.kind)
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(tags) => HttpResponse::Ok().json(tags),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch taxonomy")
+        }
+    }
+}
+
+pub async fn create_profile_tag(pool: web::Data<PgPool>, payload: web::Json<NewProfileTag>) -> impl Responder {
+    let label = payload.label.trim();
+    let slug = slugify(payload.slug.as_deref().unwrap_or(label));
+    if label.is_empty() || slug.is_empty() {
+        return HttpResponse::BadRequest().body("A label is required");
+    }
+
+    let result = sqlx::query_as::<_, ProfileTag>(
+        "INSERT INTO profile_tags (kind, slug, label) VALUES ($1, $2, $3)
+         RETURNING tag_id, kind, slug, label, active, created_at",
+    )
+    .bind(payload.kind)
+    .bind(&slug)
+    .bind(label)
+    .fetch_one(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(tag) => HttpResponse::Created().json(tag),
+        Err(sqlx::Error::Database(db)) if db.is_unique_violation() => {
+            HttpResponse::Conflict().body(format!("\"{}\" already exists", slug))
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to create tag")
+        }
+    }
+}
+
+/// Relabel a tag or bring a retired one back. Slugs never change, since profiles store them.
+pub async fn update_profile_tag(
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+    payload: web::Json<ProfileTagUpdate>,
+) -> impl Responder {
+    let label = payload.label.as_deref().map(str::trim);
+    if label == Some("") {
+        return HttpResponse::BadRequest().body("Label cannot be empty");
+    }
+
+    let result = sqlx::query_as::<_, ProfileTag>(
+        "UPDATE profile_tags SET label = COALESCE($1, label), active = COALESCE($2, active)
+         WHERE tag_id = $3
+         RETURNING tag_id, kind, slug, label, active, created_at",
+    )
+    .bind(label)
+    .bind(payload.active)
+    .bind(path.into_inner())
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(Some(tag)) => HttpResponse::Ok().json(tag),
+        Ok(None) => HttpResponse::NotFound().body("Tag not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to update tag")
+        }
+    }
+}
+
+/// Retire a tag: it can no longer be picked, but profiles that have it keep it
+pub async fn retire_profile_tag(pool: web::Data<PgPool>, path: web::Path<Uuid>) -> impl Responder {
+    let result = sqlx::query("UPDATE profile_tags SET active = FALSE WHERE tag_id = $1")
+        .bind(path.into_inner())
+        .execute(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(done) if done.rows_affected() == 0 => HttpResponse::NotFound().body("Tag not found"),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to retire tag")
+        }
+    }
+}
+
+pub fn config_admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .wrap(RequireRole(UserRole::Admin))
+            .route("/security-events", web::get().to(list_security_events))
+            .route("/mfa-policy", web::get().to(get_mfa_policy))
+            .route("/mfa-policy", web::put().to(update_mfa_policy))
+            .route("/impersonate/{user_id}", web::post().to(impersonate_user))
+            .route("/impersonation-log", web::get().to(list_impersonation_log))
+            .route("/taxonomy", web::get().to(list_profile_tags))
+            .route("/taxonomy", web::post().to(create_profile_tag))
+            .route("/taxonomy/{tag_id}", web::patch().to(update_profile_tag))
+            .route("/taxonomy/{tag_id}", web::delete().to(retire_profile_tag))
+            .configure(config_sponsor_review_routes)
+            .configure(config_questionnaire_routes),
+    );
+}
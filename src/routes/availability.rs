@@ -0,0 +1,269 @@
+use crate::auth::logged_in_user_id;
+use crate::handlers::availability::{
+    SlotTimes, Window, concrete_windows, intersect, load_schedules, parse_slots, parse_timezone, total_hours,
+};
+use crate::handlers::blocks::blocked_user_ids;
+use crate::handlers::validation::{FieldError, validation_error_response};
+use crate::middleware::role_guard::has_current_role;
+use crate::models::all_models::{AvailabilitySlot, UserRole, Weekday};
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Most other people one common-availability lookup can include
+const MAX_PARTICIPANTS: usize = 10;
+const MAX_LOOKAHEAD_DAYS: i64 = 28;
+
+#[derive(Deserialize)]
+pub struct UpdateAvailabilityRequest {
+    pub timezone: String,
+    /// Replaces every existing window
+    pub slots: Vec<SlotTimes>,
+}
+
+#[derive(Serialize)]
+pub struct AvailabilityResponse {
+    pub timezone: String,
+    pub slots: Vec<SlotTimes>,
+}
+
+#[derive(Deserialize)]
+pub struct CommonAvailabilityQuery {
+    /// Comma-separated ids of the other people
+    pub user_ids: String,
+    /// How far ahead to look, 7 days by default
+    pub days: Option<i64>,
+}
+
+/// A shared window, shown in the caller's timezone
+#[derive(Serialize)]
+pub struct CommonWindow {
+    pub start: DateTime<Tz>,
+    pub end: DateTime<Tz>,
+    pub hours: f64,
+}
+
+#[derive(Serialize)]
+pub struct CommonAvailabilityResponse {
+    pub timezone: String,
+    pub windows: Vec<CommonWindow>,
+    pub total_hours: f64,
+}
+
+async fn current_availability(pool: &PgPool, user_id: Uuid) -> Result<Option<AvailabilityResponse>, sqlx::Error> {
+    let mut schedules = load_schedules(pool, &[user_id]).await?;
+    Ok(schedules.remove(&user_id).map(|(tz, slots)| AvailabilityResponse {
+        timezone: tz.name().to_string(),
+        slots: slots.iter().map(SlotTimes::from).collect(),
+    }))
+}
+
+/// The caller's timezone and weekly windows, in local time
+pub async fn get_availability(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    match current_availability(pool.get_ref(), user_id).await {
+        Ok(Some(availability)) => HttpResponse::Ok().json(availability),
+        Ok(None) => HttpResponse::NotFound().body("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch availability")
+        }
+    }
+}
+
+async fn replace_availability(
+    pool: &PgPool,
+    user_id: Uuid,
+    timezone: Tz,
+    slots: &[AvailabilitySlot],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("UPDATE users SET timezone = $1 WHERE user_id = $2")
+        .bind(timezone.name())
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM user_availability WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let weekdays: Vec<Weekday> = slots.iter().map(|slot| slot.weekday).collect();
+    let starts: Vec<i16> = slots.iter().map(|slot| slot.start_minute).collect();
+    let ends: Vec<i16> = slots.iter().map(|slot| slot.end_minute).collect();
+    sqlx::query(
+        "INSERT INTO user_availability (user_id, weekday, start_minute, end_minute)
+         SELECT $1, * FROM unnest($2::weekday[], $3::SMALLINT[], $4::SMALLINT[])",
+    )
+    .bind(user_id)
+    .bind(&weekdays)
+    .bind(&starts)
+    .bind(&ends)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+/// Replace the caller's timezone and weekly windows
+pub async fn update_availability(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<UpdateAvailabilityRequest>,
+) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let mut errors = Vec::new();
+    let timezone = parse_timezone(&payload.timezone);
+    if timezone.is_none() {
+        errors.push(FieldError::new(
+            "timezone",
+            "invalid_timezone",
+            "Must be an IANA timezone such as \"Europe/London\"",
+        ));
+    }
+    let slots = parse_slots(&payload.slots, &mut errors);
+    let Some(timezone) = timezone.filter(|_| errors.is_empty()) else {
+        return validation_error_response(&errors);
+    };
+
+    if let Err(e) = replace_availability(pool.get_ref(), user_id, timezone, &slots).await {
+        eprintln!("Database error: {:?}", e);
+        return HttpResponse::InternalServerError().body("Failed to update availability");
+    }
+
+    HttpResponse::Ok().json(AvailabilityResponse {
+        timezone: timezone.name().to_string(),
+        slots: slots.iter().map(SlotTimes::from).collect(),
+    })
+}
+
+/// Whether the caller has a pending or accepted match with every one of `others`
+async fn matched_with_all(pool: &PgPool, user_id: Uuid, others: &[Uuid]) -> Result<bool, sqlx::Error> {
+    let matched: i64 = sqlx::query_scalar(
+        "SELECT COUNT(DISTINCT other) FROM (
+             SELECT CASE WHEN member_id = $1 THEN sponsor_id ELSE member_id END AS other
+             FROM matching_requests
+             WHERE status IN ('pending', 'accepted') AND (member_id = $1 OR sponsor_id = $1)
+         ) matches
+         WHERE other = ANY($2)",
+    )
+    .bind(user_id)
+    .bind(others)
+    .fetch_one(pool)
+    .await?;
+    Ok(matched as usize == others.len())
+}
+
+/// When the caller and everyone listed are all free over the coming days, for arranging a call
+/// or meeting. Only people the caller is matched with can be looked up, unless they're an admin.
+pub async fn common_availability(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<CommonAvailabilityQuery>,
+) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let mut others = Vec::new();
+    for id in query.user_ids.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+        match Uuid::parse_str(id) {
+            Ok(id) if id != user_id && !others.contains(&id) => others.push(id),
+            Ok(_) => {}
+            Err(_) => return HttpResponse::BadRequest().body(format!("Invalid user ID: {}", id)),
+        }
+    }
+    if others.is_empty() || others.len() > MAX_PARTICIPANTS {
+        return HttpResponse::BadRequest()
+            .body(format!("Give between 1 and {} other user IDs", MAX_PARTICIPANTS));
+    }
+    let days = query.days.unwrap_or(7).clamp(1, MAX_LOOKAHEAD_DAYS);
+
+    let is_admin = match has_current_role(pool.get_ref(), user_id, UserRole::Admin).await {
+        Ok(is_admin) => is_admin,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to fetch availability");
+        }
+    };
+    if !is_admin {
+        let allowed = match matched_with_all(pool.get_ref(), user_id, &others).await {
+            Ok(true) => match blocked_user_ids(pool.get_ref(), user_id).await {
+                Ok(blocked) => others.iter().all(|id| !blocked.contains(id)),
+                Err(e) => {
+                    eprintln!("Database error: {:?}", e);
+                    return HttpResponse::InternalServerError().body("Failed to fetch availability");
+                }
+            },
+            Ok(false) => false,
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                return HttpResponse::InternalServerError().body("Failed to fetch availability");
+            }
+        };
+        if !allowed {
+            return HttpResponse::Forbidden().body("You can only compare availability with your matches");
+        }
+    }
+
+    let mut everyone = others.clone();
+    everyone.push(user_id);
+    let schedules = match load_schedules(pool.get_ref(), &everyone).await {
+        Ok(schedules) => schedules,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to fetch availability");
+        }
+    };
+    let found: HashSet<&Uuid> = schedules.keys().collect();
+    if everyone.iter().any(|id| !found.contains(id)) {
+        return HttpResponse::NotFound().body("User not found");
+    }
+
+    let from = Utc::now();
+    let to = from + Duration::days(days);
+    let mut shared: Option<Vec<Window>> = None;
+    for (tz, slots) in schedules.values() {
+        let windows = concrete_windows(*tz, slots, from, to);
+        shared = Some(match shared {
+            Some(shared) => intersect(&shared, &windows),
+            None => windows,
+        });
+    }
+    let shared = shared.unwrap_or_default();
+
+    let caller_tz = schedules[&user_id].0;
+    HttpResponse::Ok().json(CommonAvailabilityResponse {
+        timezone: caller_tz.name().to_string(),
+        total_hours: total_hours(&shared),
+        windows: shared
+            .iter()
+            .map(|window| CommonWindow {
+                start: window.start.with_timezone(&caller_tz),
+                end: window.end.with_timezone(&caller_tz),
+                hours: window.hours(),
+            })
+            .collect(),
+    })
+}
+
+/// Availability; mounted inside the protected `/users` scope
+pub fn config_availability_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/availability", web::get().to(get_availability))
+        .route("/availability", web::put().to(update_availability))
+        .route("/availability/common", web::get().to(common_availability));
+}
@@ -0,0 +1,219 @@
+use crate::auth::logged_in_user_id;
+use crate::handlers::avatars::{
+    AVATAR_SIZES, avatar_key, avatar_max_bytes, avatar_url, initials_svg, pick_size, process_avatar,
+};
+use crate::handlers::storage::StorageBackend;
+use crate::handlers::validation::{FieldError, validation_error_response};
+use actix_multipart::Multipart;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct AvatarQuery {
+    pub size: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct AvatarResponse {
+    pub avatar_url: String,
+}
+
+/// Delete every stored size of an avatar version; failures only leave orphaned files
+async fn delete_avatar_files(storage: &dyn StorageBackend, user_id: Uuid, version: i64) {
+    for size in AVATAR_SIZES {
+        if let Err(e) = storage.delete(&avatar_key(user_id, version, size)).await {
+            eprintln!("Failed to delete old avatar: {}", e);
+        }
+    }
+}
+
+/// Read the `avatar` field of a multipart upload, stopping as soon as it exceeds the size limit
+async fn read_avatar_field(mut payload: Multipart) -> Result<Vec<u8>, HttpResponse> {
+    let max_bytes = avatar_max_bytes();
+
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|_| HttpResponse::BadRequest().body("Invalid multipart body"))?;
+        if field.name() != Some("avatar") {
+            continue;
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|_| HttpResponse::BadRequest().body("Invalid multipart body"))?;
+            if bytes.len() + chunk.len() > max_bytes {
+                return Err(validation_error_response(&[FieldError::new(
+                    "avatar",
+                    "too_large",
+                    format!("Avatar must be at most {} bytes", max_bytes),
+                )]));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        return Ok(bytes);
+    }
+
+    Err(validation_error_response(&[FieldError::new("avatar", "required", "No avatar file was uploaded")]))
+}
+
+/// Upload a new avatar as the `avatar` field of a multipart form
+pub async fn upload_avatar(
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn StorageBackend>,
+    req: HttpRequest,
+    payload: Multipart,
+) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let bytes = match read_avatar_field(payload).await {
+        Ok(bytes) => bytes,
+        Err(resp) => return resp,
+    };
+
+    // Decoding and resizing are CPU-heavy, so keep them off the async workers
+    let resized = match web::block(move || process_avatar(&bytes)).await {
+        Ok(Ok(resized)) => resized,
+        Ok(Err(error)) => return validation_error_response(&[error]),
+        Err(_) => return HttpResponse::InternalServerError().body("Failed to process avatar"),
+    };
+
+    let version = chrono::Utc::now().timestamp_millis();
+    for (size, jpeg) in resized {
+        if let Err(e) = storage.put(&avatar_key(user_id, version, size), jpeg, "image/jpeg").await {
+            eprintln!("Failed to store avatar: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to store avatar");
+        }
+    }
+
+    let url = avatar_url(user_id, Some(version));
+    let previous: Result<Option<Option<i64>>, sqlx::Error> = sqlx::query_scalar(
+        "UPDATE users u SET avatar_version = $1, avatar_url = $2
+         FROM (SELECT avatar_version FROM users WHERE user_id = $3 FOR UPDATE) old
+         WHERE u.user_id = $3
+         RETURNING old.avatar_version",
+    )
+    .bind(version)
+    .bind(&url)
+    .bind(user_id)
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    match previous {
+        Ok(Some(previous)) => {
+            if let Some(previous) = previous {
+                delete_avatar_files(storage.get_ref(), user_id, previous).await;
+            }
+            HttpResponse::Ok().json(AvatarResponse { avatar_url: url })
+        }
+        Ok(None) => {
+            delete_avatar_files(storage.get_ref(), user_id, version).await;
+            HttpResponse::NotFound().body("User not found")
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            delete_avatar_files(storage.get_ref(), user_id, version).await;
+            HttpResponse::InternalServerError().body("Failed to update avatar")
+        }
+    }
+}
+
+/// Remove the uploaded avatar and go back to the generated initials
+pub async fn remove_avatar(
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn StorageBackend>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let url = avatar_url(user_id, None);
+    let previous: Result<Option<Option<i64>>, sqlx::Error> = sqlx::query_scalar(
+        "UPDATE users u SET avatar_version = NULL, avatar_url = $1
+         FROM (SELECT avatar_version FROM users WHERE user_id = $2 FOR UPDATE) old
+         WHERE u.user_id = $2
+         RETURNING old.avatar_version",
+    )
+    .bind(&url)
+    .bind(user_id)
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    match previous {
+        Ok(Some(previous)) => {
+            if let Some(previous) = previous {
+                delete_avatar_files(storage.get_ref(), user_id, previous).await;
+            }
+            HttpResponse::Ok().json(AvatarResponse { avatar_url: url })
+        }
+        Ok(None) => HttpResponse::NotFound().body("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to remove avatar")
+        }
+    }
+}
+
+/// Serve a user's avatar at the nearest standard size, or their initials if none was uploaded
+pub async fn get_avatar(
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<Uuid>,
+    query: web::Query<AvatarQuery>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+    let size = pick_size(query.size);
+
+    let user: Result<Option<(String, Option<i64>)>, sqlx::Error> =
+        sqlx::query_as("SELECT username, avatar_version FROM users WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(pool.get_ref())
+            .await;
+
+    let (username, version) = match user {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().body("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to fetch avatar");
+        }
+    };
+
+    if let Some(version) = version {
+        match storage.get(&avatar_key(user_id, version, size)).await {
+            Ok(Some(object)) => {
+                return HttpResponse::Ok()
+                    .content_type(object.content_type)
+                    .insert_header(("Cache-Control", "public, max-age=86400"))
+                    .insert_header(("X-Content-Type-Options", "nosniff"))
+                    .body(object.bytes);
+            }
+            // Fall back to initials rather than showing a broken image
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to load avatar: {}", e),
+        }
+    }
+
+    HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        // Initials follow username changes, so don't cache them for long
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .insert_header(("X-Content-Type-Options", "nosniff"))
+        .body(initials_svg(&username, size))
+}
+
+pub fn config_public_avatar_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/avatars/{user_id}", web::get().to(get_avatar));
+}
+
+/// Avatar management; mounted inside the protected `/users` scope
+pub fn config_avatar_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/avatar", web::post().to(upload_avatar))
+        .route("/avatar", web::delete().to(remove_avatar));
+}
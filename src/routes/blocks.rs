@@ -0,0 +1,137 @@
+use crate::auth::logged_in_user_id;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct BlockedUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub avatar_url: String,
+    pub blocked_at: NaiveDateTime,
+}
+
+/// Users the caller has blocked, most recent first
+pub async fn list_blocks(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let query = "
+        SELECT u.user_id, u.username, u.avatar_url, b.created_at AS blocked_at
+        FROM user_blocks b JOIN users u ON u.user_id = b.blocked_id
+        WHERE b.blocker_id = $1
+        ORDER BY b.created_at DESC";
+
+    let result = sqlx::query_as::<_, BlockedUser>(query)
+        .bind(user_id)
+        .fetch_all(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(blocked) => HttpResponse::Ok().json(blocked),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch blocked users")
+        }
+    }
+}
+
+/// Block a user. Pending sponsor requests between the two are declined at the same time.
+pub async fn block_user(pool: web::Data<PgPool>, req: HttpRequest, path: web::Path<Uuid>) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+    let target_id = path.into_inner();
+    if target_id == user_id {
+        return HttpResponse::BadRequest().body("You can't block yourself");
+    }
+
+    let target_role: Result<Option<String>, sqlx::Error> =
+        sqlx::query_scalar("SELECT role::TEXT FROM users WHERE user_id = $1")
+            .bind(target_id)
+            .fetch_optional(pool.get_ref())
+            .await;
+    match target_role {
+        Ok(Some(role)) if role == "admin" => {
+            // Moderators have to be able to reach everyone
+            return HttpResponse::BadRequest().body("Administrators can't be blocked");
+        }
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().body("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to block user");
+        }
+    }
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to block user");
+        }
+    };
+
+    let inserted = sqlx::query(
+        "INSERT INTO user_blocks (blocker_id, blocked_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    )
+    .bind(user_id)
+    .bind(target_id)
+    .execute(&mut *tx)
+    .await;
+
+    let declined = sqlx::query(
+        "UPDATE matching_requests SET status = 'declined'
+         WHERE status = 'pending'
+           AND ((member_id = $1 AND sponsor_id = $2) OR (member_id = $2 AND sponsor_id = $1))",
+    )
+    .bind(user_id)
+    .bind(target_id)
+    .execute(&mut *tx)
+    .await;
+
+    if let Err(e) = inserted.and(declined) {
+        eprintln!("Database error: {:?}", e);
+        return HttpResponse::InternalServerError().body("Failed to block user");
+    }
+    if let Err(e) = tx.commit().await {
+        eprintln!("Database error: {:?}", e);
+        return HttpResponse::InternalServerError().body("Failed to block user");
+    }
+
+    HttpResponse::Ok().body("User blocked")
+}
+
+pub async fn unblock_user(pool: web::Data<PgPool>, req: HttpRequest, path: web::Path<Uuid>) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let result = sqlx::query("DELETE FROM user_blocks WHERE blocker_id = $1 AND blocked_id = $2")
+        .bind(user_id)
+        .bind(path.into_inner())
+        .execute(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(done) if done.rows_affected() == 0 => HttpResponse::NotFound().body("User is not blocked"),
+        Ok(_) => HttpResponse::Ok().body("User unblocked"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to unblock user")
+        }
+    }
+}
+
+/// Blocking; mounted inside the protected `/users` scope
+pub fn config_block_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/blocks", web::get().to(list_blocks))
+        .route("/blocks/{user_id}", web::post().to(block_user))
+        .route("/blocks/{user_id}", web::delete().to(unblock_user));
+}
@@ -0,0 +1,192 @@
+use crate::auth::logged_in_user_id;
+use crate::handlers::data_export::{signed_download_url, spawn_export, verify_download_link};
+use crate::handlers::storage::StorageBackend;
+use crate::middleware::impersonation_guard::DenyImpersonation;
+use crate::models::all_models::{DataExport, ExportStatus};
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+pub struct ExportStatusResponse {
+    #[serde(flatten)]
+    pub export: DataExport,
+    /// Short-lived signed link, present once the archive is ready
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DownloadQuery {
+    pub expires: i64,
+    pub signature: String,
+}
+
+/// Start building an archive of everything held about the caller. Only the latest export is
+/// kept; older archives are deleted when a new one is requested.
+pub async fn request_export(
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn StorageBackend>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    // A pending export older than an hour was lost to a restart and can be replaced
+    let in_progress: Result<Option<bool>, sqlx::Error> = sqlx::query_scalar(
+        "SELECT TRUE FROM data_exports
+         WHERE user_id = $1 AND status = 'pending' AND created_at > NOW() - INTERVAL '1 hour'",
+    )
+    .bind(user_id)
+    .fetch_optional(pool.get_ref())
+    .await;
+    match in_progress {
+        Ok(Some(_)) => return HttpResponse::Conflict().body("An export is already being prepared"),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to start export");
+        }
+    }
+
+    let old_keys: Result<Vec<Option<String>>, sqlx::Error> =
+        sqlx::query_scalar("DELETE FROM data_exports WHERE user_id = $1 RETURNING storage_key")
+            .bind(user_id)
+            .fetch_all(pool.get_ref())
+            .await;
+    match old_keys {
+        Ok(keys) => {
+            for key in keys.into_iter().flatten() {
+                if let Err(e) = storage.delete(&key).await {
+                    eprintln!("Failed to delete old export: {}", e);
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to start export");
+        }
+    }
+
+    let export = sqlx::query_as::<_, DataExport>(
+        "INSERT INTO data_exports (user_id) VALUES ($1)
+         RETURNING export_id, user_id, status, storage_key, size_bytes, created_at, completed_at, expires_at",
+    )
+    .bind(user_id)
+    .fetch_one(pool.get_ref())
+    .await;
+
+    match export {
+        Ok(export) => {
+            spawn_export(pool.get_ref().clone(), storage.into_inner(), export.export_id, user_id);
+            HttpResponse::Accepted().json(ExportStatusResponse { export, download_url: None })
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to start export")
+        }
+    }
+}
+
+/// The caller's latest export, with a fresh download link when it's ready
+pub async fn export_status(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let export = sqlx::query_as::<_, DataExport>(
+        "SELECT export_id, user_id, status, storage_key, size_bytes, created_at, completed_at, expires_at
+         FROM data_exports WHERE user_id = $1 ORDER BY created_at DESC LIMIT 1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    match export {
+        Ok(Some(export)) => {
+            let downloadable = export.status == ExportStatus::Ready
+                && export.expires_at.is_some_and(|at| at > chrono::Utc::now().naive_utc());
+            let download_url = downloadable.then(|| signed_download_url(export.export_id));
+            HttpResponse::Ok().json(ExportStatusResponse { export, download_url })
+        }
+        Ok(None) => HttpResponse::NotFound().body("No export has been requested"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch export")
+        }
+    }
+}
+
+/// Download an archive through a signed link; no login needed, the signature is the credential
+pub async fn download_export(
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<Uuid>,
+    query: web::Query<DownloadQuery>,
+) -> impl Responder {
+    let export_id = path.into_inner();
+    if !verify_download_link(export_id, query.expires, &query.signature) {
+        return HttpResponse::Forbidden().body("Invalid or expired download link");
+    }
+
+    let export = sqlx::query_as::<_, DataExport>(
+        "SELECT export_id, user_id, status, storage_key, size_bytes, created_at, completed_at, expires_at
+         FROM data_exports WHERE export_id = $1",
+    )
+    .bind(export_id)
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    let export = match export {
+        Ok(Some(export)) => export,
+        Ok(None) => return HttpResponse::Gone().body("This export is no longer available"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to fetch export");
+        }
+    };
+
+    let expired = export.expires_at.is_none_or(|at| at <= chrono::Utc::now().naive_utc());
+    let key = match export.storage_key {
+        Some(key) if export.status == ExportStatus::Ready && !expired => key,
+        _ => return HttpResponse::Gone().body("This export is no longer available"),
+    };
+
+    match storage.get(&key).await {
+        Ok(Some(object)) => HttpResponse::Ok()
+            .content_type("application/zip")
+            .insert_header((
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"data-export-{}.zip\"",
+                    export.created_at.format("%Y-%m-%d")
+                ),
+            ))
+            .insert_header(("Cache-Control", "no-store"))
+            .body(object.bytes),
+        Ok(None) => HttpResponse::Gone().body("This export is no longer available"),
+        Err(e) => {
+            eprintln!("Failed to load export: {}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch export")
+        }
+    }
+}
+
+/// Signed download links; mounted inside the public `/users` scope
+pub fn config_public_export_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/exports/{export_id}", web::get().to(download_export));
+}
+
+/// Export requests; mounted inside the protected `/users` scope
+pub fn config_export_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/export")
+            .wrap(DenyImpersonation)
+            .route(web::post().to(request_export))
+            .route(web::get().to(export_status)),
+    );
+}
@@ -0,0 +1,13 @@
+use crate::handlers::jwt_keys::KEY_SET;
+use actix_web::{HttpResponse, Responder, web};
+
+/// Public keys for verifying our access tokens. Shared HS256 secrets are never listed.
+pub async fn get_jwks() -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(KEY_SET.jwks())
+}
+
+pub fn config_jwks_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/.well-known/jwks.json", web::get().to(get_jwks));
+}
@@ -1,18 +1,28 @@
 use crate::auth::Claims;
+use crate::handlers::blocks::is_blocked_pair;
+use crate::handlers::email_verification::ensure_email_verified;
 use crate::handlers::match_algo::calculate_match_score;
-use crate::models::all_models::{MatchUser, MatchingRequest, MatchingStatus};
+use crate::handlers::profile_view::{PROFILE_COLUMNS, ProfileRow, ProfileView, Viewer, profile_views};
+use crate::middleware::role_guard::{RequireAnyRole, RequireRole};
+use crate::models::all_models::{MatchUser, MatchingRequest, MatchingStatus, UserRole};
 use actix_web::{HttpMessage, HttpRequest, HttpResponse, Responder, web};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::PgPool;
+use std::collections::HashMap;
 use uuid::Uuid;
+
+/// Columns a `MatchUser` is read from; select them `FROM users`
+const MATCH_USER_COLUMNS: &str = "user_id AS id, dob, location, interests, experience, languages, timezone,
+    COALESCE((SELECT jsonb_agg(jsonb_build_object(
+                  'weekday', a.weekday, 'start_minute', a.start_minute, 'end_minute', a.end_minute))
+              FROM user_availability a WHERE a.user_id = users.user_id), '[]') AS availability";
+
 pub async fn recommend_sponsors(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
-        let user_query = "
-            SELECT user_id as id, dob, location, interests, experience, available_days, languages
-            FROM users WHERE user_id = $1";
+        let user_query = format!("SELECT {} FROM users WHERE user_id = $1", MATCH_USER_COLUMNS);
 
-        let user_result = sqlx::query_as::<_, MatchUser>(user_query)
+        let user_result = sqlx::query_as::<_, MatchUser>(&user_query)
             .bind(&claims.id)
             .fetch_one(pool.get_ref())
             .await;
@@ -21,36 +31,41 @@ pub async fn recommend_sponsors(pool: web::Data<PgPool>, req: HttpRequest) -> im
             if member.location.is_none()
                 || member.interests.is_none()
                 || member.experience.is_none()
-                || member.available_days.is_none()
+                || member.availability.is_empty()
                 || member.languages.is_none()
             {
                 return HttpResponse::BadRequest()
                     .body("Complete your profile before requesting a sponsor.");
             }
 
-            let sponsor_query = "
-                SELECT user_id as id, dob, location, interests, experience, available_days, languages
-                FROM users WHERE role = 'sponsor'";
+            let sponsor_query = format!(
+                "SELECT {} FROM users
+                 WHERE role = 'sponsor' AND deletion_scheduled_for IS NULL AND purged_at IS NULL",
+                MATCH_USER_COLUMNS
+            );
 
-            let sponsors_result = sqlx::query_as::<_, MatchUser>(sponsor_query)
+            let sponsors_result = sqlx::query_as::<_, MatchUser>(&sponsor_query)
                 .fetch_all(pool.get_ref())
                 .await;
 
-            match sponsors_result {
-                Ok(sponsors) => {
-                    let mut sponsor_scores: Vec<(MatchUser, f32)> = sponsors
-                        .into_iter()
-                        .map(|sponsor| {
-                            let score = calculate_match_score(&member, &sponsor);
-                            (sponsor, score)
-                        })
-                        .collect();
+            let sponsors = match sponsors_result {
+                Ok(sponsors) => sponsors,
+                Err(_) => return HttpResponse::InternalServerError().body("Failed to fetch sponsors."),
+            };
+
+            let mut sponsor_scores: Vec<(Uuid, f32)> = sponsors
+                .iter()
+                .map(|sponsor| (sponsor.id, calculate_match_score(&member, sponsor)))
+                .collect();
 
-                    sponsor_scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+            sponsor_scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
 
-                    HttpResponse::Ok().json(sponsor_scores)
+            match sponsor_profiles(pool.get_ref(), &req, sponsor_scores).await {
+                Ok(recommended) => HttpResponse::Ok().json(recommended),
+                Err(e) => {
+                    eprintln!("Database error: {:?}", e);
+                    HttpResponse::InternalServerError().body("Failed to fetch sponsors.")
                 }
-                Err(_) => HttpResponse::InternalServerError().body("Failed to fetch sponsors."),
             }
         } else {
             HttpResponse::InternalServerError().body("Failed to fetch user data.")
@@ -60,6 +75,42 @@ pub async fn recommend_sponsors(pool: web::Data<PgPool>, req: HttpRequest) -> im
     }
 }
 
+#[derive(Serialize)]
+pub struct RecommendedSponsor {
+    #[serde(flatten)]
+    pub profile: ProfileView,
+    pub match_score: f32,
+}
+
+/// Scored sponsors as the member is allowed to see them, best match first. Sponsors in a block
+/// with the member are dropped by `profile_views`.
+async fn sponsor_profiles(
+    pool: &PgPool,
+    req: &HttpRequest,
+    scores: Vec<(Uuid, f32)>,
+) -> Result<Vec<RecommendedSponsor>, sqlx::Error> {
+    let ids: Vec<Uuid> = scores.iter().map(|(id, _)| *id).collect();
+    let query = format!("SELECT {} FROM users WHERE user_id = ANY($1)", PROFILE_COLUMNS);
+    let rows = sqlx::query_as::<_, ProfileRow>(&query)
+        .bind(&ids)
+        .fetch_all(pool)
+        .await?;
+
+    let viewer = Viewer::from_request(pool, req).await?;
+    let mut views: HashMap<Uuid, ProfileView> = profile_views(pool, &viewer, rows)
+        .await?
+        .into_iter()
+        .map(|view| (view.user_id, view))
+        .collect();
+
+    Ok(scores
+        .into_iter()
+        .filter_map(|(id, match_score)| {
+            views.remove(&id).map(|profile| RecommendedSponsor { profile, match_score })
+        })
+        .collect())
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SponsorRequest {
     pub sponsor_id: Uuid,
@@ -71,6 +122,40 @@ pub async fn request_sponsor(
     payload: web::Json<SponsorRequest>,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
+        let member_id = match Uuid::parse_str(&claims.id) {
+            Ok(id) => id,
+            Err(_) => return HttpResponse::BadRequest().body("Invalid user ID format"),
+        };
+        if let Err(resp) = ensure_email_verified(pool.get_ref(), member_id, "request_sponsor").await {
+            return resp;
+        }
+
+        // Accounts being deleted can't take on new members
+        let sponsor_active: Result<Option<bool>, sqlx::Error> = sqlx::query_scalar(
+            "SELECT TRUE FROM users
+             WHERE user_id = $1 AND role = 'sponsor' AND deletion_scheduled_for IS NULL AND purged_at IS NULL",
+        )
+        .bind(payload.sponsor_id)
+        .fetch_optional(pool.get_ref())
+        .await;
+        match sponsor_active {
+            Ok(Some(_)) => {}
+            Ok(None) => return HttpResponse::NotFound().body("Sponsor not found."),
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                return HttpResponse::InternalServerError().body("Failed to request sponsor.");
+            }
+        }
+
+        match is_blocked_pair(pool.get_ref(), member_id, payload.sponsor_id).await {
+            Ok(false) => {}
+            Ok(true) => return HttpResponse::Forbidden().body("You can't request this sponsor."),
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                return HttpResponse::InternalServerError().body("Failed to request sponsor.");
+            }
+        }
+
         // Check if the user has already sent a request to the same sponsor
         let check_request_query =
             "SELECT COUNT(*) FROM matching_requests WHERE member_id = $1 AND sponsor_id = $2";
@@ -87,7 +172,9 @@ pub async fn request_sponsor(
 
         // Ensure user has filled required fields before requesting
         let user_query = "
-            SELECT location, interests, experience, available_days, languages
+            SELECT location, interests, experience,
+                   EXISTS (SELECT 1 FROM user_availability a WHERE a.user_id = users.user_id),
+                   languages
             FROM users WHERE user_id = $1";
 
         let user_result: Result<
@@ -95,7 +182,7 @@ pub async fn request_sponsor(
                 Option<Value>,
                 Option<Vec<String>>,
                 Option<Vec<String>>,
-                Option<Vec<String>>,
+                bool,
                 Option<Vec<String>>,
             ),
             sqlx::Error,
@@ -105,11 +192,11 @@ pub async fn request_sponsor(
             .await;
 
         match user_result {
-            Ok((location, interests, experience, available_days, languages)) => {
+            Ok((location, interests, experience, has_availability, languages)) => {
                 if location.is_none()
                     || interests.is_none()
                     || experience.is_none()
-                    || available_days.is_none()
+                    || !has_availability
                     || languages.is_none()
                 {
                     return HttpResponse::BadRequest()
@@ -203,9 +290,21 @@ pub async fn respond_to_matching_request(
 pub fn config_matching_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/matching")
-            .route("/recommend", web::get().to(recommend_sponsors))
-            .route("/request", web::post().to(request_sponsor))
+            .service(
+                web::resource("/recommend")
+                    .wrap(RequireRole(UserRole::Member))
+                    .route(web::get().to(recommend_sponsors)),
+            )
+            .service(
+                web::resource("/request")
+                    .wrap(RequireRole(UserRole::Member))
+                    .route(web::post().to(request_sponsor)),
+            )
             .route("/status", web::get().to(check_matching_status))
-            .route("/respond", web::patch().to(respond_to_matching_request)),
+            .service(
+                web::resource("/respond")
+                    .wrap(RequireAnyRole(&[UserRole::Sponsor, UserRole::Admin]))
+                    .route(web::patch().to(respond_to_matching_request)),
+            ),
     );
 }
@@ -0,0 +1,364 @@
+use crate::auth::{Claims, TokenType, logged_in_user_id, validate_jwt};
+use crate::handlers::login_throttle::{check_login_allowed, client_ip, record_login_attempt};
+use crate::handlers::mfa::{
+    begin_enrolment, consume_recovery_code, disable_mfa, enable_mfa, mfa_requirement,
+    regenerate_recovery_codes, verify_totp_code,
+};
+use crate::handlers::password::verify_password;
+use crate::handlers::sessions::SessionClient;
+use crate::middleware::impersonation_guard::DenyImpersonation;
+use crate::routes::user_auth::complete_login;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+pub struct MfaSetupResponse {
+    /// Base32 secret for manual entry
+    pub secret: String,
+    /// otpauth:// URI to render as a QR code
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct MfaStatusResponse {
+    pub enabled: bool,
+    pub required: bool,
+}
+
+#[derive(Deserialize)]
+pub struct MfaCodeRequest {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChallengeRequest {
+    pub challenge_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct MfaLoginRequest {
+    pub challenge_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DisableMfaRequest {
+    pub password: String,
+    pub code: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct MfaUser {
+    pub username: String,
+    pub role: String,
+    pub avatar_url: String,
+    pub password_hash: String,
+    pub banned_until: Option<NaiveDateTime>,
+}
+
+async fn fetch_mfa_user(pool: &PgPool, user_id: Uuid) -> Result<MfaUser, sqlx::Error> {
+    sqlx::query_as::<_, MfaUser>(
+        "SELECT username, role::TEXT AS role, avatar_url, password_hash, banned_until
+         FROM users WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Validate an "mfa_pending" challenge token, returning the user it was issued to
+fn challenge_user(token: &str) -> Option<(Uuid, Claims)> {
+    let claims = validate_jwt(token).ok()?;
+    if claims.token_type != TokenType::MfaPending {
+        return None;
+    }
+    let user_id = Uuid::parse_str(&claims.id).ok()?;
+    Some((user_id, claims))
+}
+
+fn setup_response(result: Result<Option<(String, String)>, String>) -> HttpResponse {
+    match result {
+        Ok(Some((secret, otpauth_uri))) => HttpResponse::Ok().json(MfaSetupResponse { secret, otpauth_uri }),
+        Ok(None) => HttpResponse::Conflict().body("Two-factor authentication is already enabled"),
+        Err(e) => {
+            eprintln!("MFA setup error: {}", e);
+            HttpResponse::InternalServerError().body("Failed to start two-factor setup")
+        }
+    }
+}
+
+/// Enrolment for users whose role requires 2FA but who haven't set it up; authorised by the
+/// challenge token from the password step
+pub async fn enroll_with_challenge(
+    pool: web::Data<PgPool>,
+    payload: web::Json<ChallengeRequest>,
+) -> impl Responder {
+    let (user_id, claims) = match challenge_user(&payload.challenge_token) {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized().body("Invalid or expired challenge token"),
+    };
+
+    setup_response(begin_enrolment(pool.get_ref(), user_id, &claims.username).await)
+}
+
+/// Second step of login: exchange a challenge token plus a TOTP or recovery code for a session.
+/// If the user was enrolling, a valid code also switches 2FA on and returns recovery codes.
+pub async fn login_with_mfa(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<MfaLoginRequest>,
+) -> impl Responder {
+    let (user_id, claims) = match challenge_user(&payload.challenge_token) {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized().body("Invalid or expired challenge token"),
+    };
+    let ip = client_ip(&req);
+
+    match check_login_allowed(pool.get_ref(), &claims.username, &ip).await {
+        Ok(None) => {}
+        Ok(Some(retry_after)) => {
+            return HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .body("Too many failed login attempts. Please try again later.");
+        }
+        Err(e) => {
+            eprintln!("Login throttle error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Error logging in");
+        }
+    }
+
+    let user = match fetch_mfa_user(pool.get_ref(), user_id).await {
+        Ok(user) => user,
+        Err(_) => return HttpResponse::Unauthorized().body("Invalid or expired challenge token"),
+    };
+    if let Some(banned_until) = user.banned_until
+        && banned_until > chrono::Utc::now().naive_utc()
+    {
+        return HttpResponse::Forbidden().body("Your account is currently banned.");
+    }
+
+    let mfa = match mfa_requirement(pool.get_ref(), user_id, &user.role).await {
+        Ok(mfa) => mfa,
+        Err(e) => {
+            eprintln!("MFA lookup error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Error logging in");
+        }
+    };
+
+    let verified = match (&payload.code, &payload.recovery_code) {
+        (Some(code), _) => verify_totp_code(pool.get_ref(), user_id, &user.username, code).await,
+        (None, Some(code)) if mfa.enrolled => consume_recovery_code(pool.get_ref(), user_id, code).await,
+        _ => return HttpResponse::BadRequest().body("A verification code is required"),
+    };
+    let verified = match verified {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("MFA verification error: {}", e);
+            return HttpResponse::InternalServerError().body("Error logging in");
+        }
+    };
+
+    if let Err(e) = record_login_attempt(pool.get_ref(), &user.username, &ip, Some(user_id), verified).await {
+        eprintln!("Failed to record login attempt: {:?}", e);
+    }
+    if !verified {
+        return HttpResponse::Unauthorized().body("Invalid verification code");
+    }
+
+    let recovery_codes = if mfa.enrolled {
+        None
+    } else {
+        if let Err(e) = enable_mfa(pool.get_ref(), user_id).await {
+            eprintln!("MFA enable error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to enable two-factor authentication");
+        }
+        match regenerate_recovery_codes(pool.get_ref(), user_id).await {
+            Ok(codes) => Some(codes),
+            Err(e) => {
+                eprintln!("Recovery code error: {}", e);
+                return HttpResponse::InternalServerError().body("Failed to generate recovery codes");
+            }
+        }
+    };
+
+    let client = SessionClient::from_request(&req);
+    complete_login(pool.get_ref(), user_id, user.username, &user.role, user.avatar_url, recovery_codes, &client).await
+}
+
+pub async fn mfa_status(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+    let user = match fetch_mfa_user(pool.get_ref(), user_id).await {
+        Ok(user) => user,
+        Err(_) => return HttpResponse::NotFound().body("User not found"),
+    };
+
+    match mfa_requirement(pool.get_ref(), user_id, &user.role).await {
+        Ok(mfa) => HttpResponse::Ok().json(MfaStatusResponse {
+            enabled: mfa.enrolled,
+            required: mfa.required,
+        }),
+        Err(e) => {
+            eprintln!("MFA lookup error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch two-factor status")
+        }
+    }
+}
+
+pub async fn setup_mfa(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let user = match fetch_mfa_user(pool.get_ref(), user_id).await {
+        Ok(user) => user,
+        Err(_) => return HttpResponse::NotFound().body("User not found"),
+    };
+
+    setup_response(begin_enrolment(pool.get_ref(), user_id, &user.username).await)
+}
+
+pub async fn confirm_mfa(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<MfaCodeRequest>,
+) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let user = match fetch_mfa_user(pool.get_ref(), user_id).await {
+        Ok(user) => user,
+        Err(_) => return HttpResponse::NotFound().body("User not found"),
+    };
+
+    match verify_totp_code(pool.get_ref(), user_id, &user.username, &payload.code).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::BadRequest().body("Invalid verification code"),
+        Err(e) => {
+            eprintln!("MFA verification error: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to verify code");
+        }
+    }
+
+    if let Err(e) = enable_mfa(pool.get_ref(), user_id).await {
+        eprintln!("MFA enable error: {:?}", e);
+        return HttpResponse::InternalServerError().body("Failed to enable two-factor authentication");
+    }
+
+    match regenerate_recovery_codes(pool.get_ref(), user_id).await {
+        Ok(recovery_codes) => HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }),
+        Err(e) => {
+            eprintln!("Recovery code error: {}", e);
+            HttpResponse::InternalServerError().body("Failed to generate recovery codes")
+        }
+    }
+}
+
+pub async fn regenerate_codes(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<MfaCodeRequest>,
+) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let user = match fetch_mfa_user(pool.get_ref(), user_id).await {
+        Ok(user) => user,
+        Err(_) => return HttpResponse::NotFound().body("User not found"),
+    };
+
+    match verify_totp_code(pool.get_ref(), user_id, &user.username, &payload.code).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::BadRequest().body("Invalid verification code"),
+        Err(e) => {
+            eprintln!("MFA verification error: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to verify code");
+        }
+    }
+
+    match regenerate_recovery_codes(pool.get_ref(), user_id).await {
+        Ok(recovery_codes) => HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }),
+        Err(e) => {
+            eprintln!("Recovery code error: {}", e);
+            HttpResponse::InternalServerError().body("Failed to generate recovery codes")
+        }
+    }
+}
+
+pub async fn turn_off_mfa(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<DisableMfaRequest>,
+) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let user = match fetch_mfa_user(pool.get_ref(), user_id).await {
+        Ok(user) => user,
+        Err(_) => return HttpResponse::NotFound().body("User not found"),
+    };
+
+    match mfa_requirement(pool.get_ref(), user_id, &user.role).await {
+        Ok(mfa) if mfa.required => {
+            return HttpResponse::Forbidden().body("Two-factor authentication is required for your role");
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("MFA lookup error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to disable two-factor authentication");
+        }
+    }
+
+    if !verify_password(&payload.password, &user.password_hash).unwrap_or(false) {
+        return HttpResponse::Unauthorized().body("Invalid credentials");
+    }
+    match verify_totp_code(pool.get_ref(), user_id, &user.username, &payload.code).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::BadRequest().body("Invalid verification code"),
+        Err(e) => {
+            eprintln!("MFA verification error: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to verify code");
+        }
+    }
+
+    match disable_mfa(pool.get_ref(), user_id).await {
+        Ok(_) => HttpResponse::Ok().body("Two-factor authentication disabled"),
+        Err(e) => {
+            eprintln!("MFA disable error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to disable two-factor authentication")
+        }
+    }
+}
+
+/// Routes used during login; mounted inside the public `/users` scope
+pub fn config_public_mfa_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/login/mfa", web::post().to(login_with_mfa))
+        .route("/mfa/enroll", web::post().to(enroll_with_challenge));
+}
+
+/// Routes for managing 2FA; mounted inside the protected `/users` scope
+pub fn config_protected_mfa_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/mfa/status").wrap(DenyImpersonation).route(web::get().to(mfa_status)))
+        .service(web::resource("/mfa/setup").wrap(DenyImpersonation).route(web::post().to(setup_mfa)))
+        .service(web::resource("/mfa/confirm").wrap(DenyImpersonation).route(web::post().to(confirm_mfa)))
+        .service(web::resource("/mfa/recovery-codes").wrap(DenyImpersonation).route(web::post().to(regenerate_codes)))
+        .service(web::resource("/mfa/disable").wrap(DenyImpersonation).route(web::post().to(turn_off_mfa)));
+}
@@ -1,4 +1,18 @@
 pub mod user_auth;
 pub mod user_info;
 pub mod sponsor;
-pub mod matching;
\ No newline at end of file
+pub mod matching;
+pub mod admin;
+pub mod mfa;
+pub mod account;
+pub mod jwks;
+pub mod sessions;
+pub mod avatars;
+pub mod privacy;
+pub mod blocks;
+pub mod data_export;
+pub mod user_search;
+pub mod availability;
+pub mod sponsor_review;
+pub mod questionnaire;
+pub mod sponsor_documents;
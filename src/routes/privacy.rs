@@ -0,0 +1,107 @@
+use crate::auth::logged_in_user_id;
+use crate::handlers::profile_view::load_visibility;
+use crate::models::all_models::{FieldVisibility, LocationPrecision, ProfileVisibility};
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Fields left out keep their current setting
+#[derive(Deserialize)]
+pub struct UpdateVisibilityRequest {
+    pub bio: Option<FieldVisibility>,
+    pub interests: Option<FieldVisibility>,
+    pub experience: Option<FieldVisibility>,
+    pub languages: Option<FieldVisibility>,
+    pub location: Option<FieldVisibility>,
+    pub location_precision: Option<LocationPrecision>,
+    pub age: Option<FieldVisibility>,
+}
+
+async fn current_visibility(pool: &PgPool, user_id: Uuid) -> Result<ProfileVisibility, sqlx::Error> {
+    let mut settings = load_visibility(pool, &[user_id]).await?;
+    Ok(settings.remove(&user_id).unwrap_or_default())
+}
+
+pub async fn get_visibility(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    match current_visibility(pool.get_ref(), user_id).await {
+        Ok(visibility) => HttpResponse::Ok().json(visibility),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch privacy settings")
+        }
+    }
+}
+
+pub async fn update_visibility(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<UpdateVisibilityRequest>,
+) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let mut visibility = match current_visibility(pool.get_ref(), user_id).await {
+        Ok(visibility) => visibility,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to update privacy settings");
+        }
+    };
+
+    let update = payload.into_inner();
+    visibility.bio = update.bio.unwrap_or(visibility.bio);
+    visibility.interests = update.interests.unwrap_or(visibility.interests);
+    visibility.experience = update.experience.unwrap_or(visibility.experience);
+    visibility.languages = update.languages.unwrap_or(visibility.languages);
+    visibility.location = update.location.unwrap_or(visibility.location);
+    visibility.location_precision = update.location_precision.unwrap_or(visibility.location_precision);
+    visibility.age = update.age.unwrap_or(visibility.age);
+
+    let query = "
+        INSERT INTO profile_visibility
+            (user_id, bio, interests, experience, languages, location, location_precision, age)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (user_id) DO UPDATE SET
+            bio = EXCLUDED.bio,
+            interests = EXCLUDED.interests,
+            experience = EXCLUDED.experience,
+            languages = EXCLUDED.languages,
+            location = EXCLUDED.location,
+            location_precision = EXCLUDED.location_precision,
+            age = EXCLUDED.age,
+            updated_at = NOW()";
+
+    let result = sqlx::query(query)
+        .bind(user_id)
+        .bind(visibility.bio)
+        .bind(visibility.interests)
+        .bind(visibility.experience)
+        .bind(visibility.languages)
+        .bind(visibility.location)
+        .bind(visibility.location_precision)
+        .bind(visibility.age)
+        .execute(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json(visibility),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to update privacy settings")
+        }
+    }
+}
+
+/// Profile visibility settings; mounted inside the protected `/users` scope
+pub fn config_privacy_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/privacy", web::get().to(get_visibility))
+        .route("/privacy", web::patch().to(update_visibility));
+}
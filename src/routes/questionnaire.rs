@@ -0,0 +1,137 @@
+use crate::auth::logged_in_user_id;
+use crate::handlers::questionnaire::{list_questionnaires, validate_questions};
+use crate::handlers::validation::validation_error_response;
+use crate::models::all_models::{Question, Questionnaire};
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+use sqlx::types::Json;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct NewQuestionnaire {
+    pub questions: Vec<Question>,
+    /// Start using it straight away instead of publishing it inactive
+    #[serde(default)]
+    pub activate: bool,
+}
+
+/// Every version, newest first
+pub async fn list_sponsor_questionnaires(pool: web::Data<PgPool>) -> impl Responder {
+    match list_questionnaires(pool.get_ref()).await {
+        Ok(questionnaires) => HttpResponse::Ok().json(questionnaires),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch questionnaires")
+        }
+    }
+}
+
+async fn insert_questionnaire(
+    pool: &PgPool,
+    questions: Vec<Question>,
+    created_by: Option<Uuid>,
+    activate: bool,
+) -> Result<Questionnaire, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    // Serialises publishing, so two admins can't take the same version number
+    sqlx::query("LOCK TABLE sponsor_questionnaires IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .await?;
+    if activate {
+        sqlx::query("UPDATE sponsor_questionnaires SET active = FALSE WHERE active")
+            .execute(&mut *tx)
+            .await?;
+    }
+    let questionnaire = sqlx::query_as::<_, Questionnaire>(
+        "INSERT INTO sponsor_questionnaires (version, questions, created_by, active)
+         SELECT COALESCE(MAX(version), 0) + 1, $1, $2, $3 FROM sponsor_questionnaires
+         RETURNING version, questions, created_by, created_at, active",
+    )
+    .bind(Json(questions))
+    .bind(created_by)
+    .bind(activate)
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(questionnaire)
+}
+
+/// Publish a new version. Versions are never edited, so answers always match their questions.
+pub async fn create_sponsor_questionnaire(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<NewQuestionnaire>,
+) -> impl Responder {
+    let admin_id = logged_in_user_id(&req);
+    let NewQuestionnaire { questions, activate } = payload.into_inner();
+
+    let mut errors = Vec::new();
+    let questions = validate_questions(questions, &mut errors);
+    if !errors.is_empty() {
+        return validation_error_response(&errors);
+    }
+
+    match insert_questionnaire(pool.get_ref(), questions, admin_id, activate).await {
+        Ok(questionnaire) => HttpResponse::Created().json(questionnaire),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to create questionnaire")
+        }
+    }
+}
+
+async fn set_active_questionnaire(pool: &PgPool, version: Option<i32>) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if let Some(version) = version {
+        let exists: Option<i32> = sqlx::query_scalar("SELECT version FROM sponsor_questionnaires WHERE version = $1")
+            .bind(version)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if exists.is_none() {
+            return Ok(false);
+        }
+    }
+    sqlx::query("UPDATE sponsor_questionnaires SET active = FALSE WHERE active")
+        .execute(&mut *tx)
+        .await?;
+    if let Some(version) = version {
+        sqlx::query("UPDATE sponsor_questionnaires SET active = TRUE WHERE version = $1")
+            .bind(version)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Make this version the one applicants answer
+pub async fn activate_sponsor_questionnaire(pool: web::Data<PgPool>, path: web::Path<i32>) -> impl Responder {
+    match set_active_questionnaire(pool.get_ref(), Some(path.into_inner())).await {
+        Ok(true) => HttpResponse::Ok().body("Questionnaire activated"),
+        Ok(false) => HttpResponse::NotFound().body("Questionnaire not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to activate questionnaire")
+        }
+    }
+}
+
+/// Stop asking questions; applications go back to free text only
+pub async fn deactivate_sponsor_questionnaire(pool: web::Data<PgPool>) -> impl Responder {
+    match set_active_questionnaire(pool.get_ref(), None).await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to deactivate questionnaire")
+        }
+    }
+}
+
+/// Sponsor questionnaire management; mounted inside the `/admin` scope
+pub fn config_questionnaire_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/questionnaires", web::get().to(list_sponsor_questionnaires))
+        .route("/questionnaires", web::post().to(create_sponsor_questionnaire))
+        .route("/questionnaires/active", web::delete().to(deactivate_sponsor_questionnaire))
+        .route("/questionnaires/{version}/activate", web::post().to(activate_sponsor_questionnaire));
+}
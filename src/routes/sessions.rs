@@ -0,0 +1,89 @@
+use crate::auth::current_session;
+use crate::handlers::auth_cookies::clear_session_cookies;
+use crate::handlers::sessions::revoke_session;
+use crate::middleware::impersonation_guard::DenyImpersonation;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct SessionInfo {
+    pub session_id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: Option<NaiveDateTime>,
+    pub expires_at: NaiveDateTime,
+    /// The session this request was made with
+    #[sqlx(default)]
+    pub current: bool,
+}
+
+/// The user's live sessions, most recently used first. Impersonation sessions aren't listed.
+pub async fn list_sessions(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let (user_id, session_id) = match current_session(&req) {
+        Some(session) => session,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let query = "
+        SELECT session_id, user_agent, ip_address, created_at, last_seen_at, expires_at,
+               session_id IS NOT DISTINCT FROM $2 AS current
+        FROM sessions
+        WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW() AND impersonator_id IS NULL
+        ORDER BY last_seen_at DESC NULLS LAST";
+
+    let result = sqlx::query_as::<_, SessionInfo>(query)
+        .bind(user_id)
+        .bind(session_id)
+        .fetch_all(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(sessions) => HttpResponse::Ok().json(sessions),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch sessions")
+        }
+    }
+}
+
+/// Sign a device out. Its refresh token stops working and its live connections are closed.
+pub async fn revoke_device_session(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let (user_id, current) = match current_session(&req) {
+        Some(session) => session,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+    let session_id = path.into_inner();
+
+    match revoke_session(pool.get_ref(), user_id, session_id).await {
+        Ok(true) => {
+            let mut builder = HttpResponse::Ok();
+            if current == Some(session_id) {
+                clear_session_cookies(&mut builder);
+            }
+            builder.body("Session revoked")
+        }
+        Ok(false) => HttpResponse::NotFound().body("Session not found"),
+        Err(e) => {
+            eprintln!("Session revoke error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to revoke session")
+        }
+    }
+}
+
+/// Device management; mounted inside the protected `/users` scope
+pub fn config_session_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/sessions").wrap(DenyImpersonation).route(web::get().to(list_sessions)))
+        .service(
+            web::resource("/sessions/{session_id}")
+                .wrap(DenyImpersonation)
+                .route(web::delete().to(revoke_device_session)),
+        );
+}
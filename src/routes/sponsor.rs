@@ -4,11 +4,22 @@ use serde::{Deserialize, Serialize};
 use chrono::NaiveDateTime;
 use uuid::Uuid;
 use crate::auth::Claims;
-use crate::models::all_models::ApplicationStatus;
+use crate::handlers::email_verification::ensure_email_verified;
+use crate::handlers::questionnaire::{active_questionnaire, validate_answers};
+use crate::handlers::sponsor_applications::{check_application_info, reapply_blocked_until, record_version};
+use crate::handlers::validation::{FieldError, validation_error_response};
+use crate::middleware::role_guard::RequireRole;
+use crate::routes::sponsor_documents::config_sponsor_document_routes;
+use crate::models::all_models::{ApplicationStatus, UserRole};
+use serde_json::{Map, Value};
 
 #[derive(Debug, Deserialize,Serialize)]
 pub struct SponsorApplicationRequest {
+    /// Free text; can be left out when the questionnaire is answered instead
+    #[serde(default)]
     pub application_info: String,
+    /// Answers to the active questionnaire, keyed by question key
+    pub answers: Option<Map<String, Value>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -20,6 +31,61 @@ pub struct SponsorApplication {
     pub reviewed_by: Option<Uuid>,
     pub admin_comments: Option<String>,
     pub created_at: NaiveDateTime,
+    pub reviewed_at: Option<NaiveDateTime>,
+    pub withdrawn_at: Option<NaiveDateTime>,
+    pub questionnaire_version: Option<i32>,
+    pub answers: Option<Value>,
+}
+
+const APPLICATION_COLUMNS: &str = "application_id, user_id, status, application_info, reviewed_by, admin_comments, \
+                                   created_at, reviewed_at, withdrawn_at, questionnaire_version, answers";
+
+fn cooldown_response(until: NaiveDateTime) -> HttpResponse {
+    HttpResponse::TooManyRequests().body(format!(
+        "Your last application was rejected. You can apply again after {}.",
+        until.format("%Y-%m-%d")
+    ))
+}
+
+/// Check answers against the active questionnaire; gives its version and the answers to store,
+/// or `None` when there is no questionnaire and nothing was answered
+async fn checked_answers(pool: &PgPool, answers: Option<&Map<String, Value>>) -> Result<Option<(i32, Value)>, HttpResponse> {
+    let questionnaire = match active_questionnaire(pool).await {
+        Ok(questionnaire) => questionnaire,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return Err(HttpResponse::InternalServerError().body("Failed to fetch questionnaire"));
+        }
+    };
+
+    let mut errors = Vec::new();
+    let checked = match questionnaire {
+        Some(questionnaire) => {
+            let empty = Map::new();
+            let stored = validate_answers(&questionnaire.questions, answers.unwrap_or(&empty), &mut errors);
+            Some((questionnaire.version, stored))
+        }
+        None => {
+            if answers.is_some_and(|answers| !answers.is_empty()) {
+                errors.push(FieldError::new("answers", "no_questionnaire", "There is no questionnaire to answer"));
+            }
+            None
+        }
+    };
+
+    if errors.is_empty() { Ok(checked) } else { Err(validation_error_response(&errors)) }
+}
+
+/// The questions applicants answer right now
+pub async fn get_sponsor_questionnaire(pool: web::Data<PgPool>) -> impl Responder {
+    match active_questionnaire(pool.get_ref()).await {
+        Ok(Some(questionnaire)) => HttpResponse::Ok().json(questionnaire),
+        Ok(None) => HttpResponse::NotFound().body("There is no questionnaire; describe yourself in application_info."),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch questionnaire")
+        }
+    }
 }
 
 pub async fn submit_sponsor_application(
@@ -28,11 +94,19 @@ pub async fn submit_sponsor_application(
     payload: web::Json<SponsorApplicationRequest>,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
-        // Check if the user has already submitted an application
-        let check_query = "SELECT COUNT(*) FROM sponsor_applications WHERE user_id = $1";
+        let user_id = match Uuid::parse_str(&claims.id) {
+            Ok(id) => id,
+            Err(_) => return HttpResponse::BadRequest().body("Invalid user ID format"),
+        };
+        if let Err(resp) = ensure_email_verified(pool.get_ref(), user_id, "sponsor_application").await {
+            return resp;
+        }
+
+        // Only one application at a time; withdrawn ones don't count
+        let check_query = "SELECT COUNT(*) FROM sponsor_applications WHERE user_id = $1 AND withdrawn_at IS NULL";
 
         let existing_count: i64 = sqlx::query_scalar(check_query)
-            .bind(&claims.id)
+            .bind(user_id)
             .fetch_one(pool.get_ref())
             .await
             .unwrap_or(0);
@@ -41,18 +115,47 @@ pub async fn submit_sponsor_application(
             return HttpResponse::Conflict().body("You have already submitted an application.");
         }
 
-        // Insert the application into the database
-        let insert_query = "
-            INSERT INTO sponsor_applications (user_id, status, application_info, created_at)
-            VALUES ($1, $2, $3, NOW())
-            RETURNING application_id, user_id, status, application_info, reviewed_by, admin_comments, created_at";
-        
-        let application_result = sqlx::query_as::<_, SponsorApplication>(insert_query)
-            .bind(&claims.id)
-            .bind(ApplicationStatus::Pending) 
-            .bind(&payload.application_info)
-            .fetch_one(pool.get_ref())
-            .await;
+        match reapply_blocked_until(pool.get_ref(), user_id).await {
+            Ok(Some(until)) => return cooldown_response(until),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                return HttpResponse::InternalServerError().body("Failed to submit application");
+            }
+        }
+
+        let answers = match checked_answers(pool.get_ref(), payload.answers.as_ref()).await {
+            Ok(answers) => answers,
+            Err(resp) => return resp,
+        };
+        if let Some(error) = check_application_info(&payload.application_info, answers.as_ref().map(|(_, stored)| stored)) {
+            return validation_error_response(&[error]);
+        }
+        let (questionnaire_version, answers) = answers.unzip();
+
+        // The application and its first version are stored together
+        let application_result: Result<SponsorApplication, sqlx::Error> = async {
+            let mut tx = pool.begin().await?;
+            let insert_query = format!(
+                "INSERT INTO sponsor_applications
+                     (user_id, status, application_info, questionnaire_version, answers, created_at)
+                 VALUES ($1, $2, $3, $4, $5, NOW())
+                 RETURNING {}",
+                APPLICATION_COLUMNS
+            );
+            let application = sqlx::query_as::<_, SponsorApplication>(&insert_query)
+                .bind(user_id)
+                .bind(ApplicationStatus::Pending)
+                .bind(&payload.application_info)
+                .bind(questionnaire_version)
+                .bind(&answers)
+                .fetch_one(&mut *tx)
+                .await?;
+            record_version(&mut tx, application.application_id).await?;
+            tx.commit().await?;
+            Ok(application)
+        }
+        .await;
 
         match application_result {
             Ok(application) => HttpResponse::Ok().json(application),
@@ -67,10 +170,17 @@ pub async fn check_sponsor_application_status(
     req: HttpRequest,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
-        let query = "SELECT * FROM sponsor_applications WHERE user_id = $1";
+        let user_id = match Uuid::parse_str(&claims.id) {
+            Ok(id) => id,
+            Err(_) => return HttpResponse::BadRequest().body("Invalid user ID format"),
+        };
+        let query = format!(
+            "SELECT {} FROM sponsor_applications WHERE user_id = $1 AND withdrawn_at IS NULL",
+            APPLICATION_COLUMNS
+        );
 
-        let result = sqlx::query_as::<_, SponsorApplication>(query)
-            .bind(&claims.id)
+        let result = sqlx::query_as::<_, SponsorApplication>(&query)
+            .bind(user_id)
             .fetch_one(pool.get_ref())
             .await;
 
@@ -85,7 +195,87 @@ pub async fn check_sponsor_application_status(
 
 #[derive(Debug, Deserialize)]
 pub struct UpdateSponsorApplicationRequest {
-    pub application_info: String,
+    /// Left as it is when not given
+    pub application_info: Option<String>,
+    /// A full new set of answers to the active questionnaire; left as they are when not given
+    pub answers: Option<Map<String, Value>>,
+}
+
+/// Why an edit couldn't be saved
+enum UpdateError {
+    NotFound,
+    Approved,
+    Cooldown(NaiveDateTime),
+    Invalid(FieldError),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for UpdateError {
+    fn from(e: sqlx::Error) -> Self {
+        UpdateError::Database(e)
+    }
+}
+
+/// Save an edit as a new version. Editing a rejected application resubmits it, once the cooldown
+/// is over; editing one that needs more information sends it back for review.
+async fn revise_application(
+    pool: &PgPool,
+    user_id: Uuid,
+    application_info: Option<&str>,
+    answers: Option<(i32, Value)>,
+) -> Result<SponsorApplication, UpdateError> {
+    let mut tx = pool.begin().await?;
+
+    let current: Option<(Uuid, ApplicationStatus, String, Option<Value>)> = sqlx::query_as(
+        "SELECT application_id, status, application_info, answers FROM sponsor_applications
+         WHERE user_id = $1 AND withdrawn_at IS NULL
+         FOR UPDATE",
+    )
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let (application_id, status, previous_info, previous_answers) = current.ok_or(UpdateError::NotFound)?;
+
+    match status {
+        ApplicationStatus::Approved => return Err(UpdateError::Approved),
+        ApplicationStatus::Rejected => {
+            if let Some(until) = reapply_blocked_until(pool, user_id).await? {
+                return Err(UpdateError::Cooldown(until));
+            }
+        }
+        _ => {}
+    }
+
+    let effective_answers = answers.as_ref().map(|(_, stored)| stored).or(previous_answers.as_ref());
+    if let Some(error) = check_application_info(application_info.unwrap_or(&previous_info), effective_answers) {
+        return Err(UpdateError::Invalid(error));
+    }
+
+    let update_query = format!(
+        "UPDATE sponsor_applications 
+         SET application_info = COALESCE($1, application_info),
+             questionnaire_version = COALESCE($2, questionnaire_version),
+             answers = COALESCE($3, answers),
+             status = CASE WHEN status IN ('rejected', 'needs_info') THEN 'pending' ELSE status END 
+         WHERE application_id = $4
+         RETURNING {}",
+        APPLICATION_COLUMNS
+    );
+    let (questionnaire_version, answers) = answers.unzip();
+    let application = sqlx::query_as::<_, SponsorApplication>(&update_query)
+        .bind(application_info)
+        .bind(questionnaire_version)
+        .bind(answers)
+        .bind(application_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    if previous_info != application.application_info || previous_answers != application.answers {
+        record_version(&mut tx, application_id).await?;
+    }
+
+    tx.commit().await?;
+    Ok(application)
 }
 
 pub async fn update_sponsor_application(
@@ -94,59 +284,76 @@ pub async fn update_sponsor_application(
     payload: web::Json<UpdateSponsorApplicationRequest>,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
-        // Check if application exists
-        let check_query = "SELECT status FROM sponsor_applications WHERE user_id = $1";
-        
-        let result: Result<ApplicationStatus, sqlx::Error> = sqlx::query_scalar(check_query)
-            .bind(&claims.id)
-            .fetch_one(pool.get_ref())
-            .await;
+        let user_id = match Uuid::parse_str(&claims.id) {
+            Ok(id) => id,
+            Err(_) => return HttpResponse::BadRequest().body("Invalid user ID format"),
+        };
 
-        match result {
-            Ok(status) => {
-                if status == ApplicationStatus::Approved {
-                    return HttpResponse::Forbidden().body("You cannot update an approved application.");
-                }
-
-                let update_query = "
-                    UPDATE sponsor_applications 
-                    SET application_info = $1, status = CASE WHEN status = 'rejected' THEN 'pending' ELSE status END 
-                    WHERE user_id = $2
-                    RETURNING application_id, user_id, status, application_info, reviewed_by, admin_comments, created_at";
-
-                let updated_result = sqlx::query_as::<_, SponsorApplication>(update_query)
-                    .bind(&payload.application_info)
-                    .bind(&claims.id)
-                    .fetch_one(pool.get_ref())
-                    .await;
-
-                match updated_result {
-                    Ok(updated_application) => HttpResponse::Ok().json(updated_application),
-                    Err(_) => HttpResponse::InternalServerError().body("Failed to update application."),
-                }
+        let answers = match &payload.answers {
+            Some(answers) => match checked_answers(pool.get_ref(), Some(answers)).await {
+                Ok(answers) => answers,
+                Err(resp) => return resp,
+            },
+            None => None,
+        };
+
+        match revise_application(pool.get_ref(), user_id, payload.application_info.as_deref(), answers).await {
+            Ok(updated_application) => HttpResponse::Ok().json(updated_application),
+            Err(UpdateError::NotFound) => HttpResponse::NotFound().body("No sponsor application found."),
+            Err(UpdateError::Approved) => {
+                HttpResponse::Forbidden().body("You cannot update an approved application.")
+            }
+            Err(UpdateError::Cooldown(until)) => cooldown_response(until),
+            Err(UpdateError::Invalid(error)) => validation_error_response(&[error]),
+            Err(UpdateError::Database(e)) => {
+                eprintln!("Database error: {:?}", e);
+                HttpResponse::InternalServerError().body("Failed to update application.")
             }
-            Err(_) => HttpResponse::NotFound().body("No sponsor application found."),
         }
     } else {
         HttpResponse::Unauthorized().body("Authentication required")
     }
 }
 
+/// Withdraw the current application. It disappears for the applicant but is kept, with all its
+/// versions, for admins.
 pub async fn delete_sponsor_application(
     pool: web::Data<PgPool>,
     req: HttpRequest,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
-        let delete_query = "DELETE FROM sponsor_applications WHERE user_id = $1";
+        let user_id = match Uuid::parse_str(&claims.id) {
+            Ok(id) => id,
+            Err(_) => return HttpResponse::BadRequest().body("Invalid user ID format"),
+        };
+        let status_query = "SELECT status FROM sponsor_applications WHERE user_id = $1 AND withdrawn_at IS NULL";
+
+        let status: Result<Option<ApplicationStatus>, sqlx::Error> = sqlx::query_scalar(status_query)
+            .bind(user_id)
+            .fetch_optional(pool.get_ref())
+            .await;
+
+        match status {
+            Ok(Some(ApplicationStatus::Approved)) => {
+                return HttpResponse::Forbidden().body("You cannot withdraw an approved application.");
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => return HttpResponse::NotFound().body("No sponsor application found."),
+            Err(_) => return HttpResponse::InternalServerError().body("Failed to withdraw sponsor application."),
+        }
+
+        let withdraw_query = "
+            UPDATE sponsor_applications SET withdrawn_at = NOW()
+            WHERE user_id = $1 AND withdrawn_at IS NULL AND status <> 'approved'";
 
-        let result = sqlx::query(delete_query)
-            .bind(&claims.id)
+        let result = sqlx::query(withdraw_query)
+            .bind(user_id)
             .execute(pool.get_ref())
             .await;
 
         match result {
-            Ok(_) => HttpResponse::Ok().body("Sponsor application deleted successfully."),
-            Err(_) => HttpResponse::InternalServerError().body("Failed to delete sponsor application."),
+            Ok(_) => HttpResponse::Ok().body("Sponsor application withdrawn."),
+            Err(_) => HttpResponse::InternalServerError().body("Failed to withdraw sponsor application."),
         }
     } else {
         HttpResponse::Unauthorized().body("Authentication required")
@@ -156,9 +363,15 @@ pub async fn delete_sponsor_application(
 pub fn config_sponsor_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/sponsor") 
-            .route("/apply", web::post().to(submit_sponsor_application)) 
+            .service(
+                web::resource("/apply")
+                    .wrap(RequireRole(UserRole::Member))
+                    .route(web::post().to(submit_sponsor_application)),
+            )
+            .route("/questionnaire", web::get().to(get_sponsor_questionnaire))
             .route("/check", web::get().to(check_sponsor_application_status))
             .route("/update",web::patch().to(update_sponsor_application))
             .route("/delete",web::delete().to(delete_sponsor_application)) 
+            .configure(config_sponsor_document_routes)
     );
 }
@@ -0,0 +1,741 @@
+use crate::auth::logged_in_user_id;
+use crate::handlers::application_documents::{
+    ATTACHMENT_COLUMNS, MAX_ATTACHMENTS, MAX_REFERENCES, attachment_download, attachment_key, attachment_max_bytes,
+    clean_file_name, detect_attachment_type, hash_reference_token, reference_ttl_days, send_reference_request,
+    sha256_hex,
+};
+use crate::handlers::mailer::Mailer;
+use crate::handlers::sessions::generate_token;
+use crate::handlers::storage::StorageBackend;
+use crate::handlers::validation::{FieldError, validation_error_response};
+use crate::handlers::virus_scan::{ScanVerdict, VirusScanner};
+use crate::models::all_models::{ApplicationStatus, AttachmentKind, ScanStatus, SponsorApplicationAttachment};
+use actix_multipart::Multipart;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use chrono::NaiveDateTime;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use std::str::FromStr;
+use uuid::Uuid;
+
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_COMMENTS_LENGTH: usize = 5000;
+
+/// A reference as the applicant sees it; what the reference said is only for reviewers
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ReferenceSummary {
+    pub reference_id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub relationship: String,
+    pub sent_at: NaiveDateTime,
+    pub token_expires_at: Option<NaiveDateTime>,
+    pub responded_at: Option<NaiveDateTime>,
+}
+
+const REFERENCE_SUMMARY_COLUMNS: &str =
+    "reference_id, name, email, relationship, sent_at, token_expires_at, responded_at";
+
+#[derive(Debug, Deserialize)]
+pub struct NewReference {
+    pub name: String,
+    pub email: String,
+    /// How they know the applicant, e.g. "sponsor for 3 years"
+    pub relationship: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReferenceResponse {
+    pub recommends: bool,
+    pub known_for_years: Option<f32>,
+    pub comments: String,
+}
+
+/// What a reference sees before answering
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ReferenceForm {
+    pub applicant: String,
+    pub name: String,
+    pub relationship: String,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// The caller's current application
+#[derive(sqlx::FromRow)]
+struct CurrentApplication {
+    application_id: Uuid,
+    status: ApplicationStatus,
+    username: String,
+    email: String,
+}
+
+/// The caller's current application, or the response to send instead. With `editable`, an
+/// approved application counts as closed.
+async fn own_application(pool: &PgPool, req: &HttpRequest, editable: bool) -> Result<CurrentApplication, HttpResponse> {
+    let user_id = match logged_in_user_id(req) {
+        Some(id) => id,
+        None => return Err(HttpResponse::Unauthorized().body("Authentication required")),
+    };
+
+    let application = sqlx::query_as::<_, CurrentApplication>(
+        "SELECT a.application_id, a.status, u.username, u.email
+         FROM sponsor_applications a JOIN users u ON u.user_id = a.user_id
+         WHERE a.user_id = $1 AND a.withdrawn_at IS NULL",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await;
+
+    match application {
+        Ok(Some(application)) if editable && application.status == ApplicationStatus::Approved => {
+            Err(HttpResponse::Forbidden().body("You cannot change an approved application."))
+        }
+        Ok(Some(application)) => Ok(application),
+        Ok(None) => Err(HttpResponse::NotFound().body("No sponsor application found.")),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            Err(HttpResponse::InternalServerError().body("Failed to fetch application"))
+        }
+    }
+}
+
+struct Upload {
+    kind: AttachmentKind,
+    file_name: String,
+    bytes: Vec<u8>,
+}
+
+/// Lock the application and count its rows in `table`, so concurrent requests can't both pass a
+/// limit; call inside the transaction that inserts the new row
+async fn count_locked(conn: &mut PgConnection, application_id: Uuid, table: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query("SELECT 1 FROM sponsor_applications WHERE application_id = $1 FOR UPDATE")
+        .bind(application_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {} WHERE application_id = $1", table))
+        .bind(application_id)
+        .fetch_one(conn)
+        .await
+}
+
+/// Read the `file` field, and the optional `kind` field, of a multipart upload, stopping as soon
+/// as the file exceeds the size limit
+async fn read_attachment_upload(mut payload: Multipart) -> Result<Upload, HttpResponse> {
+    let max_bytes = attachment_max_bytes();
+    let mut kind = AttachmentKind::Other;
+    let mut file = None;
+
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|_| HttpResponse::BadRequest().body("Invalid multipart body"))?;
+        let name = field.name().map(str::to_string);
+        let limit = match name.as_deref() {
+            Some("file") => max_bytes,
+            Some("kind") => 64,
+            _ => continue,
+        };
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|_| HttpResponse::BadRequest().body("Invalid multipart body"))?;
+            if bytes.len() + chunk.len() > limit {
+                return Err(validation_error_response(&[FieldError::new(
+                    name.as_deref().unwrap_or_default(),
+                    "too_large",
+                    format!("Must be at most {} bytes", limit),
+                )]));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        if name.as_deref() == Some("kind") {
+            kind = AttachmentKind::from_str(String::from_utf8_lossy(&bytes).trim()).map_err(|_| {
+                validation_error_response(&[FieldError::new(
+                    "kind",
+                    "invalid_kind",
+                    "Must be certificate, background_check or other",
+                )])
+            })?;
+        } else {
+            let file_name = clean_file_name(field.content_disposition().and_then(|cd| cd.get_filename()));
+            file = Some((file_name, bytes));
+        }
+    }
+
+    match file {
+        Some((file_name, bytes)) if !bytes.is_empty() => Ok(Upload { kind, file_name, bytes }),
+        _ => Err(validation_error_response(&[FieldError::new("file", "required", "No file was uploaded")])),
+    }
+}
+
+/// Attach a certificate or background check, as the `file` field of a multipart form with an
+/// optional `kind`. Files are virus-scanned before they're stored; infected ones are refused.
+pub async fn upload_attachment(
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn StorageBackend>,
+    scanner: web::Data<dyn VirusScanner>,
+    req: HttpRequest,
+    payload: Multipart,
+) -> impl Responder {
+    let application = match own_application(pool.get_ref(), &req, true).await {
+        Ok(application) => application,
+        Err(resp) => return resp,
+    };
+
+    let upload = match read_attachment_upload(payload).await {
+        Ok(upload) => upload,
+        Err(resp) => return resp,
+    };
+    let Some(content_type) = detect_attachment_type(&upload.bytes) else {
+        return validation_error_response(&[FieldError::new(
+            "file",
+            "unsupported_type",
+            "Attachments must be PDF, JPEG or PNG files",
+        )]);
+    };
+
+    let scan_status = match scanner.scan(&upload.bytes).await {
+        Ok(ScanVerdict::Clean) => ScanStatus::Clean,
+        Ok(ScanVerdict::NotScanned) => ScanStatus::Unscanned,
+        Ok(ScanVerdict::Infected(signature)) => {
+            eprintln!(
+                "Refused infected attachment for application {}: {}",
+                application.application_id, signature
+            );
+            return validation_error_response(&[FieldError::new(
+                "file",
+                "infected",
+                "This file failed a virus scan and was not uploaded",
+            )]);
+        }
+        Err(e) => {
+            eprintln!("Virus scan failed: {}", e);
+            return HttpResponse::ServiceUnavailable().body("Attachments can't be checked right now; please try again later");
+        }
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to upload attachment");
+        }
+    };
+    match count_locked(&mut tx, application.application_id, "sponsor_application_attachments").await {
+        Ok(count) if count >= MAX_ATTACHMENTS => {
+            return HttpResponse::Conflict()
+                .body(format!("An application can have at most {} attachments", MAX_ATTACHMENTS));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to upload attachment");
+        }
+    }
+
+    let attachment_id = Uuid::new_v4();
+    let key = attachment_key(application.application_id, attachment_id);
+    let size_bytes = upload.bytes.len() as i64;
+    let sha256 = sha256_hex(&upload.bytes);
+    if let Err(e) = storage.put(&key, upload.bytes, content_type).await {
+        eprintln!("Failed to store attachment: {}", e);
+        return HttpResponse::InternalServerError().body("Failed to store attachment");
+    }
+
+    let insert_query = format!(
+        "INSERT INTO sponsor_application_attachments
+             (attachment_id, application_id, kind, file_name, content_type, size_bytes, sha256, storage_key,
+              scan_status, scanned_by)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+         RETURNING {}",
+        ATTACHMENT_COLUMNS
+    );
+    let result = sqlx::query_as::<_, SponsorApplicationAttachment>(&insert_query)
+        .bind(attachment_id)
+        .bind(application.application_id)
+        .bind(upload.kind)
+        .bind(&upload.file_name)
+        .bind(content_type)
+        .bind(size_bytes)
+        .bind(&sha256)
+        .bind(&key)
+        .bind(scan_status)
+        .bind((scan_status == ScanStatus::Clean).then(|| scanner.name()))
+        .fetch_one(&mut *tx)
+        .await;
+    let result = match result {
+        Ok(attachment) => tx.commit().await.map(|_| attachment),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(attachment) => HttpResponse::Created().json(attachment),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            if let Err(e) = storage.delete(&key).await {
+                eprintln!("Failed to delete attachment: {}", e);
+            }
+            HttpResponse::InternalServerError().body("Failed to upload attachment")
+        }
+    }
+}
+
+pub async fn list_attachments(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let application = match own_application(pool.get_ref(), &req, false).await {
+        Ok(application) => application,
+        Err(resp) => return resp,
+    };
+
+    let query = format!(
+        "SELECT {} FROM sponsor_application_attachments WHERE application_id = $1 ORDER BY uploaded_at",
+        ATTACHMENT_COLUMNS
+    );
+    let result = sqlx::query_as::<_, SponsorApplicationAttachment>(&query)
+        .bind(application.application_id)
+        .fetch_all(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(attachments) => HttpResponse::Ok().json(attachments),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch attachments")
+        }
+    }
+}
+
+pub async fn download_attachment(
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn StorageBackend>,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let application = match own_application(pool.get_ref(), &req, false).await {
+        Ok(application) => application,
+        Err(resp) => return resp,
+    };
+
+    let query = format!(
+        "SELECT {} FROM sponsor_application_attachments WHERE attachment_id = $1 AND application_id = $2",
+        ATTACHMENT_COLUMNS
+    );
+    let result = sqlx::query_as::<_, SponsorApplicationAttachment>(&query)
+        .bind(path.into_inner())
+        .bind(application.application_id)
+        .fetch_optional(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(Some(attachment)) => attachment_download(storage.get_ref(), &attachment).await,
+        Ok(None) => HttpResponse::NotFound().body("Attachment not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch attachment")
+        }
+    }
+}
+
+pub async fn delete_attachment(
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn StorageBackend>,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let application = match own_application(pool.get_ref(), &req, true).await {
+        Ok(application) => application,
+        Err(resp) => return resp,
+    };
+
+    let deleted: Result<Option<String>, sqlx::Error> = sqlx::query_scalar(
+        "DELETE FROM sponsor_application_attachments WHERE attachment_id = $1 AND application_id = $2
+         RETURNING storage_key",
+    )
+    .bind(path.into_inner())
+    .bind(application.application_id)
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    match deleted {
+        Ok(Some(key)) => {
+            if let Err(e) = storage.delete(&key).await {
+                eprintln!("Failed to delete attachment: {}", e);
+            }
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().body("Attachment not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to delete attachment")
+        }
+    }
+}
+
+fn check_reference(reference: &NewReference, applicant_email: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    for (field, value) in [("name", &reference.name), ("relationship", &reference.relationship)] {
+        let length = value.trim().chars().count();
+        if length == 0 || length > MAX_NAME_LENGTH {
+            errors.push(FieldError::new(
+                field,
+                "invalid_length",
+                format!("Must be between 1 and {} characters", MAX_NAME_LENGTH),
+            ));
+        }
+    }
+
+    let email = reference.email.trim();
+    if email.find('@').is_none_or(|at| at == 0 || at == email.len() - 1) {
+        errors.push(FieldError::new("email", "invalid", "Enter a valid email address"));
+    } else if email.eq_ignore_ascii_case(applicant_email) {
+        errors.push(FieldError::new("email", "self_reference", "You can't be your own reference"));
+    }
+    errors
+}
+
+/// Name a reference and email them a link to the reference form
+pub async fn add_reference(
+    pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    req: HttpRequest,
+    payload: web::Json<NewReference>,
+) -> impl Responder {
+    let application = match own_application(pool.get_ref(), &req, true).await {
+        Ok(application) => application,
+        Err(resp) => return resp,
+    };
+
+    let errors = check_reference(&payload, &application.email);
+    if !errors.is_empty() {
+        return validation_error_response(&errors);
+    }
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to add reference");
+        }
+    };
+    match count_locked(&mut tx, application.application_id, "sponsor_application_references").await {
+        Ok(count) if count >= MAX_REFERENCES => {
+            return HttpResponse::Conflict().body(format!("You can name at most {} references", MAX_REFERENCES));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to add reference");
+        }
+    }
+
+    let token = generate_token();
+    let insert_query = format!(
+        "INSERT INTO sponsor_application_references
+             (application_id, name, email, relationship, token_hash, token_expires_at)
+         VALUES ($1, $2, $3, $4, $5, NOW() + make_interval(days => $6))
+         RETURNING {}",
+        REFERENCE_SUMMARY_COLUMNS
+    );
+    let result = sqlx::query_as::<_, ReferenceSummary>(&insert_query)
+        .bind(application.application_id)
+        .bind(payload.name.trim())
+        .bind(payload.email.trim())
+        .bind(payload.relationship.trim())
+        .bind(hash_reference_token(&token))
+        .bind(reference_ttl_days())
+        .fetch_one(&mut *tx)
+        .await;
+    let result = match result {
+        Ok(reference) => tx.commit().await.map(|_| reference),
+        Err(e) => Err(e),
+    };
+
+    let reference = match result {
+        Ok(reference) => reference,
+        Err(sqlx::Error::Database(db)) if db.is_unique_violation() => {
+            return HttpResponse::Conflict().body("You have already named this reference");
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to add reference");
+        }
+    };
+
+    if let Err(e) = send_reference_request(
+        mailer.get_ref(),
+        &reference.name,
+        &reference.email,
+        &reference.relationship,
+        &application.username,
+        &token,
+    )
+    .await
+    {
+        eprintln!("Failed to send reference request email: {}", e);
+    }
+
+    HttpResponse::Created().json(reference)
+}
+
+pub async fn list_references(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let application = match own_application(pool.get_ref(), &req, false).await {
+        Ok(application) => application,
+        Err(resp) => return resp,
+    };
+
+    let query = format!(
+        "SELECT {} FROM sponsor_application_references WHERE application_id = $1 ORDER BY created_at",
+        REFERENCE_SUMMARY_COLUMNS
+    );
+    let result = sqlx::query_as::<_, ReferenceSummary>(&query)
+        .bind(application.application_id)
+        .fetch_all(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(references) => HttpResponse::Ok().json(references),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch references")
+        }
+    }
+}
+
+/// Email a fresh link to a reference who hasn't answered yet; the old link stops working.
+/// Limited to once a day per reference.
+pub async fn resend_reference(
+    pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let application = match own_application(pool.get_ref(), &req, true).await {
+        Ok(application) => application,
+        Err(resp) => return resp,
+    };
+    let reference_id = path.into_inner();
+
+    let current: Result<Option<(Option<NaiveDateTime>, bool)>, sqlx::Error> = sqlx::query_as(
+        "SELECT responded_at, sent_at > NOW() - INTERVAL '1 day' FROM sponsor_application_references
+         WHERE reference_id = $1 AND application_id = $2",
+    )
+    .bind(reference_id)
+    .bind(application.application_id)
+    .fetch_optional(pool.get_ref())
+    .await;
+    match current {
+        Ok(Some((Some(_), _))) => return HttpResponse::Conflict().body("This reference has already responded"),
+        Ok(Some((None, true))) => {
+            return HttpResponse::TooManyRequests().body("You can resend a reference request once a day");
+        }
+        Ok(Some((None, false))) => {}
+        Ok(None) => return HttpResponse::NotFound().body("Reference not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to resend reference request");
+        }
+    }
+
+    let token = generate_token();
+    let update_query = format!(
+        "UPDATE sponsor_application_references
+         SET token_hash = $1, token_expires_at = NOW() + make_interval(days => $2), sent_at = NOW()
+         WHERE reference_id = $3 AND responded_at IS NULL
+         RETURNING {}",
+        REFERENCE_SUMMARY_COLUMNS
+    );
+    let result = sqlx::query_as::<_, ReferenceSummary>(&update_query)
+        .bind(hash_reference_token(&token))
+        .bind(reference_ttl_days())
+        .bind(reference_id)
+        .fetch_optional(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(Some(reference)) => {
+            if let Err(e) = send_reference_request(
+                mailer.get_ref(),
+                &reference.name,
+                &reference.email,
+                &reference.relationship,
+                &application.username,
+                &token,
+            )
+            .await
+            {
+                eprintln!("Failed to send reference request email: {}", e);
+            }
+            HttpResponse::Ok().json(reference)
+        }
+        Ok(None) => HttpResponse::Conflict().body("This reference has already responded"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to resend reference request")
+        }
+    }
+}
+
+/// Drop a reference who hasn't answered; answered ones stay for the reviewer
+pub async fn remove_reference(pool: web::Data<PgPool>, req: HttpRequest, path: web::Path<Uuid>) -> impl Responder {
+    let application = match own_application(pool.get_ref(), &req, true).await {
+        Ok(application) => application,
+        Err(resp) => return resp,
+    };
+
+    let result: Result<Option<bool>, sqlx::Error> = sqlx::query_scalar(
+        "WITH target AS (
+             SELECT reference_id, responded_at IS NOT NULL AS responded FROM sponsor_application_references
+             WHERE reference_id = $1 AND application_id = $2
+         ), removed AS (
+             DELETE FROM sponsor_application_references
+             WHERE reference_id IN (SELECT reference_id FROM target WHERE NOT responded)
+         )
+         SELECT responded FROM target",
+    )
+    .bind(path.into_inner())
+    .bind(application.application_id)
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(Some(false)) => HttpResponse::NoContent().finish(),
+        Ok(Some(true)) => HttpResponse::Conflict().body("References who have responded can't be removed"),
+        Ok(None) => HttpResponse::NotFound().body("Reference not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to remove reference")
+        }
+    }
+}
+
+/// The reference form behind an emailed link; no login needed, the token is the credential
+pub async fn get_reference_form(pool: web::Data<PgPool>, path: web::Path<String>) -> impl Responder {
+    let result = sqlx::query_as::<_, ReferenceForm>(
+        "SELECT u.username AS applicant, r.name, r.relationship, r.token_expires_at AS expires_at
+         FROM sponsor_application_references r
+         JOIN sponsor_applications a ON a.application_id = r.application_id
+         JOIN users u ON u.user_id = a.user_id
+         WHERE r.token_hash = $1 AND r.token_expires_at > NOW() AND r.responded_at IS NULL
+           AND a.withdrawn_at IS NULL",
+    )
+    .bind(hash_reference_token(&path.into_inner()))
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(Some(form)) => HttpResponse::Ok().json(form),
+        Ok(None) => HttpResponse::NotFound().body("This link is invalid, has expired or has already been used"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch reference form")
+        }
+    }
+}
+
+/// Record a reference's answer against the application. Each link works once.
+pub async fn submit_reference_response(
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    payload: web::Json<ReferenceResponse>,
+) -> impl Responder {
+    let mut errors = Vec::new();
+    let comments = payload.comments.trim();
+    if comments.is_empty() || comments.chars().count() > MAX_COMMENTS_LENGTH {
+        errors.push(FieldError::new(
+            "comments",
+            "invalid_length",
+            format!("Must be between 1 and {} characters", MAX_COMMENTS_LENGTH),
+        ));
+    }
+    if payload.known_for_years.is_some_and(|years| !(0.0..=100.0).contains(&years)) {
+        errors.push(FieldError::new("known_for_years", "out_of_range", "Must be between 0 and 100"));
+    }
+    if !errors.is_empty() {
+        return validation_error_response(&errors);
+    }
+
+    // Clearing the token in the same statement keeps the link single-use
+    let result: Result<Option<Uuid>, sqlx::Error> = sqlx::query_scalar(
+        "UPDATE sponsor_application_references r
+         SET recommends = $1, known_for_years = $2, comments = $3, responded_at = NOW(),
+             token_hash = NULL, token_expires_at = NULL
+         FROM sponsor_applications a
+         WHERE a.application_id = r.application_id AND a.withdrawn_at IS NULL
+           AND r.token_hash = $4 AND r.token_expires_at > NOW() AND r.responded_at IS NULL
+         RETURNING r.reference_id",
+    )
+    .bind(payload.recommends)
+    .bind(payload.known_for_years)
+    .bind(comments)
+    .bind(hash_reference_token(&path.into_inner()))
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(Some(_)) => HttpResponse::Ok().body("Thank you, your reference has been recorded."),
+        Ok(None) => HttpResponse::NotFound().body("This link is invalid, has expired or has already been used"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to record reference")
+        }
+    }
+}
+
+/// Attachments and references; mounted inside the `/sponsor` scope
+pub fn config_sponsor_document_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/attachments", web::get().to(list_attachments))
+        .route("/attachments", web::post().to(upload_attachment))
+        .route("/attachments/{attachment_id}", web::get().to(download_attachment))
+        .route("/attachments/{attachment_id}", web::delete().to(delete_attachment))
+        .route("/references", web::get().to(list_references))
+        .route("/references", web::post().to(add_reference))
+        .route("/references/{reference_id}", web::delete().to(remove_reference))
+        .route("/references/{reference_id}/resend", web::post().to(resend_reference));
+}
+
+/// The reference form; mounted inside the public scope
+pub fn config_public_reference_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/sponsor-references/{token}", web::get().to(get_reference_form))
+        .route("/sponsor-references/{token}", web::post().to(submit_reference_response));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(name: &str, email: &str) -> NewReference {
+        NewReference { name: name.to_string(), email: email.to_string(), relationship: "home group".to_string() }
+    }
+
+    fn codes(errors: &[FieldError]) -> Vec<(&str, &str)> {
+        errors.iter().map(|e| (e.field.as_str(), e.code)).collect()
+    }
+
+    #[test]
+    fn a_valid_reference_passes() {
+        assert!(check_reference(&reference("Sam", "sam@example.org"), "applicant@example.org").is_empty());
+    }
+
+    #[test]
+    fn applicants_cannot_be_their_own_reference() {
+        let errors = check_reference(&reference("Me", "  Applicant@Example.ORG "), "applicant@example.org");
+        assert_eq!(codes(&errors), vec![("email", "self_reference")]);
+    }
+
+    #[test]
+    fn malformed_emails_are_rejected() {
+        for email in ["", "sam", "@example.org", "sam@", "  "] {
+            let errors = check_reference(&reference("Sam", email), "applicant@example.org");
+            assert_eq!(codes(&errors), vec![("email", "invalid")], "{:?}", email);
+        }
+    }
+
+    #[test]
+    fn names_must_be_given_and_not_too_long() {
+        let errors = check_reference(&reference("   ", "sam@example.org"), "applicant@example.org");
+        assert_eq!(codes(&errors), vec![("name", "invalid_length")]);
+
+        let long_name = "x".repeat(MAX_NAME_LENGTH + 1);
+        let errors = check_reference(&reference(&long_name, "sam@example.org"), "applicant@example.org");
+        assert_eq!(codes(&errors), vec![("name", "invalid_length")]);
+    }
+}
@@ -0,0 +1,493 @@
+use crate::auth::logged_in_user_id;
+use crate::handlers::application_documents::{ATTACHMENT_COLUMNS, REFERENCE_COLUMNS, attachment_download};
+use crate::handlers::notifications::notify_user;
+use crate::handlers::questionnaire::{AnswerFilters, known_questions, list_questionnaires, parse_answer_filters};
+use crate::handlers::sponsor_applications::{diff_versions, version_text};
+use crate::handlers::storage::StorageBackend;
+use crate::handlers::validation::validation_error_response;
+use crate::models::all_models::{
+    ApplicationStatus, SponsorApplication, SponsorApplicationAttachment, SponsorApplicationVersion, SponsorReference,
+};
+use crate::routes::admin::PaginationQuery;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const APPLICATION_COLUMNS: &str = "a.application_id, a.user_id, a.status, a.application_info, a.reviewed_by, \
+                                   a.admin_comments, a.created_at, a.reviewed_at, a.withdrawn_at, \
+                                   a.questionnaire_version, a.answers";
+
+#[derive(Debug, Deserialize)]
+pub struct ApplicationListQuery {
+    pub status: Option<ApplicationStatus>,
+    /// Start of the applicant's username, any case
+    pub username: Option<String>,
+    /// Only withdrawn (`true`) or only current (`false`) applications; both when left out
+    pub withdrawn: Option<bool>,
+    /// Questionnaire answers, e.g. `years-in-recovery>=2,program=aa`; every one must hold
+    pub answers: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// An application with enough about the people involved to review it
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ApplicationSummary {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub application: SponsorApplication,
+    pub username: String,
+    pub email: String,
+    pub applicant_role: String,
+    pub reviewer_username: Option<String>,
+    /// How many versions the applicant has submitted
+    pub versions: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewRequest {
+    /// Shown to the applicant; required unless approving
+    pub comments: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Decision {
+    Approve,
+    Reject,
+    RequestInfo,
+}
+
+impl Decision {
+    fn status(self) -> ApplicationStatus {
+        match self {
+            Decision::Approve => ApplicationStatus::Approved,
+            Decision::Reject => ApplicationStatus::Rejected,
+            Decision::RequestInfo => ApplicationStatus::NeedsInfo,
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            Decision::Approve => "Your sponsor application has been approved. Welcome aboard as a sponsor!",
+            Decision::Reject => "Your sponsor application has been rejected. See the reviewer's comments for details.",
+            Decision::RequestInfo => {
+                "Your sponsor application needs more information. Update it to send it back for review."
+            }
+        }
+    }
+}
+
+fn summary_query(filter: &str) -> String {
+    format!(
+        "SELECT {}, u.username, u.email, u.role::TEXT AS applicant_role, r.username AS reviewer_username,
+                (SELECT COUNT(*) FROM sponsor_application_versions v WHERE v.application_id = a.application_id)
+                    AS versions
+         FROM sponsor_applications a
+         JOIN users u ON u.user_id = a.user_id
+         LEFT JOIN users r ON r.user_id = a.reviewed_by
+         {}",
+        APPLICATION_COLUMNS, filter
+    )
+}
+
+/// Applications oldest first, so the review queue is worked in order
+pub async fn list_sponsor_applications(
+    pool: web::Data<PgPool>,
+    query: web::Query<ApplicationListQuery>,
+) -> impl Responder {
+    let page = PaginationQuery { limit: query.limit, offset: query.offset };
+    let username = query.username.as_deref().map(str::trim).filter(|name| !name.is_empty());
+
+    let filters = match query.answers.as_deref().map(str::trim).filter(|raw| !raw.is_empty()) {
+        Some(raw) => {
+            let questions = match list_questionnaires(pool.get_ref()).await {
+                Ok(questionnaires) => known_questions(questionnaires),
+                Err(e) => {
+                    eprintln!("Database error: {:?}", e);
+                    return HttpResponse::InternalServerError().body("Failed to fetch applications");
+                }
+            };
+            match parse_answer_filters(raw, &questions) {
+                Ok(filters) => filters,
+                Err(errors) => return validation_error_response(&errors),
+            }
+        }
+        None => AnswerFilters::default(),
+    };
+
+    // Years are compared as JSON numbers, so an answer of any other type never matches
+    let sql = summary_query(
+        "WHERE ($1::application_status IS NULL OR a.status = $1)
+           AND ($2::TEXT IS NULL OR strpos(lower(u.username), lower($2)) = 1)
+           AND ($3::BOOLEAN IS NULL OR (a.withdrawn_at IS NOT NULL) = $3)
+           AND ($4::JSONB IS NULL OR a.answers @> $4)
+           AND NOT EXISTS (
+               SELECT 1 FROM unnest($5::TEXT[], $6::TEXT[], $7::FLOAT8[]) f(key, op, value)
+               WHERE NOT COALESCE(jsonb_typeof(a.answers -> f.key) = 'number' AND CASE f.op
+                   WHEN '>=' THEN a.answers -> f.key >= to_jsonb(f.value)
+                   WHEN '<=' THEN a.answers -> f.key <= to_jsonb(f.value)
+                   WHEN '>' THEN a.answers -> f.key > to_jsonb(f.value)
+                   WHEN '<' THEN a.answers -> f.key < to_jsonb(f.value)
+                   ELSE a.answers -> f.key = to_jsonb(f.value)
+               END, FALSE))
+         ORDER BY a.created_at, a.application_id
+         LIMIT $8 OFFSET $9",
+    );
+    let result = sqlx::query_as::<_, ApplicationSummary>(&sql)
+        .bind(query.status)
+        .bind(username)
+        .bind(query.withdrawn)
+        .bind(&filters.contains)
+        .bind(&filters.number_keys)
+        .bind(&filters.number_ops)
+        .bind(&filters.number_values)
+        .bind(page.limit())
+        .bind(page.offset())
+        .fetch_all(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(applications) => HttpResponse::Ok().json(applications),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch applications")
+        }
+    }
+}
+
+pub async fn get_sponsor_application(pool: web::Data<PgPool>, path: web::Path<Uuid>) -> impl Responder {
+    let sql = summary_query("WHERE a.application_id = $1");
+    let result = sqlx::query_as::<_, ApplicationSummary>(&sql)
+        .bind(path.into_inner())
+        .fetch_optional(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(Some(application)) => HttpResponse::Ok().json(application),
+        Ok(None) => HttpResponse::NotFound().body("Application not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch application")
+        }
+    }
+}
+
+/// Why a review couldn't be recorded
+enum ReviewError {
+    NotFound,
+    AlreadyDecided(ApplicationStatus),
+    Withdrawn,
+    NotPromotable,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for ReviewError {
+    fn from(e: sqlx::Error) -> Self {
+        ReviewError::Database(e)
+    }
+}
+
+/// Record the decision; approving also makes the applicant a sponsor, in the same transaction
+async fn record_review(
+    pool: &PgPool,
+    application_id: Uuid,
+    admin_id: Uuid,
+    comments: Option<&str>,
+    decision: Decision,
+) -> Result<SponsorApplication, ReviewError> {
+    let mut tx = pool.begin().await?;
+
+    let current: Option<(Uuid, ApplicationStatus, Option<NaiveDateTime>)> = sqlx::query_as(
+        "SELECT user_id, status, withdrawn_at FROM sponsor_applications WHERE application_id = $1 FOR UPDATE",
+    )
+    .bind(application_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let (user_id, status, withdrawn_at) = current.ok_or(ReviewError::NotFound)?;
+    if withdrawn_at.is_some() {
+        return Err(ReviewError::Withdrawn);
+    }
+    if !matches!(status, ApplicationStatus::Pending | ApplicationStatus::NeedsInfo) {
+        return Err(ReviewError::AlreadyDecided(status));
+    }
+
+    if decision == Decision::Approve {
+        let promoted = sqlx::query(
+            "UPDATE users SET role = 'sponsor'
+             WHERE user_id = $1 AND role = 'member' AND deletion_scheduled_for IS NULL AND purged_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        if promoted.rows_affected() == 0 {
+            return Err(ReviewError::NotPromotable);
+        }
+    }
+
+    let sql = format!(
+        "UPDATE sponsor_applications a
+         SET status = $1, reviewed_by = $2, admin_comments = $3, reviewed_at = NOW()
+         WHERE application_id = $4
+         RETURNING {}",
+        APPLICATION_COLUMNS
+    );
+    let application = sqlx::query_as::<_, SponsorApplication>(&sql)
+        .bind(decision.status())
+        .bind(admin_id)
+        .bind(comments)
+        .bind(application_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(application)
+}
+
+async fn review_application(
+    pool: &PgPool,
+    req: &HttpRequest,
+    application_id: Uuid,
+    payload: ReviewRequest,
+    decision: Decision,
+) -> HttpResponse {
+    let admin_id = match logged_in_user_id(req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let comments = payload.comments.as_deref().map(str::trim).filter(|c| !c.is_empty());
+    if comments.is_none() && decision != Decision::Approve {
+        return HttpResponse::BadRequest().body("Comments are required when rejecting or asking for more information");
+    }
+
+    let application = match record_review(pool, application_id, admin_id, comments, decision).await {
+        Ok(application) => application,
+        Err(ReviewError::NotFound) => return HttpResponse::NotFound().body("Application not found"),
+        Err(ReviewError::AlreadyDecided(status)) => {
+            let status = status.to_string().to_lowercase();
+            return HttpResponse::Conflict().body(format!("Application has already been {}", status));
+        }
+        Err(ReviewError::Withdrawn) => {
+            return HttpResponse::Conflict().body("The applicant has withdrawn this application");
+        }
+        Err(ReviewError::NotPromotable) => {
+            return HttpResponse::Conflict().body("Only active members can be promoted to sponsor");
+        }
+        Err(ReviewError::Database(e)) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to review application");
+        }
+    };
+
+    let payload = json!({
+        "type": "sponsor_application",
+        "application_id": application.application_id,
+        "status": application.status,
+        "admin_comments": application.admin_comments,
+    });
+    notify_user(pool, application.user_id, decision.message(), payload).await;
+
+    HttpResponse::Ok().json(application)
+}
+
+pub async fn approve_sponsor_application(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    payload: web::Json<ReviewRequest>,
+) -> impl Responder {
+    review_application(pool.get_ref(), &req, path.into_inner(), payload.into_inner(), Decision::Approve).await
+}
+
+pub async fn reject_sponsor_application(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    payload: web::Json<ReviewRequest>,
+) -> impl Responder {
+    review_application(pool.get_ref(), &req, path.into_inner(), payload.into_inner(), Decision::Reject).await
+}
+
+pub async fn request_application_info(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    payload: web::Json<ReviewRequest>,
+) -> impl Responder {
+    review_application(pool.get_ref(), &req, path.into_inner(), payload.into_inner(), Decision::RequestInfo).await
+}
+
+/// Every version of an application, oldest first
+pub async fn list_application_versions(pool: web::Data<PgPool>, path: web::Path<Uuid>) -> impl Responder {
+    let result = sqlx::query_as::<_, SponsorApplicationVersion>(
+        "SELECT application_id, version, application_info, questionnaire_version, answers, created_at
+         FROM sponsor_application_versions WHERE application_id = $1 ORDER BY version",
+    )
+    .bind(path.into_inner())
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(versions) if versions.is_empty() => HttpResponse::NotFound().body("Application not found"),
+        Ok(versions) => HttpResponse::Ok().json(versions),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch versions")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    /// Defaults to the version before `to`
+    pub from: Option<i32>,
+    /// Defaults to the latest version
+    pub to: Option<i32>,
+}
+
+/// What changed between two versions, by default the latest resubmission against the one before
+pub async fn diff_application_versions(
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+    query: web::Query<DiffQuery>,
+) -> impl Responder {
+    let versions: Result<Vec<(i32, String, Option<Value>)>, sqlx::Error> = sqlx::query_as(
+        "SELECT version, application_info, answers FROM sponsor_application_versions
+         WHERE application_id = $1 ORDER BY version",
+    )
+    .bind(path.into_inner())
+    .fetch_all(pool.get_ref())
+    .await;
+
+    let versions = match versions {
+        Ok(versions) if versions.is_empty() => return HttpResponse::NotFound().body("Application not found"),
+        Ok(versions) => versions
+            .into_iter()
+            .map(|(version, info, answers)| (version, version_text(&info, answers.as_ref())))
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to fetch versions");
+        }
+    };
+
+    let latest = versions.last().map(|(version, _)| *version).unwrap_or(1);
+    let to = query.to.unwrap_or(latest);
+    let from = query.from.unwrap_or(to - 1);
+    let text = |wanted: i32| versions.iter().find(|(version, _)| *version == wanted).map(|(_, info)| info);
+
+    match (text(from), text(to)) {
+        (Some(old), Some(new)) if from != to => HttpResponse::Ok().json(diff_versions(from, old, to, new)),
+        (Some(_), Some(_)) => HttpResponse::BadRequest().body("Choose two different versions"),
+        _ if versions.len() == 1 && query.from.is_none() => {
+            HttpResponse::NotFound().body("This application has only one version")
+        }
+        _ => HttpResponse::NotFound().body("Version not found"),
+    }
+}
+
+pub async fn list_application_attachments(pool: web::Data<PgPool>, path: web::Path<Uuid>) -> impl Responder {
+    let query = format!(
+        "SELECT {} FROM sponsor_application_attachments WHERE application_id = $1 ORDER BY uploaded_at",
+        ATTACHMENT_COLUMNS
+    );
+    let result = sqlx::query_as::<_, SponsorApplicationAttachment>(&query)
+        .bind(path.into_inner())
+        .fetch_all(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(attachments) => HttpResponse::Ok().json(attachments),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch attachments")
+        }
+    }
+}
+
+pub async fn download_application_attachment(
+    pool: web::Data<PgPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> impl Responder {
+    let (application_id, attachment_id) = path.into_inner();
+    let query = format!(
+        "SELECT {} FROM sponsor_application_attachments WHERE attachment_id = $1 AND application_id = $2",
+        ATTACHMENT_COLUMNS
+    );
+    let result = sqlx::query_as::<_, SponsorApplicationAttachment>(&query)
+        .bind(attachment_id)
+        .bind(application_id)
+        .fetch_optional(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(Some(attachment)) => attachment_download(storage.get_ref(), &attachment).await,
+        Ok(None) => HttpResponse::NotFound().body("Attachment not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch attachment")
+        }
+    }
+}
+
+/// References with their answers, once given
+pub async fn list_application_references(pool: web::Data<PgPool>, path: web::Path<Uuid>) -> impl Responder {
+    let query = format!(
+        "SELECT {} FROM sponsor_application_references WHERE application_id = $1 ORDER BY created_at",
+        REFERENCE_COLUMNS
+    );
+    let result = sqlx::query_as::<_, SponsorReference>(&query)
+        .bind(path.into_inner())
+        .fetch_all(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(references) => HttpResponse::Ok().json(references),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch references")
+        }
+    }
+}
+
+/// Sponsor application review; mounted inside the `/admin` scope
+pub fn config_sponsor_review_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/sponsor-applications", web::get().to(list_sponsor_applications))
+        .route("/sponsor-applications/{application_id}", web::get().to(get_sponsor_application))
+        .route(
+            "/sponsor-applications/{application_id}/versions",
+            web::get().to(list_application_versions),
+        )
+        .route(
+            "/sponsor-applications/{application_id}/diff",
+            web::get().to(diff_application_versions),
+        )
+        .route(
+            "/sponsor-applications/{application_id}/attachments",
+            web::get().to(list_application_attachments),
+        )
+        .route(
+            "/sponsor-applications/{application_id}/attachments/{attachment_id}",
+            web::get().to(download_application_attachment),
+        )
+        .route(
+            "/sponsor-applications/{application_id}/references",
+            web::get().to(list_application_references),
+        )
+        .route(
+            "/sponsor-applications/{application_id}/approve",
+            web::post().to(approve_sponsor_application),
+        )
+        .route(
+            "/sponsor-applications/{application_id}/reject",
+            web::post().to(reject_sponsor_application),
+        )
+        .route(
+            "/sponsor-applications/{application_id}/request-info",
+            web::post().to(request_application_info),
+        );
+}
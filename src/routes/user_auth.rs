@@ -1,6 +1,31 @@
-use crate::auth::generate_jwt;
-use crate::handlers::password::{hash_password, verify_password};
-use actix_web::{HttpResponse, Responder, web};
+use crate::auth::{access_token_ttl, current_session, generate_jwt, generate_mfa_challenge, logged_in_user_id, mfa_challenge_ttl};
+use crate::handlers::account_deletion::cancel_account_deletion;
+use crate::handlers::avatars::avatar_url;
+use crate::handlers::email_verification::send_verification_email;
+use crate::config::env_or;
+use crate::handlers::auth_cookies::{
+    COOKIE_CONFIG, REFRESH_COOKIE, clear_session_cookies, csrf_check_passes, set_session_cookies,
+};
+use crate::handlers::mailer::{Mailer, OutgoingEmail, app_base_url};
+use crate::handlers::login_throttle::{check_login_allowed, client_ip, record_login_attempt};
+use crate::handlers::mfa::mfa_requirement;
+use crate::handlers::password_policy::validate_password;
+use crate::handlers::validation::validation_error_response;
+use crate::handlers::password::{dummy_verify_password, hash_password, rehash_if_needed, verify_password};
+use crate::routes::account::config_account_routes;
+use crate::routes::avatars::config_avatar_routes;
+use crate::routes::privacy::config_privacy_routes;
+use crate::routes::blocks::config_block_routes;
+use crate::routes::data_export::{config_export_routes, config_public_export_routes};
+use crate::routes::availability::config_availability_routes;
+use crate::routes::user_search::config_user_search_routes;
+use crate::routes::mfa::{config_protected_mfa_routes, config_public_mfa_routes};
+use crate::routes::sessions::config_session_routes;
+use crate::handlers::sessions::{
+    RefreshOutcome, SessionClient, create_session, revoke_all_sessions, revoke_session, rotate_refresh_token,
+};
+use crate::middleware::impersonation_guard::DenyImpersonation;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use chrono::{NaiveDate, NaiveDateTime};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
@@ -22,13 +47,17 @@ pub struct CreatedUserResponse {
 
 pub async fn create_user(
     pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
     payload: web::Json<CreateUserRequest>,
 ) -> impl Responder {
-    // Create a formatted avatar URL based on the username
-    let avatar_url = format!(
-        "https://ui-avatars.com/api/?name={}&background=random",
-        payload.username
-    );
+    // Served by our avatar route, which draws the user's initials until they upload one
+    let user_id = Uuid::new_v4();
+    let avatar_url = avatar_url(user_id, None);
+
+    let errors = validate_password("password", &payload.password, &payload.username, &payload.email).await;
+    if !errors.is_empty() {
+        return validation_error_response(&errors);
+    }
 
     // Hash the password using your custom hash_password function
     let password_hash = match hash_password(&payload.password) {
@@ -37,22 +66,38 @@ pub async fn create_user(
     };
 
     let user_profile = "Nothing to see here...";
+    let verification_token = Uuid::new_v4();
 
-    let query = "INSERT INTO users (username, email, password_hash, dob, avatar_url, user_profile) \
-                 VALUES ($1, $2, $3, $4, $5, $6) RETURNING user_id, username, avatar_url";
+    let query = "INSERT INTO users (user_id, username, email, password_hash, dob, avatar_url, user_profile, email_verification_token) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING user_id, username, avatar_url";
 
     let result = sqlx::query_as::<_, CreatedUserResponse>(query)
+        .bind(user_id)
         .bind(&payload.username)
         .bind(&payload.email)
         .bind(password_hash)
         .bind(payload.dob)
         .bind(&avatar_url)
         .bind(user_profile)
+        .bind(verification_token)
         .fetch_one(pool.get_ref())
         .await;
 
     match result {
-        Ok(record) => HttpResponse::Ok().json(record),
+        Ok(record) => {
+            // A failed email shouldn't fail registration; the user can ask for a resend
+            if let Err(e) = send_verification_email(
+                mailer.get_ref(),
+                &payload.email,
+                &payload.username,
+                verification_token,
+            )
+            .await
+            {
+                eprintln!("Failed to send verification email: {}", e);
+            }
+            HttpResponse::Ok().json(record)
+        }
         Err(e) => {
             eprintln!("Database error: {:?}", e);
             HttpResponse::InternalServerError().json("Error creating user")
@@ -73,7 +118,8 @@ struct UserAuth {
     pub password_hash: String,
     pub avatar_url: String,
     pub role:String,
-    pub banned_until:Option<NaiveDateTime>
+    pub banned_until:Option<NaiveDateTime>,
+    pub deletion_scheduled_for: Option<NaiveDateTime>,
 }
 
 #[derive(Serialize)]
@@ -81,61 +127,591 @@ pub struct LoginResponse {
     pub user_id: Uuid,
     pub username: String,
     pub avatar_url: String,
-    pub token: String,
+    /// Omitted in cookie mode, where tokens are only sent as HTTP-only cookies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub expires_in: i64,
+    /// Only present right after two-factor enrolment completes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+}
+
+/// Returned instead of tokens when the password was right but a second factor is needed
+#[derive(Serialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    /// The user's role requires 2FA but they haven't set it up yet
+    pub enrollment_required: bool,
+    pub challenge_token: String,
+    pub expires_in: i64,
+}
+
+/// Open a session for a fully authenticated user and hand out its tokens
+pub(crate) async fn complete_login(
+    pool: &PgPool,
+    user_id: Uuid,
+    username: String,
+    role: &str,
+    avatar_url: String,
+    recovery_codes: Option<Vec<String>>,
+    client: &SessionClient,
+) -> HttpResponse {
+    let (session_id, refresh_token) = match create_session(pool, user_id, client).await {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Session creation error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Error logging in");
+        }
+    };
+
+    let token = match generate_jwt(&user_id.to_string(), &username, role, &session_id.to_string()) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Token generation error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Token generation failed");
+        }
+    };
+
+    let cookie_mode = COOKIE_CONFIG.enabled;
+    let mut builder = HttpResponse::Ok();
+    if cookie_mode {
+        set_session_cookies(&mut builder, &token, &refresh_token);
+    }
+
+    let response = LoginResponse {
+        user_id,
+        username,
+        avatar_url,
+        token: (!cookie_mode).then_some(token),
+        refresh_token: (!cookie_mode).then_some(refresh_token),
+        expires_in: access_token_ttl().num_seconds(),
+        recovery_codes,
+    };
+
+    builder.json(response)
 }
 
-pub async fn login(pool: web::Data<PgPool>, payload: web::Json<LoginRequest>) -> impl Responder {
+pub async fn login(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<LoginRequest>,
+) -> impl Responder {
+    let ip = client_ip(&req);
+
+    match check_login_allowed(pool.get_ref(), &payload.username, &ip).await {
+        Ok(None) => {}
+        Ok(Some(retry_after)) => {
+            return HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .body("Too many failed login attempts. Please try again later.");
+        }
+        Err(e) => {
+            eprintln!("Login throttle error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Error logging in");
+        }
+    }
+
     // Query the user by username and fetch necessary fields
     let query = "
-        SELECT user_id, username, password_hash, avatar_url, role, banned_until 
-        FROM users WHERE username = $1";
+        SELECT user_id, username, password_hash, avatar_url, role::TEXT AS role, banned_until,
+               deletion_scheduled_for
+        FROM users WHERE username = $1 AND purged_at IS NULL";
     
     let user = sqlx::query_as::<_, UserAuth>(query)
         .bind(&payload.username)
-        .fetch_one(pool.get_ref())
+        .fetch_optional(pool.get_ref())
         .await;
 
-    match user {
-        Ok(user) => {
-            // Check if the user is banned
-            if let Some(banned_until) = user.banned_until {
-                if banned_until > chrono::Utc::now().naive_utc() {
-                    return HttpResponse::Forbidden().body("Your account is currently banned.");
-                }
+    let user = match user {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            // Same work and same answer as a wrong password, so usernames can't be enumerated
+            dummy_verify_password(&payload.password);
+            if let Err(e) = record_login_attempt(pool.get_ref(), &payload.username, &ip, None, false).await {
+                eprintln!("Failed to record login attempt: {:?}", e);
+            }
+            return HttpResponse::Unauthorized().body("Invalid credentials");
+        }
+        Err(e) => {
+            eprintln!("Error retrieving user: {:?}", e);
+            return HttpResponse::InternalServerError().body("Error logging in");
+        }
+    };
+
+    // Verify password
+    let verified = match verify_password(&payload.password, &user.password_hash) {
+        Ok(r) => r,
+        Err(_) => {
+            return HttpResponse::InternalServerError().body("Error Verifying Password!");
+        }
+    };
+
+    if !verified {
+        if let Err(e) = record_login_attempt(pool.get_ref(), &payload.username, &ip, Some(user.user_id), false).await {
+            eprintln!("Failed to record login attempt: {:?}", e);
+        }
+        return HttpResponse::Unauthorized().body("Invalid credentials");
+    }
+    rehash_if_needed(pool.get_ref(), user.user_id, &payload.password, &user.password_hash).await;
+
+    // Check if the user is banned
+    if let Some(banned_until) = user.banned_until
+        && banned_until > chrono::Utc::now().naive_utc()
+    {
+        return HttpResponse::Forbidden().body("Your account is currently banned.");
+    }
+
+    if let Some(scheduled_for) = user.deletion_scheduled_for {
+        return HttpResponse::Forbidden().body(format!(
+            "This account is scheduled for deletion on {}. Restore it to log in again.",
+            scheduled_for.format("%Y-%m-%d")
+        ));
+    }
+
+    // With 2FA the attempt only counts as a success once the second factor is checked
+    let mfa = match mfa_requirement(pool.get_ref(), user.user_id, &user.role).await {
+        Ok(mfa) => mfa,
+        Err(e) => {
+            eprintln!("MFA lookup error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Error logging in");
+        }
+    };
+    if mfa.enrolled || mfa.required {
+        return match generate_mfa_challenge(&user.user_id.to_string(), &user.username, &user.role) {
+            Ok(challenge_token) => HttpResponse::Ok().json(MfaChallengeResponse {
+                mfa_required: true,
+                enrollment_required: !mfa.enrolled,
+                challenge_token,
+                expires_in: mfa_challenge_ttl().num_seconds(),
+            }),
+            Err(e) => {
+                eprintln!("Token generation error: {:?}", e);
+                HttpResponse::InternalServerError().body("Token generation failed")
             }
+        };
+    }
+
+    if let Err(e) = record_login_attempt(pool.get_ref(), &payload.username, &ip, Some(user.user_id), true).await {
+        eprintln!("Failed to record login attempt: {:?}", e);
+    }
+
+    let client = SessionClient::from_request(&req);
+    complete_login(pool.get_ref(), user.user_id, user.username, &user.role, user.avatar_url, None, &client).await
+}
+
+/// Cancel a scheduled deletion. Takes the same credentials as login, and counts towards the same
+/// throttling, but doesn't log in.
+pub async fn restore_account(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: web::Json<LoginRequest>,
+) -> impl Responder {
+    let ip = client_ip(&req);
+
+    match check_login_allowed(pool.get_ref(), &payload.username, &ip).await {
+        Ok(None) => {}
+        Ok(Some(retry_after)) => {
+            return HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .body("Too many failed login attempts. Please try again later.");
+        }
+        Err(e) => {
+            eprintln!("Login throttle error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to restore account");
+        }
+    }
+
+    let user: Result<Option<(Uuid, String)>, sqlx::Error> =
+        sqlx::query_as("SELECT user_id, password_hash FROM users WHERE username = $1 AND purged_at IS NULL")
+            .bind(&payload.username)
+            .fetch_optional(pool.get_ref())
+            .await;
+
+    let (user_id, verified) = match user {
+        Ok(Some((user_id, password_hash))) => {
+            (Some(user_id), verify_password(&payload.password, &password_hash).unwrap_or(false))
+        }
+        Ok(None) => {
+            dummy_verify_password(&payload.password);
+            (None, false)
+        }
+        Err(e) => {
+            eprintln!("Error retrieving user: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to restore account");
+        }
+    };
+
+    let (Some(user_id), true) = (user_id, verified) else {
+        if let Err(e) = record_login_attempt(pool.get_ref(), &payload.username, &ip, user_id, false).await {
+            eprintln!("Failed to record login attempt: {:?}", e);
+        }
+        return HttpResponse::Unauthorized().body("Invalid credentials");
+    };
+
+    match cancel_account_deletion(pool.get_ref(), user_id).await {
+        Ok(true) => HttpResponse::Ok().body("Account restored. You can log in again."),
+        Ok(false) => HttpResponse::BadRequest().body("This account is not scheduled for deletion"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to restore account")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct RefreshResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub expires_in: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct SessionUser {
+    pub username: String,
+    pub role: String,
+    pub banned_until: Option<NaiveDateTime>,
+}
 
-            // Verify password
-            let verified = match verify_password(&payload.password, &user.password_hash) {
-                Ok(r) => r,
-                Err(_) => {
-                    return HttpResponse::InternalServerError().body("Error Verifying Password!");
+/// Exchange a refresh token for a new access token. The refresh token is rotated on every use.
+/// In cookie mode the token may come from the refresh cookie instead of the body.
+pub async fn refresh_session(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    payload: Option<web::Json<RefreshRequest>>,
+) -> impl Responder {
+    let presented = match payload {
+        Some(body) => body.into_inner().refresh_token,
+        None if COOKIE_CONFIG.enabled => match req.cookie(REFRESH_COOKIE) {
+            Some(cookie) => {
+                if !csrf_check_passes(&req) {
+                    return HttpResponse::Forbidden().body("Missing or invalid CSRF token");
                 }
-            };
-
-            if verified {
-                let token = match generate_jwt(&user.user_id.to_string(), &user.username, &user.role) {
-                    Ok(t) => t,
-                    Err(e) => {
-                        eprintln!("Token generation error: {:?}", e);
-                        return HttpResponse::InternalServerError().body("Token generation failed");
-                    }
-                };
+                cookie.value().to_string()
+            }
+            None => return HttpResponse::Unauthorized().body("Missing refresh token"),
+        },
+        None => return HttpResponse::BadRequest().body("Missing refresh token"),
+    };
+
+    let (session_id, user_id, refresh_token) =
+        match rotate_refresh_token(pool.get_ref(), &presented).await {
+            Ok(RefreshOutcome::Rotated {
+                session_id,
+                user_id,
+                refresh_token,
+            }) => (session_id, user_id, refresh_token),
+            Ok(RefreshOutcome::Reused) => {
+                return HttpResponse::Unauthorized()
+                    .body("Refresh token was already used; the session has been revoked");
+            }
+            Ok(RefreshOutcome::Invalid) => {
+                return HttpResponse::Unauthorized().body("Invalid or expired refresh token");
+            }
+            Err(e) => {
+                eprintln!("Session refresh error: {:?}", e);
+                return HttpResponse::InternalServerError().body("Failed to refresh session");
+            }
+        };
+
+    let user = sqlx::query_as::<_, SessionUser>(
+        "SELECT username, role::TEXT AS role, banned_until FROM users WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_one(pool.get_ref())
+    .await;
+
+    let user = match user {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Error retrieving user: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to refresh session");
+        }
+    };
+
+    if let Some(banned_until) = user.banned_until
+        && banned_until > chrono::Utc::now().naive_utc()
+    {
+        let _ = revoke_session(pool.get_ref(), user_id, session_id).await;
+        return HttpResponse::Forbidden().body("Your account is currently banned.");
+    }
+
+    match generate_jwt(&user_id.to_string(), &user.username, &user.role, &session_id.to_string()) {
+        Ok(token) => {
+            let cookie_mode = COOKIE_CONFIG.enabled;
+            let mut builder = HttpResponse::Ok();
+            if cookie_mode {
+                set_session_cookies(&mut builder, &token, &refresh_token);
+            }
+            builder.json(RefreshResponse {
+                token: (!cookie_mode).then_some(token),
+                refresh_token: (!cookie_mode).then_some(refresh_token),
+                expires_in: access_token_ttl().num_seconds(),
+            })
+        }
+        Err(e) => {
+            eprintln!("Token generation error: {:?}", e);
+            HttpResponse::InternalServerError().body("Token generation failed")
+        }
+    }
+}
+
+pub async fn logout(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let (user_id, session_id) = match current_session(&req) {
+        Some((user_id, Some(session_id))) => (user_id, session_id),
+        Some(_) => return HttpResponse::BadRequest().body("Invalid session"),
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    match revoke_session(pool.get_ref(), user_id, session_id).await {
+        Ok(_) => {
+            let mut builder = HttpResponse::Ok();
+            clear_session_cookies(&mut builder);
+            builder.body("Logged out")
+        }
+        Err(e) => {
+            eprintln!("Session revoke error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to log out")
+        }
+    }
+}
+
+pub async fn logout_all(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    match revoke_all_sessions(pool.get_ref(), user_id).await {
+        Ok(_) => {
+            let mut builder = HttpResponse::Ok();
+            clear_session_cookies(&mut builder);
+            builder.body("Logged out of all sessions")
+        }
+        Err(e) => {
+            eprintln!("Session revoke error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to log out")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: Uuid,
+}
+
+pub async fn verify_email(
+    pool: web::Data<PgPool>,
+    payload: web::Json<VerifyEmailRequest>,
+) -> impl Responder {
+    let query = "
+        UPDATE users SET email_verified = TRUE, email_verification_token = NULL
+        WHERE email_verification_token = $1";
+
+    let result = sqlx::query(query)
+        .bind(payload.token)
+        .execute(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() > 0 => HttpResponse::Ok().body("Email verified successfully"),
+        Ok(_) => HttpResponse::BadRequest().body("Invalid or expired verification token"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to verify email")
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct VerificationTarget {
+    pub username: String,
+    pub email: String,
+    pub email_verified: Option<bool>,
+}
+
+pub async fn resend_verification_email(
+    pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let target = sqlx::query_as::<_, VerificationTarget>(
+        "SELECT username, email, email_verified FROM users WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_one(pool.get_ref())
+    .await;
+
+    let target = match target {
+        Ok(t) => t,
+        Err(_) => return HttpResponse::NotFound().body("User not found"),
+    };
+
+    if target.email_verified == Some(true) {
+        return HttpResponse::Conflict().body("Email is already verified");
+    }
+
+    let token = Uuid::new_v4();
+    let update = sqlx::query("UPDATE users SET email_verification_token = $1 WHERE user_id = $2")
+        .bind(token)
+        .bind(user_id)
+        .execute(pool.get_ref())
+        .await;
+
+    if let Err(e) = update {
+        eprintln!("Database error: {:?}", e);
+        return HttpResponse::InternalServerError().body("Failed to issue verification token");
+    }
+
+    match send_verification_email(mailer.get_ref(), &target.email, &target.username, token).await {
+        Ok(_) => HttpResponse::Ok().body("Verification email sent"),
+        Err(e) => {
+            eprintln!("Failed to send verification email: {}", e);
+            HttpResponse::InternalServerError().body("Failed to send verification email")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct PasswordResetTarget {
+    pub username: String,
+    pub email: String,
+    pub forgot_password_token: Uuid,
+}
+
+/// Issue a reset token if the email belongs to an account. The response is identical either way
+/// so the endpoint can't be used to discover registered emails.
+pub async fn request_password_reset(
+    pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    payload: web::Json<ForgotPasswordRequest>,
+) -> impl Responder {
+    let ttl_minutes: i64 = env_or("PASSWORD_RESET_TTL_MINUTES", 60);
+
+    let query = "
+        UPDATE users
+        SET forgot_password_token = gen_random_uuid(),
+            forgot_password_expires_at = NOW() + make_interval(mins => $2)
+        WHERE lower(email) = lower($1)
+        RETURNING username, email, forgot_password_token";
 
-                let response = LoginResponse {
-                    user_id: user.user_id,
-                    username: user.username,
-                    avatar_url: user.avatar_url,
-                    token,
+    let result = sqlx::query_as::<_, PasswordResetTarget>(query)
+        .bind(payload.email.trim())
+        .bind(ttl_minutes as i32)
+        .fetch_optional(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(Some(target)) => {
+            // Send in the background so response time doesn't depend on whether the account exists
+            let mailer = mailer.into_inner();
+            actix_web::rt::spawn(async move {
+                let link = format!(
+                    "{}/reset-password?token={}",
+                    app_base_url(),
+                    target.forgot_password_token
+                );
+                let email = OutgoingEmail {
+                    to: target.email,
+                    subject: "Reset your password".to_string(),
+                    body: format!(
+                        "Hi {},\n\nWe received a request to reset your password. The link below is valid for {} minutes and can only be used once:\n\n{}\n\nIf you did not request this, you can ignore this email.",
+                        target.username, ttl_minutes, link
+                    ),
                 };
+                if let Err(e) = mailer.send(email).await {
+                    eprintln!("Failed to send password reset email: {}", e);
+                }
+            });
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to request password reset");
+        }
+    }
+
+    HttpResponse::Ok().body("If an account exists for that email, a reset link has been sent.")
+}
 
-                HttpResponse::Ok().json(response)
-            } else {
-                HttpResponse::Unauthorized().body("Invalid credentials")
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: Uuid,
+    pub new_password: String,
+}
+
+pub async fn reset_password(
+    pool: web::Data<PgPool>,
+    payload: web::Json<ResetPasswordRequest>,
+) -> impl Responder {
+    let account: Result<Option<(String, String)>, sqlx::Error> = sqlx::query_as(
+        "SELECT username, email FROM users WHERE forgot_password_token = $1 AND forgot_password_expires_at > NOW()",
+    )
+    .bind(payload.token)
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    let (username, email) = match account {
+        Ok(Some(account)) => account,
+        Ok(None) => return HttpResponse::BadRequest().body("Invalid or expired reset token"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to reset password");
+        }
+    };
+
+    let errors = validate_password("new_password", &payload.new_password, &username, &email).await;
+    if !errors.is_empty() {
+        return validation_error_response(&errors);
+    }
+
+    let password_hash = match hash_password(&payload.new_password) {
+        Ok(hash) => hash,
+        Err(_) => return HttpResponse::InternalServerError().body("Failed to hash password"),
+    };
+
+    // Consuming the token in the same statement keeps it single-use
+    let query = "
+        UPDATE users
+        SET password_hash = $1, forgot_password_token = NULL, forgot_password_expires_at = NULL
+        WHERE forgot_password_token = $2 AND forgot_password_expires_at > NOW()
+        RETURNING user_id";
+
+    let result: Result<Option<Uuid>, sqlx::Error> = sqlx::query_scalar(query)
+        .bind(password_hash)
+        .bind(payload.token)
+        .fetch_optional(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(Some(user_id)) => {
+            if let Err(e) = revoke_all_sessions(pool.get_ref(), user_id).await {
+                eprintln!("Failed to revoke sessions: {:?}", e);
             }
+            HttpResponse::Ok().body("Password has been reset")
         }
+        Ok(None) => HttpResponse::BadRequest().body("Invalid or expired reset token"),
         Err(e) => {
-            eprintln!("Error retrieving user: {:?}", e);
-            HttpResponse::InternalServerError().body("Error logging in")
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to reset password")
         }
     }
 }
@@ -145,6 +721,33 @@ pub fn config_user_auth_routes(cfg: &mut web::ServiceConfig) {
         web::scope("/users") 
             .route("/create", web::post().to(create_user)) 
             .route("/login", web::post().to(login)) 
+            .route("/restore", web::post().to(restore_account))
+            .route("/verify-email", web::post().to(verify_email))
+            .route("/forgot-password", web::post().to(request_password_reset))
+            .route("/reset-password", web::post().to(reset_password))
+            .route("/refresh", web::post().to(refresh_session))
+            .configure(config_public_mfa_routes)
+            .configure(config_public_export_routes)
     );
 }
 
+/// Account routes that need a logged-in user; mounted inside the protected `/users` scope
+pub fn config_protected_user_auth_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/resend-verification")
+            .wrap(DenyImpersonation)
+            .route(web::post().to(resend_verification_email)),
+    )
+    .route("/logout", web::post().to(logout))
+    .service(web::resource("/logout-all").wrap(DenyImpersonation).route(web::post().to(logout_all)))
+        .configure(config_protected_mfa_routes)
+        .configure(config_account_routes)
+        .configure(config_session_routes)
+        .configure(config_avatar_routes)
+        .configure(config_privacy_routes)
+        .configure(config_block_routes)
+        .configure(config_export_routes)
+        .configure(config_user_search_routes)
+        .configure(config_availability_routes);
+}
+
@@ -1,8 +1,16 @@
 use actix_web::{HttpRequest, HttpResponse, Responder, web, HttpMessage};
 use sqlx::PgPool;
-use crate::auth::Claims;
-use crate::models::all_models::UserRole;
-use serde_json::Value;
+use crate::auth::{Claims, logged_in_user_id};
+use crate::routes::user_auth::config_protected_user_auth_routes;
+use crate::handlers::account_deletion::schedule_account_deletion;
+use crate::handlers::auth_cookies::clear_session_cookies;
+use crate::handlers::mailer::{Mailer, OutgoingEmail};
+use crate::handlers::profile_view::{PROFILE_COLUMNS, ProfileRow, Viewer, profile_views};
+use crate::handlers::profile_fields::{normalize_location, parse_languages, resolve_tags};
+use crate::handlers::validation::validation_error_response;
+use crate::middleware::impersonation_guard::DenyImpersonation;
+use crate::models::all_models::{Location, ProfileTag, ProfileTagKind, UserRole};
+use sqlx::types::Json;
 use uuid::Uuid;
 use chrono::{NaiveDate,NaiveDateTime};                                                                                                                                                                                                                                          
 use serde::{Deserialize,Serialize};
@@ -19,19 +27,18 @@ struct UserInfo {
     pub bio: Option<String>,
     pub email_verified: bool,
     pub banned_until: Option<NaiveDateTime>,
-    pub location: Option<Value>,
+    pub location: Option<Json<Location>>,
     pub interests: Option<Vec<String>>,
     pub experience: Option<Vec<String>>,
-    pub available_days: Option<Vec<String>>,
     pub languages: Option<Vec<String>>,
-    pub privacy: bool,
+    pub timezone: String,
 }
 pub async fn get_logged_in_user_info(pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
         let query = "
             SELECT user_id, username, role, avatar_url, created_at, dob, user_profile, 
                    bio, email_verified, banned_until, location, interests, experience, 
-                   available_days, languages, privacy 
+                   languages, timezone 
             FROM users WHERE user_id = $1";
 
         let user_result = sqlx::query_as::<_, UserInfo>(query)
@@ -49,75 +56,48 @@ pub async fn get_logged_in_user_info(pool: web::Data<PgPool>, req: HttpRequest)
 }
 
 
-#[derive(Serialize, Deserialize,sqlx::FromRow)] 
-struct PublicUserInfo {
-    username: String,
-    role: String,
-    avatar_url: String,
-    user_profile: String,
-    bio: Option<String>,
-    interests: Option<Vec<String>>,
-    experience: Option<Vec<String>>,
-    languages: Option<Vec<String>>,
-}
-
-#[derive(Serialize, Deserialize,sqlx::FromRow)]
-
-struct PrivateUserInfo {
-    username: String,
-    role: String,
-    avatar_url: String,
-}
-
-#[derive(sqlx::FromRow)] 
-struct UserPrivacyCheck {
-    privacy: bool,
-}
-
+/// Another user's profile, showing only the fields their visibility settings allow this viewer
 async fn get_user_by_name(
     pool: web::Data<PgPool>,
+    req: HttpRequest,
     path: web::Path<String>, 
 ) -> impl Responder {
     let username = path.into_inner();
 
-    let privacy_query = "SELECT privacy FROM users WHERE username = $1";
-    let privacy_result = sqlx::query_as::<_, UserPrivacyCheck>(privacy_query)
+    let query = format!(
+        "SELECT {} FROM users WHERE username = $1 AND deletion_scheduled_for IS NULL AND purged_at IS NULL",
+        PROFILE_COLUMNS
+    );
+    let user_result = sqlx::query_as::<_, ProfileRow>(&query)
         .bind(&username)
-        .fetch_one(pool.get_ref())
+        .fetch_optional(pool.get_ref())
         .await;
 
-    match privacy_result {
-        Ok(privacy_data) => {
-            if privacy_data.privacy {
-                
-                let private_query = "SELECT username, role, avatar_url FROM users WHERE username = $1";
-                
-                let private_user_result = sqlx::query_as::<_, PrivateUserInfo>(private_query)
-                    .bind(&username)
-                    .fetch_one(pool.get_ref())
-                    .await;
-
-                return match private_user_result {
-                    Ok(private_user) => HttpResponse::Ok().json(private_user),
-                    Err(_) => HttpResponse::NotFound().body("User not found"),
-                };
-            }
-
-            let query = "
-                SELECT username, role, avatar_url, user_profile, bio, interests, experience, languages
-                FROM users WHERE username = $1";
+    let row = match user_result {
+        Ok(Some(row)) => row,
+        Ok(None) => return HttpResponse::NotFound().body("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to fetch user");
+        }
+    };
 
-            let user_result = sqlx::query_as::<_, PublicUserInfo>(query)
-                .bind(&username)
-                .fetch_one(pool.get_ref())
-                .await;
+    let viewer = match Viewer::from_request(pool.get_ref(), &req).await {
+        Ok(viewer) => viewer,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to fetch user");
+        }
+    };
 
-            match user_result {
-                Ok(user) => HttpResponse::Ok().json(user),
-                Err(_) => HttpResponse::NotFound().body("User not found"),
-            }
-        },
-        Err(_) => HttpResponse::NotFound().body("User not found"),
+    // Blocked viewers get the same answer as for a user that doesn't exist
+    match profile_views(pool.get_ref(), &viewer, vec![row]).await {
+        Ok(mut views) if !views.is_empty() => HttpResponse::Ok().json(views.remove(0)),
+        Ok(_) => HttpResponse::NotFound().body("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch user")
+        }
     }
 }
 
@@ -127,24 +107,22 @@ async fn get_user_by_name(
 pub struct UpdateUserRequest {
     pub user_profile: Option<String>,
     pub bio: Option<String>,
-    pub location: Option<Value>,
+    pub location: Option<Location>,
+    /// Taxonomy slugs or labels, see `/taxonomy`
     pub interests: Option<Vec<String>>,
     pub experience: Option<Vec<String>>,
-    pub available_days: Option<Vec<String>>,
+    /// BCP 47 tags, e.g. `["en", "pt-BR"]`
     pub languages: Option<Vec<String>>,
-    pub privacy: Option<bool>,
 }
 
 #[derive(Serialize, sqlx::FromRow)]
 pub struct UpdatedUserProfile {
     pub user_profile: String,
     pub bio: Option<String>,
-    pub location: Option<Value>,
+    pub location: Option<Json<Location>>,
     pub interests: Option<Vec<String>>,
     pub experience: Option<Vec<String>>,
-    pub available_days: Option<Vec<String>>,
     pub languages: Option<Vec<String>>,
-    pub privacy: bool,
 }
 
 pub async fn update_user_profile(
@@ -152,63 +130,156 @@ pub async fn update_user_profile(
     req: HttpRequest,
     payload: web::Json<UpdateUserRequest>,
 ) -> impl Responder {
-    if let Some(claims) = req.extensions().get::<Claims>() {
-        let query = "
-            UPDATE users SET 
-                user_profile = COALESCE($1, user_profile), 
-                bio = COALESCE($2, bio), 
-                location = COALESCE($3, location), 
-                interests = COALESCE($4, interests), 
-                experience = COALESCE($5, experience), 
-                available_days = COALESCE($6, available_days), 
-                languages = COALESCE($7, languages), 
-                privacy = COALESCE($8, privacy)
-            WHERE user_id = $9
-            RETURNING user_profile, bio, location, interests, experience, available_days, languages, privacy";
-
-       
-        let result = sqlx::query_as::<_, UpdatedUserProfile>(query)
-            .bind(&payload.user_profile)
-            .bind(&payload.bio)
-            .bind(&payload.location)
-            .bind(&payload.interests)
-            .bind(&payload.experience)
-            .bind(&payload.available_days)
-            .bind(&payload.languages)
-            .bind(&payload.privacy)
-            .bind(&claims.id)
-            .fetch_one(pool.get_ref())
-            .await;
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+    let payload = payload.into_inner();
 
-        match result {
-            Ok(updated_user) => HttpResponse::Ok().json(updated_user),
-            Err(_) => HttpResponse::InternalServerError().body("Failed to update profile"),
+    // Tags already on the profile stay valid even if they have since been retired
+    let current = sqlx::query_as::<_, (Option<Vec<String>>, Option<Vec<String>>)>(
+        "SELECT interests, experience FROM users WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_one(pool.get_ref())
+    .await;
+    let (current_interests, current_experience) = match current {
+        Ok((interests, experience)) => (interests.unwrap_or_default(), experience.unwrap_or_default()),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to update profile");
+        }
+    };
+
+    let mut errors = Vec::new();
+    let location = payload
+        .location
+        .map(|location| Json(normalize_location(location, &mut errors)));
+    let languages = payload
+        .languages
+        .map(|languages| parse_languages(&languages, &mut errors));
+
+    let mut interests = None;
+    let mut experience = None;
+    for (kind, field, values, current, resolved) in [
+        (ProfileTagKind::Interest, "interests", payload.interests, current_interests, &mut interests),
+        (ProfileTagKind::Experience, "experience", payload.experience, current_experience, &mut experience),
+    ] {
+        let Some(values) = values else { continue };
+        match resolve_tags(pool.get_ref(), kind, field, &values, &current, &mut errors).await {
+            Ok(slugs) => *resolved = Some(slugs),
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                return HttpResponse::InternalServerError().body("Failed to update profile");
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return validation_error_response(&errors);
+    }
+
+    let query = "
+        UPDATE users SET 
+            user_profile = COALESCE($1, user_profile), 
+            bio = COALESCE($2, bio), 
+            location = COALESCE($3, location), 
+            interests = COALESCE($4, interests), 
+            experience = COALESCE($5, experience), 
+            languages = COALESCE($6, languages)
+        WHERE user_id = $7
+        RETURNING user_profile, bio, location, interests, experience, languages";
+
+    let result = sqlx::query_as::<_, UpdatedUserProfile>(query)
+        .bind(&payload.user_profile)
+        .bind(&payload.bio)
+        .bind(&location)
+        .bind(&interests)
+        .bind(&experience)
+        .bind(&languages)
+        .bind(user_id)
+        .fetch_one(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(updated_user) => HttpResponse::Ok().json(updated_user),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to update profile")
+        }
+    }
+}
+
+/// Active interest and experience options, grouped by kind
+pub async fn get_profile_taxonomy(pool: web::Data<PgPool>) -> impl Responder {
+    let result = sqlx::query_as::<_, ProfileTag>(
+        "SELECT tag_id, kind, slug, label, active, created_at FROM profile_tags
+         WHERE active ORDER BY kind, label",
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(tags) => {
+            let (interests, experience): (Vec<ProfileTag>, Vec<ProfileTag>) =
+                tags.into_iter().partition(|tag| tag.kind == ProfileTagKind::Interest);
+            HttpResponse::Ok().json(serde_json::json!({ "interests": interests, "experience": experience }))
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch taxonomy")
         }
-    } else {
-        HttpResponse::Unauthorized().body("Authentication required")
     }
 }
 
 
+/// Schedule the account for deletion. Until the grace period is over it can be restored with its
+/// credentials at `/api/public/users/restore`; after that it is anonymised.
 pub async fn delete_user_account(
     pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
     req: HttpRequest,
 ) -> impl Responder {
-    if let Some(claims) = req.extensions().get::<Claims>() {
-        let query = "DELETE FROM users WHERE user_id = $1";
+    let user_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
 
-        let result = sqlx::query(query)
-            .bind(&claims.id)
-            .execute(pool.get_ref())
-            .await;
+    let scheduled_for = match schedule_account_deletion(pool.get_ref(), user_id).await {
+        Ok(Some(scheduled_for)) => scheduled_for,
+        Ok(None) => return HttpResponse::NotFound().body("User not found"),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to delete account");
+        }
+    };
 
-        match result {
-            Ok(_) => HttpResponse::Ok().body("Account deleted successfully"),
-            Err(_) => HttpResponse::InternalServerError().body("Failed to delete account"),
+    let contact: Result<(String, String), sqlx::Error> =
+        sqlx::query_as("SELECT username, email FROM users WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(pool.get_ref())
+            .await;
+    if let Ok((username, email)) = contact {
+        let notice = OutgoingEmail {
+            to: email,
+            subject: "Your account is scheduled for deletion".to_string(),
+            body: format!(
+                "Hi {},\n\nYour account will be permanently deleted on {}. Until then you can restore it by signing in on the account restore page. After that date your profile is erased and anything you posted is shown as from a deleted user.",
+                username,
+                scheduled_for.format("%Y-%m-%d")
+            ),
+        };
+        if let Err(e) = mailer.send(notice).await {
+            eprintln!("Failed to send deletion notice: {}", e);
         }
-    } else {
-        HttpResponse::Unauthorized().body("Authentication required")
     }
+
+    let mut builder = HttpResponse::Ok();
+    clear_session_cookies(&mut builder);
+    builder.body(format!(
+        "Account scheduled for deletion on {}. You can restore it until then.",
+        scheduled_for.format("%Y-%m-%d")
+    ))
 }
 
 
@@ -217,9 +288,16 @@ pub async fn delete_user_account(
 pub fn config_user_info_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/users") 
+            .configure(config_protected_user_auth_routes)
             .route("/info", web::get().to(get_logged_in_user_info)) 
-             .route("/{username}", web::get().to(get_user_by_name))
              .route("/update-info", web::patch().to(update_user_profile))
-             .route("/delete-user", web::delete().to(delete_user_account))
+             .service(
+                 web::resource("/delete-user")
+                     .wrap(DenyImpersonation)
+                     .route(web::delete().to(delete_user_account)),
+             )
+             .route("/taxonomy", web::get().to(get_profile_taxonomy))
+             // Must stay last so it doesn't shadow the fixed paths above
+             .route("/{username}", web::get().to(get_user_by_name))
     );
 }
\ No newline at end of file
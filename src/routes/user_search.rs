@@ -0,0 +1,337 @@
+use crate::auth::logged_in_user_id;
+use crate::handlers::profile_fields::{normalize_language_tag, primary_language, slugify};
+use crate::handlers::profile_view::{PROFILE_COLUMNS, ProfileRow, ProfileView, Viewer, profile_views};
+use crate::handlers::validation::{FieldError, validation_error_response};
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const MAX_QUERY_LENGTH: usize = 50;
+const MAX_RADIUS_KM: f64 = 1000.0;
+/// Rows read per round trip; hidden fields can filter some out after the query
+const SEARCH_BATCH_SIZE: i64 = 100;
+/// Round trips one page may take before handing back a cursor with fewer results
+const MAX_SEARCH_BATCHES: usize = 5;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    /// Username prefix or approximate username
+    pub q: Option<String>,
+    pub role: Option<String>,
+    /// Comma-separated; matches users with every one of these interests
+    pub interests: Option<String>,
+    /// Comma-separated; matches users who speak any of these languages
+    pub languages: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub radius_km: Option<f64>,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResults {
+    pub results: Vec<ProfileView>,
+    /// Pass back as `cursor` for the next page; absent once there are no more results
+    pub next_cursor: Option<String>,
+}
+
+/// Position after the last row seen. Results are ordered by relevance, then username.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SearchCursor {
+    rank: f32,
+    username: String,
+}
+
+impl SearchCursor {
+    fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(value: &str) -> Option<SearchCursor> {
+        serde_json::from_slice(&hex::decode(value).ok()?).ok()
+    }
+}
+
+/// Validated filters, ready to bind
+struct SearchFilters {
+    q: Option<String>,
+    prefix: Option<String>,
+    role: Option<String>,
+    interests: Option<Vec<String>>,
+    languages: Option<Vec<String>>,
+    radius: Option<(f64, f64, f64)>,
+    limit: usize,
+    cursor: Option<SearchCursor>,
+}
+
+fn comma_list(value: &Option<String>) -> Option<Vec<String>> {
+    let items: Vec<String> = value
+        .as_deref()?
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect();
+    (!items.is_empty()).then_some(items)
+}
+
+fn parse_filters(query: &SearchQuery) -> Result<SearchFilters, Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    let q = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+    if q.is_some_and(|q| q.chars().count() > MAX_QUERY_LENGTH) {
+        errors.push(FieldError::new(
+            "q",
+            "too_long",
+            format!("Must be at most {} characters", MAX_QUERY_LENGTH),
+        ));
+    }
+    // LIKE wildcards in the query are matched literally
+    let prefix = q.map(|q| {
+        let escaped = q.to_lowercase().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        format!("{}%", escaped)
+    });
+
+    let role = query.role.as_deref().map(str::to_lowercase);
+    if role.as_deref().is_some_and(|role| !matches!(role, "member" | "sponsor" | "admin")) {
+        errors.push(FieldError::new("role", "invalid_role", "Must be member, sponsor or admin"));
+    }
+
+    let interests = comma_list(&query.interests).map(|items| {
+        let mut slugs: Vec<String> = items.iter().map(|item| slugify(item)).collect();
+        slugs.sort();
+        slugs.dedup();
+        slugs
+    });
+
+    let languages = comma_list(&query.languages).map(|items| {
+        let mut primaries = Vec::new();
+        for item in items {
+            match normalize_language_tag(&item) {
+                Some(tag) => primaries.push(primary_language(&tag).to_string()),
+                None => errors.push(FieldError::new(
+                    "languages",
+                    "invalid_language",
+                    format!("\"{}\" is not a language code such as \"en\" or \"pt-BR\"", item),
+                )),
+            }
+        }
+        primaries.sort();
+        primaries.dedup();
+        primaries
+    });
+
+    let radius = match (query.latitude, query.longitude, query.radius_km) {
+        (None, None, None) => None,
+        (Some(latitude), Some(longitude), Some(radius_km)) => {
+            if !(-90.0..=90.0).contains(&latitude) {
+                errors.push(FieldError::new("latitude", "out_of_range", "Latitude must be between -90 and 90"));
+            }
+            if !(-180.0..=180.0).contains(&longitude) {
+                errors.push(FieldError::new("longitude", "out_of_range", "Longitude must be between -180 and 180"));
+            }
+            if !(radius_km > 0.0 && radius_km <= MAX_RADIUS_KM) {
+                errors.push(FieldError::new(
+                    "radius_km",
+                    "out_of_range",
+                    format!("Radius must be more than 0 and at most {} km", MAX_RADIUS_KM),
+                ));
+            }
+            Some((latitude, longitude, radius_km))
+        }
+        _ => {
+            errors.push(FieldError::new(
+                "radius_km",
+                "incomplete",
+                "latitude, longitude and radius_km must be given together",
+            ));
+            None
+        }
+    };
+
+    let cursor = match query.cursor.as_deref() {
+        Some(value) => match SearchCursor::decode(value) {
+            Some(cursor) => Some(cursor),
+            None => {
+                errors.push(FieldError::new("cursor", "invalid_cursor", "Cursor is not valid"));
+                None
+            }
+        },
+        None => None,
+    };
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(SearchFilters {
+        q: q.map(str::to_string),
+        prefix,
+        role,
+        interests,
+        languages,
+        radius,
+        limit: query.limit.unwrap_or(20).clamp(1, 50) as usize,
+        cursor,
+    })
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    #[sqlx(flatten)]
+    profile: ProfileRow,
+    rank: f32,
+}
+
+/// One batch of candidates after `after`, matched on the stored values. Whether the searcher may
+/// see the fields that matched is checked afterwards, on the rendered profiles.
+async fn search_batch(
+    pool: &PgPool,
+    searcher_id: Uuid,
+    filters: &SearchFilters,
+    after: Option<&SearchCursor>,
+) -> Result<Vec<SearchRow>, sqlx::Error> {
+    // Prefix matches rank above fuzzy ones; without a query everything ranks equally
+    let query = format!(
+        "SELECT {}, rank FROM (
+             SELECT u.*,
+                    (CASE WHEN $1::TEXT IS NULL THEN 0
+                          WHEN lower(u.username) LIKE $2 THEN 1 + similarity(u.username, $1)
+                          ELSE similarity(u.username, $1) END)::REAL AS rank
+             FROM users u
+             WHERE u.user_id <> $3 AND u.deletion_scheduled_for IS NULL AND u.purged_at IS NULL
+               AND ($1::TEXT IS NULL OR lower(u.username) LIKE $2 OR u.username % $1)
+               AND ($4::TEXT IS NULL OR u.role::TEXT = $4)
+               AND ($5::TEXT[] IS NULL OR u.interests @> $5)
+               AND ($6::TEXT[] IS NULL OR EXISTS (
+                   SELECT 1 FROM unnest(u.languages) l WHERE split_part(l, '-', 1) = ANY($6)))
+               AND ($9::FLOAT8 IS NULL OR 6371 * 2 * asin(sqrt(
+                   power(sin(radians(((u.location->>'latitude')::FLOAT8 - $7) / 2)), 2)
+                   + cos(radians($7)) * cos(radians((u.location->>'latitude')::FLOAT8))
+                     * power(sin(radians(((u.location->>'longitude')::FLOAT8 - $8) / 2)), 2)
+               )) <= $9)
+         ) found
+         WHERE $10::REAL IS NULL OR rank < $10 OR (rank = $10 AND username > $11)
+         ORDER BY rank DESC, username
+         LIMIT $12",
+        PROFILE_COLUMNS
+    );
+
+    let (latitude, longitude, radius_km) = match filters.radius {
+        Some((latitude, longitude, radius_km)) => (Some(latitude), Some(longitude), Some(radius_km)),
+        None => (None, None, None),
+    };
+
+    sqlx::query_as::<_, SearchRow>(&query)
+        .bind(&filters.q)
+        .bind(&filters.prefix)
+        .bind(searcher_id)
+        .bind(&filters.role)
+        .bind(&filters.interests)
+        .bind(&filters.languages)
+        .bind(latitude)
+        .bind(longitude)
+        .bind(radius_km)
+        .bind(after.map(|cursor| cursor.rank))
+        .bind(after.map(|cursor| cursor.username.as_str()))
+        .bind(SEARCH_BATCH_SIZE)
+        .fetch_all(pool)
+        .await
+}
+
+/// A profile only counts as a match if the searcher can see every field the filters looked at;
+/// otherwise the filter would reveal a hidden value
+fn filters_visible(view: &ProfileView, filters: &SearchFilters) -> bool {
+    (filters.interests.is_none() || view.interests.is_some())
+        && (filters.languages.is_none() || view.languages.is_some())
+        && (filters.radius.is_none()
+            || view.location.as_ref().is_some_and(|location| location.latitude.is_some()))
+}
+
+/// Search the user directory by username and profile fields. Blocked, deleted and hidden
+/// profiles never match.
+pub async fn search_users(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<SearchQuery>,
+) -> impl Responder {
+    let searcher_id = match logged_in_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().body("Authentication required"),
+    };
+
+    let filters = match parse_filters(&query) {
+        Ok(filters) => filters,
+        Err(errors) => return validation_error_response(&errors),
+    };
+
+    let viewer = match Viewer::from_request(pool.get_ref(), &req).await {
+        Ok(viewer) => viewer,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Search failed");
+        }
+    };
+
+    let mut results = Vec::new();
+    let mut cursor = filters.cursor.clone();
+    let mut next_cursor = None;
+    for _ in 0..MAX_SEARCH_BATCHES {
+        let rows = match search_batch(pool.get_ref(), searcher_id, &filters, cursor.as_ref()).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                return HttpResponse::InternalServerError().body("Search failed");
+            }
+        };
+        let exhausted = (rows.len() as i64) < SEARCH_BATCH_SIZE;
+
+        let positions: HashMap<Uuid, SearchCursor> = rows
+            .iter()
+            .map(|row| (row.profile.user_id, SearchCursor { rank: row.rank, username: row.profile.username.clone() }))
+            .collect();
+        let batch_end = rows
+            .last()
+            .map(|row| SearchCursor { rank: row.rank, username: row.profile.username.clone() });
+
+        let views = match profile_views(pool.get_ref(), &viewer, rows.into_iter().map(|row| row.profile).collect()).await
+        {
+            Ok(views) => views,
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                return HttpResponse::InternalServerError().body("Search failed");
+            }
+        };
+
+        for view in views.into_iter().filter(|view| filters_visible(view, &filters)) {
+            let position = positions.get(&view.user_id).cloned();
+            results.push(view);
+            if results.len() == filters.limit {
+                next_cursor = position;
+                break;
+            }
+        }
+
+        if results.len() == filters.limit {
+            break;
+        }
+        if exhausted {
+            next_cursor = None;
+            break;
+        }
+        // Page not full yet; carry on after this batch, or hand back its end if we stop here
+        cursor = batch_end;
+        next_cursor = cursor.clone();
+    }
+
+    HttpResponse::Ok().json(SearchResults {
+        results,
+        next_cursor: next_cursor.map(|cursor| cursor.encode()),
+    })
+}
+
+/// Directory search; mounted inside the protected `/users` scope
+pub fn config_user_search_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/search", web::get().to(search_users));
+}